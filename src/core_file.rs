@@ -0,0 +1,554 @@
+//! Support for ELF core files, so that a crash can be inspected after the process is gone.
+//!
+//! `object` doesn't give us program headers or notes, so the (simple) ELF64 layout is parsed by
+//! hand here. Only x86_64 little-endian cores are supported, which is all deet runs on anyway.
+
+use crate::inferior::Target;
+use crate::memory_map::MemoryMapping;
+use crate::siginfo::SigInfo;
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, Write};
+use std::mem::size_of;
+
+// Note types
+pub const NT_PRSTATUS: u32 = 1;
+pub const NT_FPREGSET: u32 = 2;
+pub const NT_PRPSINFO: u32 = 3;
+pub const NT_AUXV: u32 = 6;
+pub const NT_SIGINFO: u32 = 0x5349_4749;
+pub const NT_FILE: u32 = 0x4649_4c45;
+
+// Program header types
+pub const PT_LOAD: u32 = 1;
+pub const PT_NOTE: u32 = 4;
+
+// Program header flags
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+const ET_CORE: u16 = 4;
+const EM_X86_64: u16 = 62;
+const PAGE_SIZE: usize = 4096;
+
+/// Offset of `pr_pid` in `struct elf_prstatus`.
+pub const PRSTATUS_PID_OFFSET: usize = 32;
+/// Offset of `pr_reg` (a `user_regs_struct`) in `struct elf_prstatus`.
+pub const PRSTATUS_REGS_OFFSET: usize = 112;
+/// Size of `struct elf_prstatus` on x86_64.
+pub const PRSTATUS_SIZE: usize = 336;
+/// Size of `struct elf_prpsinfo` on x86_64.
+pub const PRPSINFO_SIZE: usize = 136;
+
+#[derive(Debug)]
+pub enum Error {
+    ErrorOpeningFile,
+    NotACoreFile,
+    Malformed(&'static str),
+}
+
+/// Registers of one thread, from an NT_PRSTATUS note (and the NT_FPREGSET that follows it).
+pub struct CoreThread {
+    pub pid: i32,
+    /// Signal that was pending for this thread when the core was written
+    pub signal: i32,
+    pub regs: libc::user_regs_struct,
+    /// Raw `user_fpregs_struct`, if the core has one for this thread
+    #[allow(dead_code)]
+    pub fpregs: Option<Vec<u8>>,
+}
+
+/// A PT_LOAD segment: a piece of the process's memory.
+struct Segment {
+    vaddr: usize,
+    memsz: usize,
+    offset: usize,
+    filesz: usize,
+    flags: u32,
+}
+
+pub struct CoreFile {
+    data: memmap::Mmap,
+    segments: Vec<Segment>,
+    pub threads: Vec<CoreThread>,
+    pub siginfo: Option<SigInfo>,
+    /// Files mapped into the process, from the NT_FILE note
+    pub mapped_files: Vec<MemoryMapping>,
+    /// Raw auxiliary vector, from the NT_AUXV note
+    pub auxv: Vec<u8>,
+    /// Index into `threads` of the thread that `get_registers` reports on
+    pub selected_thread: usize,
+}
+
+pub fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+pub fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+/// Splits the contents of a PT_NOTE segment into (type, name, descriptor) triples.
+fn parse_notes(data: &[u8]) -> Vec<(u32, &[u8], &[u8])> {
+    let mut notes = Vec::new();
+    let mut offset = 0;
+    while offset + 12 <= data.len() {
+        let namesz = read_u32(data, offset).unwrap() as usize;
+        let descsz = read_u32(data, offset + 4).unwrap() as usize;
+        let note_type = read_u32(data, offset + 8).unwrap();
+        offset += 12;
+        let name = match data.get(offset..offset + namesz) {
+            Some(name) => name,
+            None => break,
+        };
+        offset += align4(namesz);
+        let desc = match data.get(offset..offset + descsz) {
+            Some(desc) => desc,
+            None => break,
+        };
+        offset += align4(descsz);
+        notes.push((note_type, name, desc));
+    }
+    notes
+}
+
+/// Parses an NT_FILE note into the list of mapped files.
+fn parse_file_note(desc: &[u8]) -> Vec<MemoryMapping> {
+    let mut mappings = Vec::new();
+    let count = read_u64(desc, 0).unwrap_or(0) as usize;
+    let page_size = read_u64(desc, 8).unwrap_or(0) as usize;
+    let names_offset = 16 + count * 24;
+    let mut names = desc
+        .get(names_offset..)
+        .unwrap_or(&[])
+        .split(|b| *b == 0)
+        .map(|name| String::from_utf8_lossy(name).to_string());
+    for i in 0..count {
+        let entry = 16 + i * 24;
+        let (start, end, page_offset) = match (
+            read_u64(desc, entry),
+            read_u64(desc, entry + 8),
+            read_u64(desc, entry + 16),
+        ) {
+            (Some(start), Some(end), Some(page_offset)) => (start, end, page_offset),
+            _ => break,
+        };
+        mappings.push(MemoryMapping {
+            start: start as usize,
+            end: end as usize,
+            perms: "r--p".to_string(),
+            offset: page_offset as usize * page_size,
+            path: names.next().unwrap_or_default(),
+        });
+    }
+    mappings
+}
+
+impl CoreFile {
+    pub fn from_file(path: &str) -> Result<CoreFile, Error> {
+        let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
+        let data = unsafe { memmap::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? };
+        if data.len() < 64 || &data[0..4] != b"\x7fELF" || data[4] != 2 {
+            return Err(Error::NotACoreFile);
+        }
+        if read_u16(&data, 16) != Some(ET_CORE) {
+            return Err(Error::NotACoreFile);
+        }
+        let phoff = read_u64(&data, 32).unwrap() as usize;
+        let phentsize = read_u16(&data, 54).unwrap() as usize;
+        let phnum = read_u16(&data, 56).unwrap() as usize;
+
+        let mut core = CoreFile {
+            segments: Vec::new(),
+            threads: Vec::new(),
+            siginfo: None,
+            mapped_files: Vec::new(),
+            auxv: Vec::new(),
+            selected_thread: 0,
+            data,
+        };
+        let mut note_ranges = Vec::new();
+        for i in 0..phnum {
+            let ph = phoff + i * phentsize;
+            let p_type = read_u32(&core.data, ph).ok_or(Error::Malformed("program header"))?;
+            let flags = read_u32(&core.data, ph + 4).ok_or(Error::Malformed("program header"))?;
+            let offset = read_u64(&core.data, ph + 8).ok_or(Error::Malformed("program header"))?;
+            let vaddr = read_u64(&core.data, ph + 16).ok_or(Error::Malformed("program header"))?;
+            let filesz = read_u64(&core.data, ph + 32).ok_or(Error::Malformed("program header"))?;
+            let memsz = read_u64(&core.data, ph + 40).ok_or(Error::Malformed("program header"))?;
+            match p_type {
+                PT_LOAD => core.segments.push(Segment {
+                    vaddr: vaddr as usize,
+                    memsz: memsz as usize,
+                    offset: offset as usize,
+                    filesz: filesz as usize,
+                    flags,
+                }),
+                PT_NOTE => note_ranges.push((offset as usize, filesz as usize)),
+                _ => {}
+            }
+        }
+
+        for (offset, size) in note_ranges {
+            let notes = core
+                .data
+                .get(offset..offset + size)
+                .ok_or(Error::Malformed("note segment"))?;
+            for (note_type, name, desc) in parse_notes(notes) {
+                if !name.starts_with(b"CORE") {
+                    continue;
+                }
+                match note_type {
+                    NT_PRSTATUS => {
+                        if desc.len()
+                            < PRSTATUS_REGS_OFFSET + std::mem::size_of::<libc::user_regs_struct>()
+                        {
+                            return Err(Error::Malformed("NT_PRSTATUS"));
+                        }
+                        let regs = unsafe {
+                            std::ptr::read_unaligned(desc[PRSTATUS_REGS_OFFSET..].as_ptr()
+                                as *const libc::user_regs_struct)
+                        };
+                        core.threads.push(CoreThread {
+                            pid: read_u32(desc, PRSTATUS_PID_OFFSET).unwrap() as i32,
+                            signal: read_u16(desc, 12).unwrap() as i32,
+                            regs,
+                            fpregs: None,
+                        });
+                    }
+                    NT_FPREGSET => {
+                        if let Some(thread) = core.threads.last_mut() {
+                            thread.fpregs = Some(desc.to_vec());
+                        }
+                    }
+                    // Only the thread that received the signal has one
+                    NT_SIGINFO if core.siginfo.is_none() => {
+                        core.siginfo = SigInfo::from_bytes(desc);
+                    }
+                    NT_FILE => core.mapped_files = parse_file_note(desc),
+                    NT_AUXV => core.auxv = desc.to_vec(),
+                    _ => {}
+                }
+            }
+        }
+        if core.threads.is_empty() {
+            return Err(Error::Malformed("no NT_PRSTATUS notes"));
+        }
+        Ok(core)
+    }
+
+    /// Returns the pid of the process that dumped this core.
+    pub fn pid(&self) -> i32 {
+        self.threads[0].pid
+    }
+
+    /// Reconstructs the process's memory map from the PT_LOAD segments, naming them after the
+    /// files in NT_FILE.
+    pub fn mappings(&self) -> Vec<MemoryMapping> {
+        self.segments
+            .iter()
+            .map(|segment| {
+                let file = self
+                    .mapped_files
+                    .iter()
+                    .find(|file| file.contains(segment.vaddr));
+                MemoryMapping {
+                    start: segment.vaddr,
+                    end: segment.vaddr + segment.memsz,
+                    perms: format!(
+                        "{}{}{}p",
+                        if segment.flags & PF_R != 0 { 'r' } else { '-' },
+                        if segment.flags & PF_W != 0 { 'w' } else { '-' },
+                        if segment.flags & PF_X != 0 { 'x' } else { '-' },
+                    ),
+                    offset: file.map_or(0, |f| f.offset + (segment.vaddr - f.start)),
+                    path: file.map_or(String::new(), |f| f.path.clone()),
+                }
+            })
+            .collect()
+    }
+}
+
+impl Target for CoreFile {
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            let curr = addr + bytes.len();
+            let segment = self
+                .segments
+                .iter()
+                .find(|s| s.vaddr <= curr && curr < s.vaddr + s.memsz)
+                .ok_or(nix::Error::EFAULT)?;
+            let segment_offset = curr - segment.vaddr;
+            let chunk_len = std::cmp::min(len - bytes.len(), segment.memsz - segment_offset);
+            for i in segment_offset..segment_offset + chunk_len {
+                if i < segment.filesz {
+                    bytes.push(
+                        *self
+                            .data
+                            .get(segment.offset + i)
+                            .ok_or(nix::Error::EFAULT)?,
+                    );
+                } else if segment.flags & PF_W != 0 {
+                    // Writable memory past what the kernel dumped is zero-filled (e.g. bss)
+                    bytes.push(0);
+                } else {
+                    // Read-only file mappings (e.g. program text) usually aren't dumped at all
+                    return Err(nix::Error::EIO);
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn get_registers(&self) -> Result<libc::user_regs_struct, nix::Error> {
+        Ok(self.threads[self.selected_thread].regs)
+    }
+
+    fn get_siginfo(&self) -> Result<SigInfo, nix::Error> {
+        self.siginfo.ok_or(nix::Error::EINVAL)
+    }
+
+    fn get_auxv(&self) -> Result<Vec<u8>, nix::Error> {
+        Ok(self.auxv.clone())
+    }
+}
+
+/// Process-wide information that goes into the NT_PRSTATUS and NT_PRPSINFO notes of a core.
+pub struct ProcessInfo {
+    pub pid: i32,
+    pub ppid: i32,
+    pub pgrp: i32,
+    pub sid: i32,
+    pub uid: u32,
+    pub gid: u32,
+    /// One-letter process state, as in /proc/<pid>/stat
+    pub state: u8,
+    /// Executable name (at most 16 bytes are kept)
+    pub fname: String,
+    /// Command line (at most 80 bytes are kept)
+    pub psargs: String,
+}
+
+/// Everything needed to write a core file, apart from the memory contents themselves.
+pub struct CoreDump {
+    pub process: ProcessInfo,
+    pub threads: Vec<CoreThread>,
+    pub siginfo: Option<SigInfo>,
+    pub auxv: Vec<u8>,
+    /// Memory to dump. Each mapping becomes one PT_LOAD segment.
+    pub mappings: Vec<MemoryMapping>,
+}
+
+/// Appends a note with the name "CORE" to `buf`.
+fn push_note(buf: &mut Vec<u8>, note_type: u32, desc: &[u8]) {
+    let name = b"CORE\0";
+    buf.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&note_type.to_le_bytes());
+    buf.extend_from_slice(name);
+    buf.resize(align4(buf.len()), 0);
+    buf.extend_from_slice(desc);
+    buf.resize(align4(buf.len()), 0);
+}
+
+/// Copies `src` into `dest`, truncating it if necessary and leaving room for a NUL terminator.
+fn copy_c_string(dest: &mut [u8], src: &str) {
+    let len = std::cmp::min(dest.len() - 1, src.len());
+    dest[..len].copy_from_slice(&src.as_bytes()[..len]);
+}
+
+impl CoreDump {
+    fn prstatus(&self, thread: &CoreThread) -> Vec<u8> {
+        let mut desc = vec![0u8; PRSTATUS_SIZE];
+        if let Some(siginfo) = &self.siginfo {
+            desc[0..4].copy_from_slice(&siginfo.signo.to_le_bytes());
+            desc[4..8].copy_from_slice(&siginfo.code.to_le_bytes());
+            desc[8..12].copy_from_slice(&siginfo.errno.to_le_bytes());
+        }
+        desc[12..14].copy_from_slice(&(thread.signal as i16).to_le_bytes());
+        desc[PRSTATUS_PID_OFFSET..PRSTATUS_PID_OFFSET + 4]
+            .copy_from_slice(&thread.pid.to_le_bytes());
+        desc[36..40].copy_from_slice(&self.process.ppid.to_le_bytes());
+        desc[40..44].copy_from_slice(&self.process.pgrp.to_le_bytes());
+        desc[44..48].copy_from_slice(&self.process.sid.to_le_bytes());
+        let regs_size = size_of::<libc::user_regs_struct>();
+        let regs = unsafe {
+            std::slice::from_raw_parts(
+                &thread.regs as *const libc::user_regs_struct as *const u8,
+                regs_size,
+            )
+        };
+        desc[PRSTATUS_REGS_OFFSET..PRSTATUS_REGS_OFFSET + regs_size].copy_from_slice(regs);
+        // pr_fpvalid
+        let fpvalid = i32::from(thread.fpregs.is_some());
+        desc[PRSTATUS_REGS_OFFSET + regs_size..PRSTATUS_REGS_OFFSET + regs_size + 4]
+            .copy_from_slice(&fpvalid.to_le_bytes());
+        desc
+    }
+
+    fn prpsinfo(&self) -> Vec<u8> {
+        let mut desc = vec![0u8; PRPSINFO_SIZE];
+        desc[1] = self.process.state;
+        desc[16..20].copy_from_slice(&self.process.uid.to_le_bytes());
+        desc[20..24].copy_from_slice(&self.process.gid.to_le_bytes());
+        desc[24..28].copy_from_slice(&self.process.pid.to_le_bytes());
+        desc[28..32].copy_from_slice(&self.process.ppid.to_le_bytes());
+        desc[32..36].copy_from_slice(&self.process.pgrp.to_le_bytes());
+        desc[36..40].copy_from_slice(&self.process.sid.to_le_bytes());
+        copy_c_string(&mut desc[40..56], &self.process.fname);
+        copy_c_string(&mut desc[56..136], &self.process.psargs);
+        desc
+    }
+
+    fn file_note(&self) -> Vec<u8> {
+        let files: Vec<&MemoryMapping> = self
+            .mappings
+            .iter()
+            .filter(|m| m.path.starts_with('/'))
+            .collect();
+        let mut desc = Vec::new();
+        desc.extend_from_slice(&(files.len() as u64).to_le_bytes());
+        desc.extend_from_slice(&(PAGE_SIZE as u64).to_le_bytes());
+        for file in &files {
+            desc.extend_from_slice(&(file.start as u64).to_le_bytes());
+            desc.extend_from_slice(&(file.end as u64).to_le_bytes());
+            desc.extend_from_slice(&((file.offset / PAGE_SIZE) as u64).to_le_bytes());
+        }
+        for file in &files {
+            desc.extend_from_slice(file.path.as_bytes());
+            desc.push(0);
+        }
+        desc
+    }
+
+    fn notes(&self) -> Vec<u8> {
+        let mut notes = Vec::new();
+        for (i, thread) in self.threads.iter().enumerate() {
+            push_note(&mut notes, NT_PRSTATUS, &self.prstatus(thread));
+            if i == 0 {
+                push_note(&mut notes, NT_PRPSINFO, &self.prpsinfo());
+                if let Some(siginfo) = self.siginfo {
+                    push_note(&mut notes, NT_SIGINFO, &siginfo.to_bytes());
+                }
+                push_note(&mut notes, NT_AUXV, &self.auxv);
+                push_note(&mut notes, NT_FILE, &self.file_note());
+            }
+            if let Some(fpregs) = &thread.fpregs {
+                push_note(&mut notes, NT_FPREGSET, fpregs);
+            }
+        }
+        notes
+    }
+
+    /// Writes the core file. `read_memory` fills the given buffer with the process's memory at
+    /// the given address, returning `false` if it couldn't (in which case zeros are written).
+    pub fn write(
+        &self,
+        path: &str,
+        read_memory: &mut dyn FnMut(usize, &mut [u8]) -> bool,
+    ) -> io::Result<()> {
+        let notes = self.notes();
+        let phnum = 1 + self.mappings.len();
+        let headers_size = 64 + phnum * 56;
+        let data_start = (headers_size + notes.len() + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+
+        let mut out = io::BufWriter::new(fs::File::create(path)?);
+
+        // ELF header
+        let mut ehdr = vec![0u8; 64];
+        ehdr[0..4].copy_from_slice(b"\x7fELF");
+        ehdr[4] = 2; // ELFCLASS64
+        ehdr[5] = 1; // ELFDATA2LSB
+        ehdr[6] = 1; // EV_CURRENT
+        ehdr[16..18].copy_from_slice(&ET_CORE.to_le_bytes());
+        ehdr[18..20].copy_from_slice(&EM_X86_64.to_le_bytes());
+        ehdr[20..24].copy_from_slice(&1u32.to_le_bytes());
+        ehdr[32..40].copy_from_slice(&64u64.to_le_bytes()); // e_phoff
+        ehdr[52..54].copy_from_slice(&64u16.to_le_bytes()); // e_ehsize
+        ehdr[54..56].copy_from_slice(&56u16.to_le_bytes()); // e_phentsize
+        ehdr[56..58].copy_from_slice(&(phnum as u16).to_le_bytes());
+        ehdr[58..60].copy_from_slice(&64u16.to_le_bytes()); // e_shentsize
+        out.write_all(&ehdr)?;
+
+        // Program headers
+        let write_phdr = |out: &mut dyn Write,
+                          p_type: u32,
+                          flags: u32,
+                          offset: usize,
+                          vaddr: usize,
+                          filesz: usize,
+                          align: usize|
+         -> io::Result<()> {
+            out.write_all(&p_type.to_le_bytes())?;
+            out.write_all(&flags.to_le_bytes())?;
+            for field in &[offset, vaddr, 0, filesz, filesz, align] {
+                out.write_all(&(*field as u64).to_le_bytes())?;
+            }
+            Ok(())
+        };
+        write_phdr(&mut out, PT_NOTE, 0, headers_size, 0, notes.len(), 4)?;
+        let mut offset = data_start;
+        for mapping in &self.mappings {
+            let mut flags = 0;
+            if mapping.is_readable() {
+                flags |= PF_R;
+            }
+            if mapping.is_writable() {
+                flags |= PF_W;
+            }
+            if mapping.is_executable() {
+                flags |= PF_X;
+            }
+            let size = mapping.end - mapping.start;
+            write_phdr(
+                &mut out,
+                PT_LOAD,
+                flags,
+                offset,
+                mapping.start,
+                size,
+                PAGE_SIZE,
+            )?;
+            offset += size;
+        }
+
+        out.write_all(&notes)?;
+        out.write_all(&vec![0u8; data_start - headers_size - notes.len()])?;
+
+        // Memory contents, a chunk at a time so that big mappings don't need to fit in memory
+        let mut buf = vec![0u8; 256 * PAGE_SIZE];
+        for mapping in &self.mappings {
+            let mut addr = mapping.start;
+            while addr < mapping.end {
+                let len = std::cmp::min(buf.len(), mapping.end - addr);
+                if !read_memory(addr, &mut buf[..len]) {
+                    // Part of the chunk may still be readable, so try again a page at a time
+                    for (i, page) in buf[..len].chunks_mut(PAGE_SIZE).enumerate() {
+                        if !read_memory(addr + i * PAGE_SIZE, page) {
+                            page.iter_mut().for_each(|b| *b = 0);
+                        }
+                    }
+                }
+                out.write_all(&buf[..len])?;
+                addr += len;
+            }
+        }
+        out.flush()
+    }
+}
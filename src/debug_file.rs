@@ -0,0 +1,159 @@
+use object::Object;
+use std::convert::TryInto;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where distributions install debug files
+const DEFAULT_DEBUG_FILE_DIRECTORY: &str = "/usr/lib/debug";
+
+/// Colon-separated list of directories to search instead of `DEFAULT_DEBUG_FILE_DIRECTORY`
+const DEBUG_FILE_DIRECTORY_VAR: &str = "DEET_DEBUG_FILE_DIRECTORY";
+
+/// ELF note type of a build ID note (in `.note.gnu.build-id`)
+const NT_GNU_BUILD_ID: u32 = 3;
+
+/// Returns the path of the separate debug file for `object` (which was loaded from `path`), if
+/// there is one. Distributions ship debug info split out of the binaries like this (with
+/// `objcopy --only-keep-debug`). Like gdb, we look for it by build ID and then by the name in
+/// `.gnu_debuglink`.
+pub fn find_debug_file(object: &object::File, path: &str) -> Option<PathBuf> {
+    if let Some(build_id) = build_id(object) {
+        if let Some(found) = find_by_build_id(&build_id) {
+            return Some(found);
+        }
+    }
+    let (name, crc) = debuglink(object)?;
+    find_by_debuglink(path, &name, crc)
+}
+
+/// Returns the directories searched for debug files.
+fn debug_file_directories() -> Vec<PathBuf> {
+    match env::var(DEBUG_FILE_DIRECTORY_VAR) {
+        Ok(dirs) => env::split_paths(&dirs).collect(),
+        Err(_) => vec![PathBuf::from(DEFAULT_DEBUG_FILE_DIRECTORY)],
+    }
+}
+
+/// Returns the directory where debuginfod clients cache what they download, which is laid out
+/// by build ID.
+fn debuginfod_cache_directory() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("DEBUGINFOD_CACHE_PATH") {
+        return Some(PathBuf::from(dir));
+    }
+    Some(cache_directory()?.join("debuginfod_client"))
+}
+
+/// Returns the user's cache directory: `$XDG_CACHE_HOME`, or `~/.cache` if that isn't set.
+pub fn cache_directory() -> Option<PathBuf> {
+    match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => Some(PathBuf::from(env::var_os("HOME")?).join(".cache")),
+    }
+}
+
+/// Reads the build ID out of `.note.gnu.build-id`.
+pub fn build_id(object: &object::File) -> Option<Vec<u8>> {
+    let notes = object.section_data_by_name(".note.gnu.build-id")?;
+    let word = |offset: usize| -> Option<u32> {
+        let bytes = notes.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if object.is_little_endian() {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+    // Each note is namesz, descsz and type, then the name and the descriptor, each padded to 4
+    // bytes
+    let mut offset = 0;
+    while offset + 12 <= notes.len() {
+        let namesz = word(offset)? as usize;
+        let descsz = word(offset + 4)? as usize;
+        let note_type = word(offset + 8)?;
+        let name_start = offset + 12;
+        let desc_start = name_start + ((namesz + 3) & !3);
+        let name = notes.get(name_start..name_start + namesz)?;
+        if note_type == NT_GNU_BUILD_ID && name == b"GNU\0" {
+            return Some(notes.get(desc_start..desc_start + descsz)?.to_vec());
+        }
+        offset = desc_start + ((descsz + 3) & !3);
+    }
+    None
+}
+
+/// Looks for `.build-id/xx/yyyy.debug` under the debug file directories, where `xxyyyy` is the
+/// build ID in hex, and for the debuginfod cache's `xxyyyy/debuginfo`.
+fn find_by_build_id(build_id: &[u8]) -> Option<PathBuf> {
+    if build_id.len() < 2 {
+        return None;
+    }
+    let hex: String = build_id.iter().map(|b| format!("{:02x}", b)).collect();
+    debug_file_directories()
+        .into_iter()
+        .map(|dir| {
+            dir.join(".build-id")
+                .join(&hex[..2])
+                .join(format!("{}.debug", &hex[2..]))
+        })
+        .chain(debuginfod_cache_directory().map(|dir| dir.join(&hex).join("debuginfo")))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Reads `.gnu_debuglink`: the name of the debug file, then (4-byte aligned) the CRC32 of its
+/// contents.
+fn debuglink(object: &object::File) -> Option<(String, u32)> {
+    let link = object.section_data_by_name(".gnu_debuglink")?;
+    let name_len = link.iter().position(|b| *b == 0)?;
+    let name = String::from_utf8(link[..name_len].to_vec()).ok()?;
+    let crc_offset = (name_len + 1 + 3) & !3;
+    let crc_bytes = link.get(crc_offset..crc_offset + 4)?.try_into().ok()?;
+    let crc = if object.is_little_endian() {
+        u32::from_le_bytes(crc_bytes)
+    } else {
+        u32::from_be_bytes(crc_bytes)
+    };
+    Some((name, crc))
+}
+
+/// Looks for the debug file named in `.gnu_debuglink` next to the binary, in a `.debug`
+/// subdirectory, and under the debug file directories (mirroring the binary's directory), and
+/// returns the first one whose CRC matches.
+fn find_by_debuglink(path: &str, name: &str, crc: u32) -> Option<PathBuf> {
+    let binary = fs::canonicalize(path).ok()?;
+    let binary_dir = binary.parent()?;
+    let mut candidates = vec![binary_dir.join(name), binary_dir.join(".debug").join(name)];
+    for dir in debug_file_directories() {
+        candidates.push(
+            dir.join(binary_dir.strip_prefix("/").unwrap_or(binary_dir))
+                .join(name),
+        );
+    }
+    candidates
+        .into_iter()
+        // A debug link with the same name as the binary would point back at the binary itself
+        .filter(|candidate| candidate.as_path() != binary.as_path())
+        .find(|candidate| file_crc32(candidate) == Some(crc))
+}
+
+fn file_crc32(path: &Path) -> Option<u32> {
+    Some(crc32(&fs::read(path).ok()?))
+}
+
+/// The CRC32 used by `.gnu_debuglink` (the same one as zlib's).
+fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut crc = i as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+        *entry = crc;
+    }
+    !data.iter().fold(!0u32, |crc, byte| {
+        table[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
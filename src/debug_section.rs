@@ -0,0 +1,205 @@
+//! Reads sections straight from the ELF section headers, decompressing compressed debug sections
+//! (`gcc -gz`). The object crate doesn't tell us whether a section is compressed, and doesn't
+//! know zstd.
+
+use std::borrow::Cow;
+use std::convert::TryInto;
+use std::io::Read;
+
+/// `e_ident[EI_CLASS]` of a 64-bit ELF file
+const ELFCLASS64: u8 = 2;
+/// `e_ident[EI_DATA]` of a little-endian ELF file
+const ELFDATA2LSB: u8 = 1;
+/// Section type of sections that take no space in the file (e.g. `.bss`)
+const SHT_NOBITS: u32 = 8;
+/// Section flag of sections that start with a compression header
+const SHF_COMPRESSED: u64 = 0x800;
+/// `e_shstrndx` value saying the real index is in the first section header's `sh_link`
+const SHN_XINDEX: usize = 0xffff;
+const ELFCOMPRESS_ZLIB: u32 = 1;
+const ELFCOMPRESS_ZSTD: u32 = 2;
+/// Size of an `Elf64_Chdr`
+const COMPRESSION_HEADER_SIZE: usize = 24;
+/// Size of the header of a `.zdebug_*` section: "ZLIB" and the big-endian uncompressed size
+const ZDEBUG_HEADER_SIZE: usize = 12;
+/// How many times bigger than the compressed data we'll allocate for the uncompressed data
+/// before we've seen it. Debug info usually compresses 3-10 times.
+const MAX_COMPRESSION_RATIO: u64 = 16;
+
+/// Returns the contents of the section called `name` in the ELF file `data`, decompressed if need
+/// be. Compressed sections either have `SHF_COMPRESSED` set and start with a compression header
+/// (zlib or zstd), or, the older GNU way, are called `.zdebug_*` instead of `.debug_*` and are
+/// always zlib.
+pub fn section_data<'data>(data: &'data [u8], name: &str) -> Option<Cow<'data, [u8]>> {
+    let elf = Elf::parse(data)?;
+    if let Some(section) = elf.section(name) {
+        let contents = elf.contents(section)?;
+        return if section.flags & SHF_COMPRESSED != 0 {
+            elf.decompress(contents).map(Cow::Owned)
+        } else {
+            Some(Cow::Borrowed(contents))
+        };
+    }
+    let zdebug_name = format!(".zdebug_{}", name.strip_prefix(".debug_")?);
+    let contents = elf.contents(elf.section(&zdebug_name)?)?;
+    if contents.get(..4)? != b"ZLIB" {
+        return None;
+    }
+    let size = u64::from_be_bytes(contents.get(4..ZDEBUG_HEADER_SIZE)?.try_into().ok()?);
+    inflate(&contents[ZDEBUG_HEADER_SIZE..], size).map(Cow::Owned)
+}
+
+struct SectionHeader {
+    name: usize,
+    kind: u32,
+    flags: u64,
+    offset: usize,
+    size: usize,
+}
+
+/// Just enough of a 64-bit ELF file to find sections by name.
+struct Elf<'data> {
+    data: &'data [u8],
+    little_endian: bool,
+    sections: Vec<SectionHeader>,
+    names: &'data [u8],
+}
+
+impl<'data> Elf<'data> {
+    fn parse(data: &'data [u8]) -> Option<Elf<'data>> {
+        if data.get(..4)? != b"\x7fELF" || *data.get(4)? != ELFCLASS64 {
+            return None;
+        }
+        let mut elf = Elf {
+            data,
+            little_endian: *data.get(5)? == ELFDATA2LSB,
+            sections: Vec::new(),
+            names: &[],
+        };
+        let shoff = elf.u64_at(0x28)? as usize;
+        let shentsize = elf.u16_at(0x3a)? as usize;
+        let mut shnum = elf.u16_at(0x3c)? as usize;
+        let mut shstrndx = elf.u16_at(0x3e)? as usize;
+        if shoff == 0 {
+            return None;
+        }
+        // Files with too many sections for the ELF header keep the counts in the first section
+        // header
+        if shnum == 0 {
+            shnum = elf.u64_at(shoff.checked_add(32)?)? as usize;
+        }
+        if shstrndx == SHN_XINDEX {
+            shstrndx = elf.u32_at(shoff.checked_add(40)?)? as usize;
+        }
+        for index in 0..shnum {
+            let header = shoff.checked_add(index * shentsize)?;
+            elf.sections.push(SectionHeader {
+                name: elf.u32_at(header)? as usize,
+                kind: elf.u32_at(header + 4)?,
+                flags: elf.u64_at(header + 8)?,
+                offset: elf.u64_at(header + 24)? as usize,
+                size: elf.u64_at(header + 32)? as usize,
+            });
+        }
+        elf.names = elf.contents(elf.sections.get(shstrndx)?)?;
+        Some(elf)
+    }
+
+    fn section(&self, name: &str) -> Option<&SectionHeader> {
+        self.sections.iter().find(|section| {
+            self.names
+                .get(section.name..)
+                .and_then(|names| names.split(|b| *b == 0).next())
+                == Some(name.as_bytes())
+        })
+    }
+
+    fn contents(&self, section: &SectionHeader) -> Option<&'data [u8]> {
+        if section.kind == SHT_NOBITS {
+            return Some(&[]);
+        }
+        self.data
+            .get(section.offset..section.offset.checked_add(section.size)?)
+    }
+
+    /// Decompresses the contents of a `SHF_COMPRESSED` section.
+    fn decompress(&self, contents: &[u8]) -> Option<Vec<u8>> {
+        let header = Elf {
+            data: contents,
+            little_endian: self.little_endian,
+            sections: Vec::new(),
+            names: &[],
+        };
+        let kind = header.u32_at(0)?;
+        let size = header.u64_at(8)?;
+        let compressed = contents.get(COMPRESSION_HEADER_SIZE..)?;
+        match kind {
+            ELFCOMPRESS_ZLIB => inflate(compressed, size),
+            ELFCOMPRESS_ZSTD => read_section(
+                zstd::stream::read::Decoder::new(compressed).ok()?,
+                compressed,
+                size,
+            ),
+            _ => None,
+        }
+    }
+
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        let bytes = self
+            .data
+            .get(offset..offset.checked_add(2)?)?
+            .try_into()
+            .ok()?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        let bytes = self
+            .data
+            .get(offset..offset.checked_add(4)?)?
+            .try_into()
+            .ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn u64_at(&self, offset: usize) -> Option<u64> {
+        let bytes = self
+            .data
+            .get(offset..offset.checked_add(8)?)?
+            .try_into()
+            .ok()?;
+        Some(if self.little_endian {
+            u64::from_le_bytes(bytes)
+        } else {
+            u64::from_be_bytes(bytes)
+        })
+    }
+}
+
+fn inflate(compressed: &[u8], size: u64) -> Option<Vec<u8>> {
+    read_section(flate2::read::ZlibDecoder::new(compressed), compressed, size)
+}
+
+/// Reads a decompressed section from `decoder`, which should come to the `size` bytes its header
+/// says. The header could be corrupt, so this doesn't trust `size` to allocate up front, and
+/// stops reading once the data is longer than it should be.
+fn read_section(decoder: impl Read, compressed: &[u8], size: u64) -> Option<Vec<u8>> {
+    let capacity = size.min(MAX_COMPRESSION_RATIO * compressed.len() as u64);
+    let mut data = Vec::with_capacity(capacity as usize);
+    decoder
+        .take(size.saturating_add(1))
+        .read_to_end(&mut data)
+        .ok()?;
+    if data.len() as u64 != size {
+        return None;
+    }
+    Some(data)
+}
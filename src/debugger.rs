@@ -1,23 +1,130 @@
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
 use std::process::exit;
 
+use crate::core_file::CoreFile;
 use crate::debugger_command::DebuggerCommand;
-use crate::dwarf_data::DwarfData;
-use crate::inferior::{Inferior, Status};
+use crate::demangle::demangle_type;
+use crate::dwarf_data::{
+    DwarfData, FrameRegisters, Location, SearchKind, SearchResult, Type, TypeKind, Types, Variable,
+};
+use crate::exception;
+use crate::inferior::{print_registers, register_value, Inferior, Status, Target};
+use crate::location::{self, Frame, Place};
+use crate::memory_map::{self, MemoryMapping};
+use crate::printf;
+use crate::shared_library::{LibraryChanges, SharedLibraries};
+use crate::siginfo::SigInfo;
+use crate::value::{self, format_value};
+use nix::sys::signal::Signal;
+use regex::Regex;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
+/// Where a program that is (re)started should stop first.
+#[derive(Clone, Copy)]
+enum StopAt {
+    /// Wherever it stops by itself, e.g. at a breakpoint (`run`)
+    Anywhere,
+    /// At the beginning of `main` (`start`)
+    Main,
+    /// At its very first instruction (`starti`)
+    FirstInstruction,
+}
+
+/// A breakpoint or catchpoint the user asked for. We keep the location as typed rather than an
+/// address, because the address can change between runs (PIE executables and shared libraries are
+/// loaded somewhere different each time), and because the code might not have been loaded yet.
+struct Breakpoint {
+    /// What the user refers to it by. Numbers aren't reused when breakpoints are deleted.
+    number: usize,
+    kind: BreakpointKind,
+    /// Where the breakpoint is currently inserted, which is nowhere while it is pending. A
+    /// function that was inlined has a location for every copy.
+    addrs: Vec<usize>,
+    /// `tbreak`: deleted the first time the program stops at it
+    temporary: bool,
+    /// `dprintf`: print something and keep going, rather than stop
+    dprintf: Option<Dprintf>,
+    /// `commands`: what to run when the program stops here, which can start with `silent`
+    commands: Vec<String>,
+}
+
+/// What a `dprintf` breakpoint prints: `printf(format, args...)`, where the arguments are
+/// expressions evaluated each time it's hit.
+struct Dprintf {
+    format: String,
+    args: Vec<String>,
+}
+
+enum BreakpointKind {
+    /// `break LOCATION`, where the location is `*ADDRESS`, `FUNCTION`, `LINE` or `FILE:LINE`
+    Location(String),
+    /// `catch load [NAME]`: stop when a library (whose path contains NAME) is loaded
+    Load(Option<String>),
+    /// `catch unload [NAME]`: stop when a library (whose path contains NAME) is unloaded
+    Unload(Option<String>),
+    /// `catch panic`: stop when a Rust program panics, before unwinding starts
+    Panic,
+    /// `catch throw`: stop when a C++ exception is thrown
+    Throw,
+    /// `catch catch`: stop when a C++ exception is caught
+    Catch,
+}
+
+impl BreakpointKind {
+    /// Returns the runtime function an exception catchpoint stops at, and how to describe what
+    /// it caught.
+    fn exception_event(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            BreakpointKind::Panic => Some((exception::RUST_PANIC, "panic")),
+            BreakpointKind::Throw => Some((exception::CXA_THROW, "exception thrown")),
+            BreakpointKind::Catch => Some((exception::CXA_BEGIN_CATCH, "exception caught")),
+            _ => None,
+        }
+    }
+}
+
 pub struct Debugger {
     target: String,
     history_path: String,
+    /// Where the breakpoints (including catchpoints and dprintfs, with their commands) are kept
+    /// between sessions debugging this target, if we know where home is
+    session_path: Option<String>,
     readline: Editor<()>,
     inferior: Option<Inferior>,
+    /// Core file being inspected, when started with `--core`. Dropped as soon as the program is
+    /// run for real.
+    core: Option<CoreFile>,
     debug_data: DwarfData,
-    breakpoints: Vec<usize>,
+    shared_libraries: SharedLibraries,
+    breakpoints: Vec<Breakpoint>,
+    /// Number of the last breakpoint or catchpoint created
+    last_breakpoint_number: usize,
+    /// Commands to run before asking the user for more, e.g. those of a breakpoint that was hit
+    queued_commands: VecDeque<String>,
+    /// How many of the queued commands at startup come from the init files, which run before the
+    /// session is restored
+    init_commands: usize,
+    /// Set when the program stops at a breakpoint whose commands start with `silent`, so that
+    /// the stop isn't announced
+    silent_stop: bool,
+    /// Arguments to start the program with when `run` is given none: those given last time, or
+    /// with `--args`
+    args: Vec<String>,
+    /// `--batch`: quit once the queued commands are done, rather than prompt for more
+    batch: bool,
+    /// How the program last exited, as a shell would report it
+    exit_status: i32,
 }
 
 impl Debugger {
-    /// Initializes the debugger.
-    pub fn new(target: &str) -> Debugger {
+    /// Initializes the debugger. If `core_path` is given, the debugger starts out inspecting that
+    /// core file instead of a live process.
+    pub fn new(target: &str, core_path: Option<&str>) -> Debugger {
         let debug_data = match DwarfData::from_file(target) {
             Ok(val) => val,
             Err(crate::dwarf_data::Error::ErrorOpeningFile) => {
@@ -29,8 +136,34 @@ impl Debugger {
                 exit(1);
             }
         };
-        debug_data.print();
+        let mut debug_data = debug_data;
+        if !debug_data.has_debug_info() {
+            println!("(No debugging symbols found in {target})");
+        }
+        let core = core_path.map(|path| match CoreFile::from_file(path) {
+            Ok(core) => core,
+            Err(err) => {
+                println!("Could not load core file {path}: {err:?}");
+                exit(1);
+            }
+        });
+        let mut shared_libraries = SharedLibraries::new();
+        if let Some(core) = &core {
+            debug_data.set_load_bias(core.load_bias(&debug_data));
+            shared_libraries.init(core, &debug_data);
+            if let Err(e) = shared_libraries.update(core) {
+                println!("Could not read the list of shared libraries: {e}");
+            }
+        }
         let history_path = format!("{}/.deet_history", std::env::var("HOME").unwrap());
+        let session_path = match (std::env::var("HOME"), fs::canonicalize(target)) {
+            (Ok(home), Ok(path)) => Some(format!(
+                "{}/.deet_sessions/{}",
+                home,
+                session_file_name(&path)
+            )),
+            _ => None,
+        };
         let mut readline = Editor::<()>::new();
         // Attempt to load history from ~/.deet_history if it exists
         let _ = readline.load_history(&history_path);
@@ -38,105 +171,479 @@ impl Debugger {
         Debugger {
             target: target.to_string(),
             history_path,
+            session_path,
             readline,
             inferior: None,
+            core,
             debug_data,
+            shared_libraries,
             breakpoints: vec![],
+            last_breakpoint_number: 0,
+            queued_commands: VecDeque::new(),
+            init_commands: 0,
+            silent_stop: false,
+            args: Vec::new(),
+            batch: false,
+            exit_status: 0,
+        }
+    }
+
+    /// Sets the arguments the program is run with, as `deet --args` does.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    /// Makes the debugger quit once it has run the queued commands.
+    pub fn set_batch(&mut self) {
+        self.batch = true;
+    }
+
+    /// Queues a command to run once the ones queued so far are done.
+    pub fn queue_command(&mut self, command: &str) {
+        self.queued_commands.push_back(command.to_string());
+    }
+
+    /// Queues the commands of the script at `path` to run once the ones queued so far are done.
+    pub fn queue_script(&mut self, path: &str) {
+        match read_script(path) {
+            Ok(commands) => self.queued_commands.extend(commands),
+            Err(e) => println!("{path}: {e}."),
         }
     }
 
-    pub fn run(&mut self) {
+    /// Queues the commands of `~/.deetinit` and then `./.deetinit`, where they exist.
+    pub fn load_init_files(&mut self) {
+        let home_init = format!("{}/.deetinit", std::env::var("HOME").unwrap());
+        let local_init = ".deetinit";
+        if Path::new(&home_init).exists() {
+            self.queue_script(&home_init);
+        }
+        let same_file = match (fs::canonicalize(&home_init), fs::canonicalize(local_init)) {
+            (Ok(home_init), Ok(local_init)) => home_init == local_init,
+            _ => false,
+        };
+        if Path::new(local_init).exists() && !same_file {
+            self.queue_script(local_init);
+        }
+        self.init_commands = self.queued_commands.len();
+    }
+
+    /// Runs commands until the user quits. Returns the program's exit status, which is what
+    /// `deet --batch` exits with.
+    pub fn run(&mut self) -> i32 {
+        if let Some(core) = &self.core {
+            self.report_core(core);
+        }
+        // Like gdb, run the init files before anything else. The session comes next, so that it
+        // doesn't add the breakpoints the init files just set again.
+        let later = self.queued_commands.split_off(self.init_commands);
+        if !self.run_queued_commands() {
+            return self.exit_status;
+        }
+        // Scripted runs should do the same thing every time
+        if !self.batch {
+            self.restore_session();
+        }
+        self.queued_commands.extend(later);
+        self.run_commands();
+        if !self.batch {
+            self.save_session();
+        }
+        self.exit_status
+    }
+
+    /// Runs the queued commands, without prompting for more. Returns `false` if one of them
+    /// quits.
+    fn run_queued_commands(&mut self) -> bool {
+        while let Some(line) = self.queued_commands.pop_front() {
+            match DebuggerCommand::from_line(&line) {
+                Some(command) => {
+                    if !self.execute(command) {
+                        return false;
+                    }
+                }
+                None => println!("Unrecognized command."),
+            }
+        }
+        true
+    }
+
+    fn run_commands(&mut self) {
         loop {
-            match self.get_next_command() {
-                DebuggerCommand::Run(args) => match &mut self.inferior {
-                    // if self.inferior.is_some() {}
-                    Some(inferior) => match inferior.kill() {
+            let command = self.get_next_command();
+            if !self.execute(command) {
+                return;
+            }
+        }
+    }
+
+    /// Carries out a command. Returns `false` if it's time to quit.
+    fn execute(&mut self, command: DebuggerCommand) -> bool {
+        match command {
+            DebuggerCommand::Run(args) => self.restart(args, StopAt::Anywhere),
+            DebuggerCommand::Start(args) => self.restart(args, StopAt::Main),
+            DebuggerCommand::Starti(args) => self.restart(args, StopAt::FirstInstruction),
+            DebuggerCommand::Cont => {
+                if self.inferior.is_none() {
+                    println!("The program is not being run.");
+                    return true;
+                }
+                match self.continue_inferior() {
+                    Ok(status) => self.report_status(status),
+                    Err(e) => println!("error cannot continue child process: {e}"),
+                }
+            }
+            DebuggerCommand::Until(location) => self.until(&location),
+            DebuggerCommand::Advance(location) => self.advance(&location),
+            DebuggerCommand::Quit => {
+                if let Some(inferior) = &mut self.inferior {
+                    match inferior.kill() {
                         Ok(status) => {
-                            match status {
-                                Status::Exited(exit_code) => {
-                                    println!("Child exited (status {exit_code})");
-                                    self.inferior = None;
-                                }
-                                Status::Signaled(signal) => {
-                                    println!("Child exited due to signal {signal}");
-                                    self.inferior = None;
-                                }
-                                Status::Stopped(signal, rip) => {
-                                    println!("Child Stopped ({signal:?}, {rip})");
-                                    let line = self.debug_data.get_line_from_addr(rip);
-                                    let func = self.debug_data.get_function_from_addr(rip);
-                                    if line.is_some() && func.is_some() {
-                                        println!(
-                                            "Stopped at {} ({})",
-                                            func.unwrap(),
-                                            line.unwrap()
-                                        );
-                                    }
-                                }
-                            }
-                            self.inferior = None;
-                            self.start_deet(args);
-                        }
-                        Err(_) => self.start_deet(args),
-                    },
-                    None => self.start_deet(args),
-                },
-                DebuggerCommand::Cont => {
-                    if let Some(inferior) = &self.inferior {
-                        match inferior.cont() {
-                            Ok(status) => {
-                                println!("Child process {status:?}");
-                                if let Status::Stopped(_, rip) = status {
-                                    let line = self.debug_data.get_line_from_addr(rip);
-                                    let func = self.debug_data.get_function_from_addr(rip);
-                                    if line.is_some() && func.is_some() {
-                                        println!(
-                                            "Stopped at {} ({})",
-                                            func.unwrap(),
-                                            line.unwrap()
-                                        );
-                                    }
-                                }
-                            }
-                            Err(e) => println!("error cannot continue child process: {e}"),
+                            println!("exiting inferior {status:?}");
                         }
+                        Err(e) => println!("could not kill previous inferior {e:?}"),
+                    }
+                };
+                return false;
+            }
+            DebuggerCommand::Backtrace => match self.target() {
+                Some(target) => {
+                    if let Err(e) = target.print_backtrace(&self.modules()) {
+                        println!("error printing backtrace: {e}");
                     }
                 }
-                DebuggerCommand::Quit => {
-                    if let Some(inferior) = &mut self.inferior {
-                        match inferior.kill() {
-                            Ok(status) => {
-                                println!("exiting inferior {status:?}");
-                            }
-                            Err(e) => println!("could not kill previous inferior {e:?}"),
-                        }
-                    };
-                    return;
-                }
-                DebuggerCommand::Backtrace => {
-                    if let Some(inferior) = &self.inferior {
-                        inferior.print_backtrace(&self.debug_data).unwrap()
+                None => println!("No stack."),
+            },
+            DebuggerCommand::Break(location) => self.add_breakpoint(&location, false, None),
+            DebuggerCommand::Tbreak(location) => self.add_breakpoint(&location, true, None),
+            DebuggerCommand::Dprintf(args) => self.add_dprintf(&args),
+            DebuggerCommand::Commands(number) => self.set_commands(&number),
+            DebuggerCommand::Save(what) => match what.split_once(' ') {
+                Some(("breakpoints", path)) => self.save_breakpoints(path.trim()),
+                None if what == "breakpoints" => println!("Argument required (file name)."),
+                _ => println!("Undefined save command: \"{what}\"."),
+            },
+            DebuggerCommand::Source(path) => self.source(&path),
+            DebuggerCommand::Rbreak(pattern) => self.add_regex_breakpoints(&pattern),
+            DebuggerCommand::Delete(numbers) => self.delete(&numbers),
+            DebuggerCommand::Clear(location) => self.clear(&location),
+            DebuggerCommand::Catch(event) => self.add_catchpoint(&event),
+            DebuggerCommand::Print(expr) => self.print_expression(&expr),
+            DebuggerCommand::Examine(format, expr) => self.examine_memory(&format, &expr),
+            DebuggerCommand::Info(what) => self.print_info(&what),
+            DebuggerCommand::Thread(num) => self.select_thread(&num),
+            DebuggerCommand::Gcore(path) => match &self.inferior {
+                Some(inferior) => {
+                    let path = path.unwrap_or_else(|| format!("core.{}", inferior.pid()));
+                    match inferior.gcore(&path) {
+                        Ok(()) => println!("Saved corefile {path}"),
+                        Err(e) => println!("Can't create a corefile: {e}"),
                     }
                 }
-                DebuggerCommand::Break(arg) => {
-                    let addr = self.parse_address(&arg[1..]).unwrap();
-                    self.breakpoints.push(addr);
-                    // check if inferior is running already and borrow as mutable reference
-                    if self.inferior.is_some() {
-                        let inf = self.inferior.as_mut().unwrap();
-                        inf.set_breakpoint(addr).unwrap();
+                None => println!("The program is not being run."),
+            },
+        }
+        true
+    }
+
+    /// Implements `break LOCATION`, and `tbreak LOCATION` if `temporary`. If the program is
+    /// running, the breakpoint is inserted right away; otherwise it's inserted when the program
+    /// starts. Locations that don't match anything yet are kept as pending breakpoints, in case a
+    /// shared library that gets loaded later defines them.
+    fn add_breakpoint(&mut self, location: &str, temporary: bool, dprintf: Option<Dprintf>) {
+        let location = location.trim();
+        if location.is_empty() {
+            println!("Argument required (location to break at).");
+            return;
+        }
+        let num = self.last_breakpoint_number + 1;
+        let what = match (temporary, &dprintf) {
+            (_, Some(_)) => "Dprintf",
+            (true, None) => "Temporary breakpoint",
+            (false, None) => "Breakpoint",
+        };
+        let addrs = self.resolve_location(location);
+        if let Some(inferior) = &mut self.inferior {
+            // Addresses this breakpoint inserted, rather than shared with another one
+            let mut inserted = Vec::new();
+            for addr in &addrs {
+                let new = !inferior.has_breakpoint(*addr);
+                if let Err(e) = inferior.set_breakpoint(*addr) {
+                    println!("Cannot insert breakpoint at {:#x}: {e}", addr);
+                    // Don't leave behind traps that no breakpoint accounts for
+                    for addr in inserted {
+                        let _ = inferior.remove_breakpoint(addr);
                     }
-                    // The ref mut part of the pattern means that inferior is a mutable reference to the value inside the Some variant,
-                    // rather than taking ownership of the value.
-                    // if let Some(ref mut inferior) = self.inferior {
-                    //     inferior.set_breakpoint(addr).unwrap();
-                    // }
+                    return;
+                }
+                if new {
+                    inserted.push(*addr);
+                }
+            }
+        }
+        match addrs.as_slice() {
+            [] => {
+                println!("No symbol, line or address matches \"{location}\".");
+                println!("{} {} ({}) pending.", what, num, location);
+            }
+            [addr] => println!("{} {} at {:#x}", what, num, addr),
+            addrs => {
+                println!(
+                    "{} {} at {}. ({} locations)",
+                    what,
+                    num,
+                    location,
+                    addrs.len()
+                );
+                // Say which is which, e.g. for overloads or inlined copies
+                for (i, addr) in addrs.iter().enumerate() {
+                    println!(
+                        "  {}.{} {:#x} in {}",
+                        num,
+                        i + 1,
+                        addr,
+                        self.describe_location(*addr)
+                    );
+                }
+            }
+        }
+        let inserted = self.inferior.is_some();
+        self.last_breakpoint_number = num;
+        self.breakpoints.push(Breakpoint {
+            number: num,
+            kind: BreakpointKind::Location(location.to_string()),
+            addrs: if inserted { addrs } else { Vec::new() },
+            temporary,
+            dprintf,
+            commands: Vec::new(),
+        });
+    }
+
+    /// Implements `dprintf LOCATION,"FORMAT",EXPR...`: a breakpoint that prints the expressions
+    /// with a C `printf` format, and then lets the program carry on.
+    fn add_dprintf(&mut self, args: &str) {
+        let (location, rest) = match args.split_once(',') {
+            Some((location, rest)) if !location.trim().is_empty() => (location, rest),
+            _ => {
+                println!("Format string required");
+                return;
+            }
+        };
+        let (format, rest) = match printf::parse_string_literal(rest) {
+            Some(parsed) => parsed,
+            None => {
+                println!("Bad format string");
+                return;
+            }
+        };
+        let rest = rest.trim();
+        let args = if rest.is_empty() {
+            Vec::new()
+        } else if let Some(rest) = rest.strip_prefix(',') {
+            rest.split(',').map(|arg| arg.trim().to_string()).collect()
+        } else {
+            println!("Invalid argument syntax");
+            return;
+        };
+        self.add_breakpoint(location, false, Some(Dprintf { format, args }));
+    }
+
+    /// Implements `catch load [NAME]`, `catch unload [NAME]`, `catch panic`, `catch throw` and
+    /// `catch catch`. The last three are breakpoints on functions of the language runtime, which
+    /// may be in a library that isn't loaded yet.
+    fn add_catchpoint(&mut self, args: &str) {
+        let mut args = args.split_whitespace();
+        let (event, name) = (args.next(), args.next().map(|name| name.to_string()));
+        let kind = match event {
+            Some("load") => BreakpointKind::Load(name),
+            Some("unload") => BreakpointKind::Unload(name),
+            Some("panic") => BreakpointKind::Panic,
+            Some("throw") => BreakpointKind::Throw,
+            Some("catch") => BreakpointKind::Catch,
+            Some(event) => {
+                println!("Undefined catch command: \"{event}\".");
+                return;
+            }
+            None => {
+                println!(
+                    "Argument required (event to catch: load, unload, panic, throw or catch)."
+                );
+                return;
+            }
+        };
+        self.last_breakpoint_number += 1;
+        self.breakpoints.push(Breakpoint {
+            number: self.last_breakpoint_number,
+            kind,
+            addrs: Vec::new(),
+            temporary: false,
+            dprintf: None,
+            commands: Vec::new(),
+        });
+        println!(
+            "Catchpoint {} ({})",
+            self.last_breakpoint_number,
+            event.unwrap()
+        );
+        self.arm_breakpoints();
+    }
+
+    /// Re-resolves the location of every breakpoint and inserts the ones that now resolve to new
+    /// addresses. Called when the program starts and whenever the set of loaded libraries
+    /// changes. Breakpoints in libraries that were unloaded become pending again.
+    fn arm_breakpoints(&mut self) {
+        let resolved: Vec<Vec<usize>> = self
+            .breakpoints
+            .iter()
+            .map(|breakpoint| match &breakpoint.kind {
+                BreakpointKind::Location(location) => self.resolve_location(location),
+                kind => match kind.exception_event() {
+                    Some((function, _)) => self
+                        .modules()
+                        .into_iter()
+                        .find_map(|module| module.get_runtime_function_address(function))
+                        .into_iter()
+                        .collect(),
+                    None => Vec::new(),
+                },
+            })
+            .collect();
+        let inferior = match &mut self.inferior {
+            Some(inferior) => inferior,
+            None => return,
+        };
+        let mut stale = Vec::new();
+        for (breakpoint, addrs) in self.breakpoints.iter_mut().zip(resolved) {
+            if breakpoint.addrs == addrs {
+                continue;
+            }
+            let old_addrs = std::mem::take(&mut breakpoint.addrs);
+            for addr in addrs {
+                if old_addrs.contains(&addr) {
+                    breakpoint.addrs.push(addr);
+                    continue;
                 }
+                match inferior.set_breakpoint(addr) {
+                    Ok(_) => breakpoint.addrs.push(addr),
+                    Err(e) => println!(
+                        "Cannot insert breakpoint {} at {:#x}: {e}",
+                        breakpoint.number, addr
+                    ),
+                }
+            }
+            stale.extend(
+                old_addrs
+                    .into_iter()
+                    .filter(|addr| !breakpoint.addrs.contains(addr)),
+            );
+        }
+        for addr in stale {
+            let still_used = Some(addr) == self.shared_libraries.event_address()
+                || self
+                    .breakpoints
+                    .iter()
+                    .any(|other| other.addrs.contains(&addr));
+            if still_used {
+                continue;
             }
+            // Code that is still mapped gets its original byte back; code in a library that was
+            // unloaded can't be written to any more
+            let mapped = self.module_containing(addr).is_some();
+            let inferior = self.inferior.as_mut().unwrap();
+            if !mapped {
+                inferior.forget_breakpoint(addr);
+            } else if let Err(e) = inferior.remove_breakpoint(addr) {
+                println!("Cannot remove breakpoint at {:#x}: {e}", addr);
+            }
+        }
+    }
+
+    /// Tells the user about any `catch load`/`catch unload` catchpoints matching `changes`.
+    /// Returns `true` if the program should stop.
+    fn report_catchpoints(&self, changes: &LibraryChanges) -> bool {
+        let mut caught = false;
+        for breakpoint in &self.breakpoints {
+            let (libraries, verb, name) = match &breakpoint.kind {
+                BreakpointKind::Load(name) => (&changes.loaded, "loaded", name),
+                BreakpointKind::Unload(name) => (&changes.unloaded, "unloaded", name),
+                _ => continue,
+            };
+            let matching: Vec<&String> = libraries
+                .iter()
+                .filter(|path| match name {
+                    Some(name) => path.contains(name.as_str()),
+                    None => true,
+                })
+                .collect();
+            if matching.is_empty() {
+                continue;
+            }
+            println!("Catchpoint {}", breakpoint.number);
+            for path in matching {
+                println!("  Inferior {} {}", verb, path);
+            }
+            caught = true;
+        }
+        caught
+    }
+
+    /// Turns a breakpoint location into runtime addresses. Locations can be `*ADDRESS`,
+    /// `FUNCTION`, `LINE`, `FILE:LINE` or `FILE:FUNCTION`, where `FUNCTION` can be qualified
+    /// (`ns::Class::method`) and name several overloads, or pick one (`method(int)`). Functions
+    /// and files are looked up in the executable first and then in the loaded shared libraries;
+    /// a bare `LINE` always refers to the executable. Functions without debug info are found
+    /// through the ELF symbols. A function resolves to its own code (if it has any) and to every
+    /// place it was inlined.
+    fn resolve_location(&self, location: &str) -> Vec<usize> {
+        if let Some(addr) = location.strip_prefix('*') {
+            return self.parse_address(addr).into_iter().collect();
+        }
+        let (file, rest) = match split_file(location) {
+            Some((file, rest)) => (Some(file), rest),
+            None => (None, location),
+        };
+        if let Ok(line) = rest.parse() {
+            return match file {
+                Some(file) => self
+                    .modules()
+                    .into_iter()
+                    .find_map(|module| module.get_addr_for_line(Some(file), line)),
+                None => self.debug_data.get_addr_for_line(None, line),
+            }
+            .into_iter()
+            .collect();
+        }
+        let mut addrs: Vec<usize> = self
+            .modules()
+            .into_iter()
+            .map(|module| {
+                module
+                    .get_addrs_for_function(file, rest)
+                    .into_iter()
+                    .map(|addr| module.skip_prologue(addr))
+                    .collect::<Vec<usize>>()
+            })
+            .find(|addrs| !addrs.is_empty())
+            .unwrap_or_default()
+            .into_iter()
+            .chain(
+                self.modules()
+                    .into_iter()
+                    .flat_map(|module| module.get_inlined_addrs_for_function(file, rest)),
+            )
+            .collect();
+        // Without DWARF we can't tell where the prologue ends, so break on the first
+        // instruction
+        if addrs.is_empty() && file.is_none() {
+            addrs.extend(
+                self.modules()
+                    .into_iter()
+                    .find_map(|module| module.get_function_symbol_address(rest)),
+            );
         }
+        addrs
     }
 
-    #[allow(dead_code)]
     fn parse_address(&self, addr: &str) -> Option<usize> {
         let addr_without_0x = if addr.to_lowercase().starts_with("0x") {
             &addr[2..]
@@ -147,72 +654,1343 @@ impl Debugger {
     }
 
     /// This function prompts the user to enter a command, and continues re-prompting until the user
-    /// enters a valid command. It uses DebuggerCommand::from_tokens to do the command parsing.
-    ///
-    /// You don't need to read, understand, or modify this function.
+    /// enters a valid command. It uses DebuggerCommand::from_line to do the command parsing.
+    /// Queued commands (those of a breakpoint that was just hit) come before the user's.
     fn get_next_command(&mut self) -> DebuggerCommand {
         loop {
-            // Print prompt and get next line of user input
-            match self.readline.readline("(deet) ") {
+            let line = match self.next_line("(deet) ") {
+                Some(line) => line,
+                // ctrl+d is the equivalent of "quit" for our purposes
+                None => return DebuggerCommand::Quit,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(cmd) = DebuggerCommand::from_line(&line) {
+                return cmd;
+            } else {
+                println!("Unrecognized command.");
+            }
+        }
+    }
+
+    /// Returns the next queued command if there is one, and otherwise prompts the user for a
+    /// line with `prompt`. Returns `None` at the end of the input, which in batch mode is when
+    /// the queue runs out.
+    fn next_line(&mut self, prompt: &str) -> Option<String> {
+        if let Some(line) = self.queued_commands.pop_front() {
+            return Some(line);
+        }
+        if self.batch {
+            return None;
+        }
+        loop {
+            match self.readline.readline(prompt) {
                 Err(ReadlineError::Interrupted) => {
                     // User pressed ctrl+c. We're going to ignore it
                     println!("Type \"quit\" to exit");
                 }
-                Err(ReadlineError::Eof) => {
-                    // User pressed ctrl+d, which is the equivalent of "quit" for our purposes
-                    return DebuggerCommand::Quit;
-                }
+                Err(ReadlineError::Eof) => return None,
                 Err(err) => {
                     panic!("Unexpected I/O error: {:?}", err);
                 }
                 Ok(line) => {
-                    if line.trim().is_empty() {
-                        continue;
-                    }
-                    self.readline.add_history_entry(line.as_str());
-                    if let Err(err) = self.readline.save_history(&self.history_path) {
-                        println!(
-                            "Warning: failed to save history file at {}: {}",
-                            self.history_path, err
-                        );
+                    if !line.trim().is_empty() {
+                        self.readline.add_history_entry(line.as_str());
+                        if let Err(err) = self.readline.save_history(&self.history_path) {
+                            println!(
+                                "Warning: failed to save history file at {}: {}",
+                                self.history_path, err
+                            );
+                        }
                     }
-                    let tokens: Vec<&str> = line.split_whitespace().collect();
-                    if let Some(cmd) = DebuggerCommand::from_tokens(&tokens) {
-                        return cmd;
-                    } else {
-                        println!("Unrecognized command.");
+                    return Some(line);
+                }
+            }
+        }
+    }
+
+    /// Implements `commands [N]`: reads the commands to run when breakpoint N (or the last one
+    /// created) is hit, one per line up to `end`. If the first is `silent`, the stop isn't
+    /// announced.
+    fn set_commands(&mut self, number: &str) {
+        let number = match number.trim() {
+            "" => self.last_breakpoint_number,
+            number => match number.parse() {
+                Ok(number) => number,
+                Err(_) => {
+                    println!("Invalid breakpoint number \"{number}\".");
+                    return;
+                }
+            },
+        };
+        let index = match self.breakpoints.iter().position(|bp| bp.number == number) {
+            Some(index) => index,
+            None => {
+                println!("No breakpoint number {number}.");
+                return;
+            }
+        };
+        if self.queued_commands.is_empty() {
+            println!("Type commands for breakpoint(s) {number}, one per line.");
+            println!("End with a line saying just \"end\".");
+        }
+        let mut commands = Vec::new();
+        while let Some(line) = self.next_line(">") {
+            match line.trim() {
+                "end" => break,
+                "" => {}
+                command => commands.push(command.to_string()),
+            }
+        }
+        self.breakpoints[index].commands = commands;
+    }
+
+    /// Returns a script of the commands that recreate the breakpoints, with their commands. They
+    /// are given by location rather than address, so that they work in a later run.
+    fn breakpoint_script(&self) -> String {
+        let mut script = String::new();
+        for breakpoint in &self.breakpoints {
+            let command = match (&breakpoint.kind, &breakpoint.dprintf) {
+                (BreakpointKind::Location(location), Some(dprintf)) => {
+                    let mut command =
+                        format!("dprintf {},{}", location, printf::quote(&dprintf.format));
+                    for arg in &dprintf.args {
+                        command += &format!(",{}", arg);
                     }
+                    command
+                }
+                (BreakpointKind::Location(location), None) if breakpoint.temporary => {
+                    format!("tbreak {}", location)
+                }
+                (BreakpointKind::Location(location), None) => format!("break {}", location),
+                (BreakpointKind::Load(name), _) => {
+                    format!("catch load {}", name.as_deref().unwrap_or_default())
+                }
+                (BreakpointKind::Unload(name), _) => {
+                    format!("catch unload {}", name.as_deref().unwrap_or_default())
+                }
+                (BreakpointKind::Panic, _) => "catch panic".to_string(),
+                (BreakpointKind::Throw, _) => "catch throw".to_string(),
+                (BreakpointKind::Catch, _) => "catch catch".to_string(),
+            };
+            script += command.trim_end();
+            script.push('\n');
+            if !breakpoint.commands.is_empty() {
+                // Without a number, `commands` applies to the breakpoint just created
+                script += "commands\n";
+                for command in &breakpoint.commands {
+                    script += &format!("  {}\n", command);
+                }
+                script += "end\n";
+            }
+        }
+        script
+    }
+
+    /// Implements `save breakpoints FILE`.
+    fn save_breakpoints(&self, path: &str) {
+        match fs::write(path, self.breakpoint_script()) {
+            Ok(()) => println!("Saved to file '{path}'."),
+            Err(e) => println!("Unable to write {path}: {e}"),
+        }
+    }
+
+    /// Implements `source FILE`: runs the commands in FILE next, as if the user had typed them.
+    fn source(&mut self, path: &str) {
+        let path = path.trim();
+        if path.is_empty() {
+            println!("source command requires file name of file to source.");
+            return;
+        }
+        match read_script(path) {
+            Ok(commands) => {
+                for command in commands.into_iter().rev() {
+                    self.queued_commands.push_front(command);
                 }
             }
+            Err(e) => println!("{path}: {e}."),
         }
     }
 
-    fn start_deet(&mut self, args: Vec<String>) {
-        if let Some(inferior) = Inferior::new(&self.target, &args, &self.breakpoints) {
-            match inferior.cont() {
-                Ok(status) => match status {
-                    Status::Exited(exit_code) => {
-                        println!("Child exited (status {exit_code})");
-                        self.inferior = None;
+    /// Queues the commands that set up the breakpoints saved the last time we debugged this
+    /// target, leaving out the ones that match a breakpoint (with the same commands) we already
+    /// have, such as one an init file sets every time.
+    fn restore_session(&mut self) {
+        let path = match &self.session_path {
+            Some(path) if Path::new(path).exists() => path.clone(),
+            _ => return,
+        };
+        let saved = match read_script(&path) {
+            Ok(saved) => saved,
+            Err(e) => {
+                println!("{path}: {e}.");
+                return;
+            }
+        };
+        let existing: Vec<String> = self
+            .breakpoint_script()
+            .lines()
+            .map(|line| line.trim().to_string())
+            .collect();
+        let existing = breakpoint_commands(&existing);
+        println!("Restoring breakpoints from {path}");
+        for commands in breakpoint_commands(&saved).into_iter().rev() {
+            if !existing.contains(&commands) {
+                for command in commands.iter().rev() {
+                    self.queued_commands.push_front(command.clone());
+                }
+            }
+        }
+    }
+
+    /// Saves the breakpoints for `restore_session` to set up the next time we debug this target.
+    fn save_session(&self) {
+        let path = match &self.session_path {
+            Some(path) => path,
+            None => return,
+        };
+        let result = if self.breakpoints.is_empty() {
+            match fs::remove_file(path) {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            }
+        } else {
+            let dir = Path::new(path).parent().unwrap_or_else(|| Path::new("."));
+            fs::create_dir_all(dir).and_then(|_| fs::write(path, self.breakpoint_script()))
+        };
+        if let Err(e) = result {
+            println!("Warning: failed to save session file at {path}: {e}");
+        }
+    }
+
+    /// Kills the inferior, if there is one, so that it can be started again.
+    fn kill_inferior(&mut self) {
+        let inferior = match &mut self.inferior {
+            Some(inferior) => inferior,
+            None => return,
+        };
+        if let Ok(status) = inferior.kill() {
+            match status {
+                Status::Exited(exit_code) => println!("Child exited (status {exit_code})"),
+                Status::Signaled(signal) => println!("Child exited due to signal {signal}"),
+                Status::Stopped(signal, rip) => {
+                    println!("Child Stopped ({signal:?}, {rip})");
+                    let line = self.debug_data.get_line_from_addr(rip);
+                    let func = self.debug_data.get_function_from_addr(rip);
+                    if let (Some(func), Some(line)) = (func, line) {
+                        println!("Stopped at {} ({})", func, line);
                     }
-                    Status::Signaled(signal) => {
-                        println!("Child exited due to signal {signal}");
-                        self.inferior = None;
+                }
+            }
+        }
+        self.inferior = None;
+    }
+
+    /// Implements `run`, `start` and `starti`: starts the program afresh (killing it if it's
+    /// already running), and lets it run until `stop_at`.
+    fn restart(&mut self, args: Vec<String>, stop_at: StopAt) {
+        // Without arguments, the program gets the same ones as last time
+        let args = if args.is_empty() {
+            self.args.clone()
+        } else {
+            self.args = args.clone();
+            args
+        };
+        self.kill_inferior();
+        self.core = None;
+        if !self.start_deet(args) {
+            return;
+        }
+        match stop_at {
+            StopAt::Anywhere => match self.continue_inferior() {
+                Ok(status) => self.report_status(status),
+                Err(_) => panic!("Error continuing program"),
+            },
+            StopAt::Main => {
+                let addrs = self.resolve_location("main");
+                if addrs.is_empty() {
+                    println!("No symbol \"main\" in current context.");
+                    self.report_first_instruction();
+                } else {
+                    self.run_until(&addrs, &[], None);
+                }
+            }
+            StopAt::FirstInstruction => self.report_first_instruction(),
+        }
+    }
+
+    /// Says where a program that was just started is stopped: at the first instruction of the
+    /// dynamic linker, or of the executable if it's static.
+    fn report_first_instruction(&self) {
+        if let Some(Ok(regs)) = self
+            .inferior
+            .as_ref()
+            .map(|inferior| inferior.get_registers())
+        {
+            println!("Program stopped.");
+            self.print_stop_location(regs.rip as usize);
+        }
+    }
+
+    /// Implements `until` and `until LOCATION`. Without a location, runs until a line after the
+    /// current one in the current function is reached, which gets out of a loop when at its
+    /// end. With one, runs until LOCATION is reached in the current frame. Either way, also
+    /// stops when the current function returns, or leaves the inlined copy it's in.
+    fn until(&mut self, location: &str) {
+        let rip = match self
+            .inferior
+            .as_ref()
+            .map(|inferior| inferior.get_registers())
+        {
+            Some(Ok(regs)) => regs.rip as usize,
+            _ => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        let location = location.trim();
+        let addrs = if location.is_empty() {
+            self.module_containing(rip)
+                .map_or(Vec::new(), |module| module.get_later_line_addrs(rip))
+        } else {
+            self.resolve_location(location)
+        };
+        if addrs.is_empty() && !location.is_empty() {
+            println!("No symbol, line or address matches \"{location}\".");
+            return;
+        }
+        let mut in_frame = addrs;
+        if !location.is_empty() {
+            in_frame.extend(self.inlined_exit_addrs(rip));
+        }
+        let return_address = self.caller_frame().map(|caller| caller.rip);
+        self.run_until(&[], &in_frame, return_address);
+    }
+
+    /// Implements `advance LOCATION`: runs until LOCATION is reached (in any frame), or the
+    /// current function returns (or leaves the inlined copy it's in).
+    fn advance(&mut self, location: &str) {
+        let location = location.trim();
+        if location.is_empty() {
+            println!("Argument required (a location).");
+            return;
+        }
+        if self.inferior.is_none() {
+            println!("The program is not being run.");
+            return;
+        }
+        let addrs = self.resolve_location(location);
+        if addrs.is_empty() {
+            println!("No symbol, line or address matches \"{location}\".");
+            return;
+        }
+        // Leaving an inlined function counts as returning from it
+        let exits = match self.target().map(|target| target.get_registers()) {
+            Some(Ok(regs)) => self.inlined_exit_addrs(regs.rip as usize),
+            _ => Vec::new(),
+        };
+        let return_address = self.caller_frame().map(|caller| caller.rip);
+        self.run_until(&addrs, &exits, return_address);
+    }
+
+    /// Returns the addresses just past the inlined copy `addr` is in, if it's in one (see
+    /// `DwarfData::get_inlined_exit_addrs`).
+    fn inlined_exit_addrs(&self, addr: usize) -> Vec<usize> {
+        self.module_containing(addr)
+            .map_or(Vec::new(), |module| module.get_inlined_exit_addrs(addr))
+    }
+
+    /// Continues the inferior with one-shot breakpoints until it reaches one of `anywhere`, one
+    /// of `in_frame` in the current frame (or a caller, e.g. if it returned), or
+    /// `return_address` in a caller. Stops for any other reason (the user's breakpoints,
+    /// signals) are reported as usual. The frame checks are what keep recursive calls from
+    /// stopping us early.
+    fn run_until(&mut self, anywhere: &[usize], in_frame: &[usize], return_address: Option<usize>) {
+        let start_cfa = self.caller_frame().map(|caller| caller.rsp);
+        let inferior = self.inferior.as_mut().unwrap();
+        let mut inserted = Vec::new();
+        for addr in anywhere.iter().chain(in_frame).chain(&return_address) {
+            if inferior.has_breakpoint(*addr) {
+                continue;
+            }
+            match inferior.set_breakpoint(*addr) {
+                Ok(_) => inserted.push(*addr),
+                Err(e) => println!("Cannot insert breakpoint at {:#x}: {e}", addr),
+            }
+        }
+        let status = loop {
+            let status = match self.continue_inferior() {
+                Ok(status) => status,
+                Err(e) => {
+                    println!("error cannot continue child process: {e}");
+                    return;
+                }
+            };
+            let rip = match status {
+                Status::Stopped(Signal::SIGTRAP, rip) if inserted.contains(&rip) => rip,
+                status => break status,
+            };
+            if anywhere.contains(&rip) || self.breakpoints.iter().any(|bp| bp.addrs.contains(&rip))
+            {
+                break status;
+            }
+            // Frames further down the stack have lower CFAs
+            let cfa = self.caller_frame().map(|caller| caller.rsp);
+            let in_caller = matches!((cfa, start_cfa), (Some(cfa), Some(start)) if cfa > start);
+            let in_current = matches!((cfa, start_cfa), (Some(cfa), Some(start)) if cfa >= start);
+            if (return_address == Some(rip) && in_caller)
+                || (in_frame.contains(&rip) && in_current)
+                || start_cfa.is_none()
+            {
+                break status;
+            }
+        };
+        match (&status, &mut self.inferior) {
+            (Status::Stopped(..), Some(inferior)) => {
+                for addr in inserted {
+                    if let Err(e) = inferior.remove_breakpoint(addr) {
+                        println!("Cannot remove breakpoint at {:#x}: {e}", addr);
                     }
-                    Status::Stopped(signal, rip) => {
-                        println!("Child Stopped ({signal:?}, {rip})");
-                        let line = self.debug_data.get_line_from_addr(rip);
-                        let func = self.debug_data.get_function_from_addr(rip);
-                        if line.is_some() && func.is_some() {
-                            println!("Stopped at {} ({})", func.unwrap(), line.unwrap());
+                }
+            }
+            // The process is gone, and its breakpoints with it
+            _ => self.inferior = None,
+        }
+        self.report_status(status);
+    }
+
+    /// Returns the registers of the caller of the innermost frame, as they'll be once it
+    /// returns. Its `rsp` is the innermost frame's CFA, which identifies the frame.
+    fn caller_frame(&self) -> Option<FrameRegisters> {
+        let target = self.target()?;
+        let regs = target.get_registers().ok()?;
+        target.unwind(
+            &self.modules(),
+            regs.rip as usize,
+            &FrameRegisters::from_registers(&regs),
+        )
+    }
+
+    /// Starts the program, stopped at its first instruction with the breakpoints inserted.
+    /// Returns `false` if it couldn't be started.
+    fn start_deet(&mut self, args: Vec<String>) -> bool {
+        if let Some(mut inferior) = Inferior::new(&self.target, &args) {
+            // Now that the program is loaded, we know where, and can place the breakpoints
+            self.debug_data
+                .set_load_bias(inferior.load_bias(&self.debug_data));
+            self.shared_libraries.init(&inferior, &self.debug_data);
+            if let Some(addr) = self.shared_libraries.event_address() {
+                if let Err(e) = inferior.set_breakpoint(addr) {
+                    println!("Cannot track shared libraries: {e}");
+                }
+            }
+            self.inferior = Some(inferior);
+            // Breakpoints in shared libraries stay pending until the libraries are loaded
+            for breakpoint in &mut self.breakpoints {
+                breakpoint.addrs.clear();
+            }
+            self.arm_breakpoints();
+            true
+        } else {
+            println!("Error starting subprocess");
+            false
+        }
+    }
+
+    /// Continues the inferior until it stops for a reason the user cares about. Stops at the
+    /// shared library event breakpoint are handled here: we refresh the list of libraries, arm
+    /// any pending breakpoints they resolve, and only stop if a catchpoint asks us to.
+    fn continue_inferior(&mut self) -> Result<Status, nix::Error> {
+        loop {
+            let inferior = self.inferior.as_mut().unwrap();
+            match inferior.cont()? {
+                Status::Stopped(Signal::SIGTRAP, rip)
+                    if Some(rip) == self.shared_libraries.event_address() =>
+                {
+                    let changes = match self.shared_libraries.update(inferior) {
+                        Ok(changes) => changes,
+                        Err(e) => {
+                            println!("Could not read the list of shared libraries: {e}");
+                            continue;
                         }
-                        self.inferior = Some(inferior);
+                    };
+                    self.arm_breakpoints();
+                    if self.report_catchpoints(&changes) {
+                        return Ok(Status::Stopped(Signal::SIGTRAP, rip));
+                    }
+                }
+                Status::Stopped(Signal::SIGTRAP, rip) => {
+                    if self.hit_breakpoints(rip) {
+                        return Ok(Status::Stopped(Signal::SIGTRAP, rip));
                     }
+                }
+                status => return Ok(status),
+            }
+        }
+    }
+
+    /// Does what the breakpoints at `rip` ask for now that the program has reached it: prints
+    /// what `dprintf`s print, queues the breakpoints' commands, and deletes temporary
+    /// breakpoints. Returns `false` if the program should carry on, which is when only
+    /// `dprintf`s are there.
+    fn hit_breakpoints(&mut self, rip: usize) -> bool {
+        let hit: Vec<&Breakpoint> = self
+            .breakpoints
+            .iter()
+            .filter(|breakpoint| breakpoint.addrs.contains(&rip))
+            .collect();
+        for dprintf in hit
+            .iter()
+            .filter_map(|breakpoint| breakpoint.dprintf.as_ref())
+        {
+            self.print_dprintf(dprintf);
+        }
+        if !hit.is_empty() && hit.iter().all(|breakpoint| breakpoint.dprintf.is_some()) {
+            return false;
+        }
+        // Their commands run next, before the user gets to type any. Like gdb, we drop the ones
+        // after a command that resumes the program, so that they don't run at the next stop.
+        let mut commands: Vec<String> = hit
+            .iter()
+            .flat_map(|breakpoint| &breakpoint.commands)
+            .cloned()
+            .collect();
+        if let Some(resume) = commands.iter().position(|command| {
+            matches!(DebuggerCommand::from_line(command), Some(command) if command.resumes())
+        }) {
+            commands.truncate(resume + 1);
+        }
+        self.silent_stop = commands.first().map(String::as_str) == Some("silent");
+        for command in commands.into_iter().rev() {
+            if command != "silent" {
+                self.queued_commands.push_front(command);
+            }
+        }
+        self.delete_breakpoints(|breakpoint| {
+            breakpoint.temporary && breakpoint.addrs.contains(&rip)
+        });
+        true
+    }
+
+    /// Deletes the breakpoints `delete` picks, taking them out of the program unless another
+    /// breakpoint is at the same address. Returns their numbers.
+    fn delete_breakpoints(&mut self, delete: impl Fn(&Breakpoint) -> bool) -> Vec<usize> {
+        let mut numbers = Vec::new();
+        let mut addrs = Vec::new();
+        self.breakpoints.retain(|breakpoint| {
+            let deleted = delete(breakpoint);
+            if deleted {
+                numbers.push(breakpoint.number);
+                addrs.extend(breakpoint.addrs.iter().copied());
+            }
+            !deleted
+        });
+        for addr in addrs {
+            let still_used = Some(addr) == self.shared_libraries.event_address()
+                || self
+                    .breakpoints
+                    .iter()
+                    .any(|other| other.addrs.contains(&addr));
+            if let (false, Some(inferior)) = (still_used, &mut self.inferior) {
+                if let Err(e) = inferior.remove_breakpoint(addr) {
+                    println!("Cannot remove breakpoint at {:#x}: {e}", addr);
+                }
+            }
+        }
+        numbers
+    }
+
+    /// Implements `delete [N...]`, which deletes the breakpoints numbered N, or all of them.
+    fn delete(&mut self, args: &str) {
+        let mut numbers = Vec::new();
+        for arg in args.split_whitespace() {
+            match arg.parse() {
+                Ok(number) => numbers.push(number),
+                Err(_) => {
+                    println!("Invalid breakpoint number \"{arg}\".");
+                    return;
+                }
+            }
+        }
+        for number in &numbers {
+            if !self.breakpoints.iter().any(|bp| bp.number == *number) {
+                println!("No breakpoint number {number}.");
+            }
+        }
+        self.delete_breakpoints(|breakpoint| {
+            numbers.is_empty() || numbers.contains(&breakpoint.number)
+        });
+    }
+
+    /// Implements `clear LOCATION`, which deletes the breakpoints set at LOCATION, or at any of
+    /// the addresses it stands for.
+    fn clear(&mut self, location: &str) {
+        let location = location.trim();
+        if location.is_empty() {
+            println!("Argument required (location to clear).");
+            return;
+        }
+        let addrs = self.resolve_location(location);
+        let deleted = self.delete_breakpoints(|breakpoint| match &breakpoint.kind {
+            BreakpointKind::Location(other) => {
+                other == location || breakpoint.addrs.iter().any(|addr| addrs.contains(addr))
+            }
+            _ => false,
+        });
+        match deleted.as_slice() {
+            [] => println!("No breakpoint at {location}."),
+            [number] => println!("Deleted breakpoint {number}"),
+            numbers => {
+                let numbers: Vec<String> = numbers.iter().map(usize::to_string).collect();
+                println!("Deleted breakpoints {}", numbers.join(" "));
+            }
+        }
+    }
+
+    /// Prints what a `dprintf` breakpoint prints, or why it can't.
+    fn print_dprintf(&self, dprintf: &Dprintf) {
+        let target = match self.target() {
+            Some(target) => target,
+            None => return,
+        };
+        let args: Result<Vec<printf::Arg>, String> = dprintf
+            .args
+            .iter()
+            .map(|expr| self.eval_printf_arg(target, expr))
+            .collect();
+        match args.and_then(|args| printf::format(&dprintf.format, &args)) {
+            Ok(text) => {
+                print!("{}", text);
+                let _ = std::io::stdout().flush();
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    /// Evaluates an argument of a `dprintf`: a variable, `&variable`, `$register` or number.
+    fn eval_printf_arg(&self, target: &dyn Target, expr: &str) -> Result<printf::Arg, String> {
+        let no_symbol = || format!("No symbol \"{}\" in current context.", expr);
+        if expr.starts_with('$') || expr.starts_with('&') || expr.starts_with(char::is_numeric) {
+            return match self.eval_address(target, expr) {
+                Some(value) => Ok(printf::Arg::Int(value as i64)),
+                None => Err(no_symbol()),
+            };
+        }
+        let (place, var) = self.locate_variable(target, expr).ok_or_else(no_symbol)?;
+        let bytes = match place {
+            Place::Memory(addr) => target
+                .read_memory(addr, var.entity_type.size)
+                .map_err(|e| format!("Cannot access memory at address {:#x}: {e}", addr))?,
+            Place::Value(mut bytes) => {
+                bytes.resize(var.entity_type.size, 0);
+                bytes
+            }
+            Place::OptimizedOut => return Err(format!("value of {} has been optimized out", expr)),
+        };
+        value::printf_arg(&bytes, &var.entity_type, &var.types, target)
+            .ok_or_else(|| format!("Value of {} can't be passed to printf.", expr))
+    }
+
+    /// Tells the user what happened to the inferior after it ran, and forgets about it if it is
+    /// gone.
+    fn report_status(&mut self, status: Status) {
+        if Inferior::was_interrupted(&status) {
+            println!("Program interrupted");
+        }
+        match status {
+            Status::Exited(exit_code) => {
+                println!("Child exited (status {exit_code})");
+                self.inferior = None;
+                self.exit_status = exit_code;
+            }
+            Status::Signaled(signal) => {
+                println!("Child exited due to signal {signal}");
+                self.inferior = None;
+                self.exit_status = 128 + signal as i32;
+            }
+            Status::Stopped(_, _) if std::mem::take(&mut self.silent_stop) => {}
+            Status::Stopped(signal, rip) => {
+                println!("Child Stopped ({signal:?}, {rip:#x})");
+                if signal == Signal::SIGTRAP {
+                    self.report_exception(rip);
+                }
+                self.print_stop_location(rip);
+                let siginfo = self
+                    .inferior
+                    .as_ref()
+                    .and_then(|inf| inf.get_siginfo().ok());
+                if let Some(siginfo) = siginfo {
+                    if siginfo.is_fault() {
+                        let inferior = self.inferior.as_ref().unwrap();
+                        let maps = memory_map::read_maps(inferior.pid()).unwrap_or_default();
+                        self.report_crash(inferior, &maps, &siginfo);
+                    }
+                }
+            }
+        }
+    }
+
+    /// If the inferior stopped at an exception catchpoint, says which, and what the panic
+    /// message or the type of the C++ exception is.
+    fn report_exception(&self, rip: usize) {
+        let inferior = match &self.inferior {
+            Some(inferior) => inferior,
+            None => return,
+        };
+        for breakpoint in &self.breakpoints {
+            let event = match breakpoint.kind.exception_event() {
+                Some((_, event)) if breakpoint.addrs.contains(&rip) => event,
+                _ => continue,
+            };
+            let details = match breakpoint.kind {
+                BreakpointKind::Panic => exception::panic_message(inferior)
+                    .map(|message| format!("panicked with {:?}", message)),
+                BreakpointKind::Throw => exception::thrown_type(inferior)
+                    .map(|name| format!("exception of type {}", demangle_type(&name))),
+                _ => exception::caught_type(inferior)
+                    .map(|name| format!("exception of type {}", demangle_type(&name))),
+            };
+            match details {
+                Some(details) => {
+                    println!("Catchpoint {} ({}): {}", breakpoint.number, event, details)
+                }
+                None => println!("Catchpoint {} ({})", breakpoint.number, event),
+            }
+        }
+    }
+
+    /// Describes the state the core file's process was in when it died.
+    fn report_core(&self, core: &CoreFile) {
+        println!("Core was generated by pid {}.", core.pid());
+        match &core.siginfo {
+            Some(siginfo) if siginfo.is_fault() => {
+                self.report_crash(core, &core.mappings(), siginfo);
+            }
+            Some(siginfo) => {
+                println!(
+                    "Program terminated with signal {}.",
+                    siginfo
+                        .signal()
+                        .map_or("<unknown>".to_string(), |sig| sig.to_string())
+                );
+                if let Ok(regs) = core.get_registers() {
+                    self.print_stop_location(regs.rip as usize);
+                }
+            }
+            None => {
+                if let Ok(regs) = core.get_registers() {
+                    self.print_stop_location(regs.rip as usize);
+                }
+            }
+        }
+    }
+
+    /// Explains why the process crashed: the faulting address and the mapping (if any) containing
+    /// it, the faulting instruction, the registers and a backtrace.
+    fn report_crash(&self, target: &dyn Target, maps: &[MemoryMapping], siginfo: &SigInfo) {
+        println!(
+            "Program received signal {} ({}: {})",
+            siginfo
+                .signal()
+                .map_or("<unknown>".to_string(), |sig| sig.to_string()),
+            siginfo.code_name(),
+            siginfo.code_description()
+        );
+        match memory_map::find_mapping(maps, siginfo.addr) {
+            Some(mapping) => println!("Fault address: {:#x} (in {})", siginfo.addr, mapping),
+            None => println!("Fault address: {:#x} (not in any mapping)", siginfo.addr),
+        }
+        if let Ok(regs) = target.get_registers() {
+            let rip = regs.rip as usize;
+            // x86 instructions are at most 15 bytes long. We can't decode them, so show all 15.
+            match target.read_memory(rip, 15) {
+                Ok(bytes) => println!(
+                    "Faulting instruction at {:#x}: {}",
+                    rip,
+                    bytes
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect::<Vec<String>>()
+                        .join(" ")
+                ),
+                Err(_) => println!("Faulting instruction at {:#x}: <unreadable>", rip),
+            }
+            println!("Registers:");
+            print_registers(&regs);
+        }
+        println!("Backtrace:");
+        if let Err(e) = target.print_backtrace(&self.modules()) {
+            println!("error printing backtrace: {e}");
+        }
+    }
+
+    /// Returns whatever we are currently inspecting: the running inferior if there is one,
+    /// otherwise the core file.
+    fn target(&self) -> Option<&dyn Target> {
+        match (&self.inferior, &self.core) {
+            (Some(inferior), _) => Some(inferior as &dyn Target),
+            (None, Some(core)) => Some(core as &dyn Target),
+            (None, None) => None,
+        }
+    }
+
+    /// Finds a variable visible from the innermost frame, and where it is: a local of the
+    /// function we're stopped in, or otherwise a global of the executable or a shared library.
+    fn locate_variable(&self, target: &dyn Target, name: &str) -> Option<(Place, Variable)> {
+        let modules = self.modules();
+        let frame = Frame::innermost(target, &modules).ok()?;
+        if let Some(func) = frame.function() {
+            if let Some(var) = func.variables.iter().find(|var| var.name == name) {
+                return Some((location::locate(&var.location, &frame, &func), var.clone()));
+            }
+            // In a C++ method, members can be used without `this->`
+            if let Some(this) = func.variables.iter().find(|var| var.name == "this") {
+                let place = location::locate(&this.location, &frame, &func);
+                if let Some(member) = member_of_this(target, place, this, name) {
+                    return Some(member);
+                }
+            }
+        }
+        let (module, var) = modules
+            .iter()
+            .find_map(|module| Some((module, module.get_global_variable(name)?)))?;
+        Some((location::locate_global(&var.location, &frame, module), var))
+    }
+
+    /// Evaluates an expression used as an address by `x`: a number, `$register` or `&variable`.
+    fn eval_address(&self, target: &dyn Target, expr: &str) -> Option<usize> {
+        if let Some(reg) = expr.strip_prefix('$') {
+            let regs = target.get_registers().ok()?;
+            return register_value(&regs, reg).map(|value| value as usize);
+        }
+        if let Some(name) = expr.strip_prefix('&') {
+            return match self.locate_variable(target, name) {
+                Some((Place::Memory(addr), _)) => Some(addr),
+                Some(_) => None,
+                None => self.get_symbol_address(name),
+            };
+        }
+        if expr.to_lowercase().starts_with("0x") {
+            self.parse_address(expr)
+        } else {
+            expr.parse().ok()
+        }
+    }
+
+    /// Evaluates and prints an expression for the `print` command: a variable, `&variable`, a
+    /// `$register` or the `$_siginfo` convenience variable.
+    fn print_expression(&self, expr: &str) {
+        let expr = expr.trim();
+        let target = match self.target() {
+            Some(target) => target,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        if let Some(path) = expr.strip_prefix("$_siginfo") {
+            match target.get_siginfo() {
+                Ok(siginfo) => match siginfo.field(path.trim_start_matches('.')) {
+                    Some(value) => println!("{value}"),
+                    None => println!("There is no member named {}.", path),
+                },
+                Err(e) => println!("Unable to read signal information: {e}"),
+            }
+        } else if let Some(reg) = expr.strip_prefix('$') {
+            match target
+                .get_registers()
+                .ok()
+                .and_then(|regs| register_value(&regs, reg))
+            {
+                Some(value) => println!("{:#x}", value),
+                None => println!("Invalid register `{reg}'"),
+            }
+        } else if let Some(name) = expr.strip_prefix('&') {
+            match self.locate_variable(target, name) {
+                Some((Place::Memory(addr), var)) => {
+                    println!("({} *) {:#x}", var.entity_type.name, addr)
+                }
+                Some(_) => println!("Can't take address of \"{name}\" which isn't an lvalue."),
+                None => match self.get_symbol_address(name) {
+                    Some(addr) => println!("(<no debug info> *) {:#x} <{}>", addr, name),
+                    None => println!("No symbol \"{name}\" in current context."),
                 },
-                Err(_) => panic!("Error continuing program"),
             }
         } else {
-            println!("Error starting subprocess");
+            match self.locate_variable(target, expr) {
+                Some((Place::Memory(addr), var)) => {
+                    match target.read_memory(addr, var.entity_type.size) {
+                        Ok(bytes) => println!(
+                            "{}",
+                            format_value(&bytes, &var.entity_type, &var.types, target)
+                        ),
+                        Err(e) => println!("Cannot access memory at address {:#x}: {e}", addr),
+                    }
+                }
+                Some((Place::Value(mut bytes), var)) => {
+                    bytes.resize(var.entity_type.size, 0);
+                    println!(
+                        "{}",
+                        format_value(&bytes, &var.entity_type, &var.types, target)
+                    );
+                }
+                Some((Place::OptimizedOut, _)) => println!("<optimized out>"),
+                // We know where it is, but not how big it is or how to show it
+                None if self.get_symbol_address(expr).is_some() => {
+                    println!("'{expr}' has unknown type; use x/FMT &{expr} to examine its memory")
+                }
+                None => println!("No symbol \"{expr}\" in current context."),
+            }
+        }
+    }
+
+    /// Implements `x/FMT ADDRESS`. FMT is an optional count, followed by an optional format
+    /// letter (x, d, u or c) and an optional unit size letter (b, h, w or g), like in gdb.
+    fn examine_memory(&self, format: &str, expr: &str) {
+        let target = match self.target() {
+            Some(target) => target,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        let digits: String = format.chars().take_while(|c| c.is_ascii_digit()).collect();
+        let count = digits.parse().unwrap_or(1);
+        let mut letter = 'x';
+        let mut size = 4;
+        for c in format[digits.len()..].chars() {
+            match c {
+                'x' | 'd' | 'u' | 'c' => letter = c,
+                'b' => size = 1,
+                'h' => size = 2,
+                'w' => size = 4,
+                'g' => size = 8,
+                _ => {
+                    println!("Invalid format letter '{c}'");
+                    return;
+                }
+            }
+        }
+        if letter == 'c' {
+            size = 1;
+        }
+        let addr = match self.eval_address(target, expr.trim()) {
+            Some(addr) => addr,
+            None => {
+                println!("Invalid address expression \"{expr}\"");
+                return;
+            }
+        };
+        let bytes = match target.read_memory(addr, count * size) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                println!("Cannot access memory at address {:#x}: {e}", addr);
+                return;
+            }
+        };
+        let per_line = std::cmp::max(1, 16 / size);
+        for (i, unit) in bytes.chunks(size).enumerate() {
+            if i % per_line == 0 {
+                if i > 0 {
+                    println!();
+                }
+                let line_addr = addr + i * size;
+                match self.describe_symbol(line_addr) {
+                    Some(symbol) => print!("{:#x} <{}>:", line_addr, symbol),
+                    None => print!("{:#x}:", line_addr),
+                }
+            }
+            let mut raw = [0u8; 8];
+            raw[..size].copy_from_slice(unit);
+            let value = u64::from_le_bytes(raw);
+            let shift = 64 - 8 * size as u32;
+            match letter {
+                'd' => print!("\t{}", ((value << shift) as i64) >> shift),
+                'u' => print!("\t{}", value),
+                'c' => print!("\t{} {:?}", value as i8, value as u8 as char),
+                _ => print!("\t{:#0width$x}", value, width = 2 + 2 * size),
+            }
+        }
+        println!();
+    }
+
+    /// Implements `info registers [REG]`, `info threads`, `info sharedlibrary` and
+    /// `info symbol ADDRESS`.
+    fn print_info(&self, what: &str) {
+        let mut args = what.split_whitespace();
+        let target = self.target();
+        match (args.next(), target) {
+            (Some("registers") | Some("reg") | Some("r"), Some(target)) => {
+                match target.get_registers() {
+                    Ok(regs) => match args.next() {
+                        Some(reg) => match register_value(&regs, reg.trim_start_matches('$')) {
+                            Some(value) => println!("{:<8} {:#018x} {}", reg, value, value as i64),
+                            None => println!("Invalid register `{reg}'"),
+                        },
+                        None => print_registers(&regs),
+                    },
+                    Err(e) => println!("Unable to read registers: {e}"),
+                }
+            }
+            (Some("threads"), Some(_)) => {
+                if let Some(core) = &self.core {
+                    for (i, thread) in core.threads.iter().enumerate() {
+                        println!(
+                            "{} {:<4} LWP {:<8} {}",
+                            if i == core.selected_thread { '*' } else { ' ' },
+                            i + 1,
+                            thread.pid,
+                            self.describe_location(thread.regs.rip as usize)
+                        );
+                    }
+                } else if let Some(inferior) = &self.inferior {
+                    // We don't follow threads in live processes, so there is only ever one
+                    let rip = inferior.get_registers().map_or(0, |regs| regs.rip as usize);
+                    println!(
+                        "* 1    process {:<8} {}",
+                        inferior.pid(),
+                        self.describe_location(rip)
+                    );
+                }
+            }
+            (Some("sharedlibrary") | Some("shared") | Some("dll"), _) => {
+                self.print_shared_libraries()
+            }
+            (Some("symbol"), _) => {
+                let expr = args.collect::<Vec<&str>>().join(" ");
+                self.print_symbol_info(&expr);
+            }
+            (Some("functions"), _) => self.print_search(
+                SearchKind::Functions,
+                &args.collect::<Vec<&str>>().join(" "),
+            ),
+            (Some("variables"), _) => self.print_search(
+                SearchKind::Variables,
+                &args.collect::<Vec<&str>>().join(" "),
+            ),
+            (Some("types"), _) => {
+                self.print_search(SearchKind::Types, &args.collect::<Vec<&str>>().join(" "))
+            }
+            (Some("registers") | Some("reg") | Some("r") | Some("threads"), None) => {
+                println!("The program has no registers now.")
+            }
+            _ => println!("Undefined info command: \"{what}\"."),
         }
     }
+
+    /// Implements `info functions`, `info variables` and `info types`, optionally narrowed down
+    /// to the names matching a regular expression. Functions and variables we only have ELF
+    /// symbols for are listed at the end, with their addresses.
+    fn print_search(&self, kind: SearchKind, pattern: &str) {
+        let regex = match Regex::new(pattern) {
+            Ok(regex) => regex,
+            Err(e) => {
+                println!("Invalid regexp: {e}");
+                return;
+            }
+        };
+        let what = match kind {
+            SearchKind::Functions => "functions",
+            SearchKind::Variables => "variables",
+            SearchKind::Types => "types",
+        };
+        if pattern.is_empty() {
+            println!("All defined {what}:");
+        } else {
+            println!("All {what} matching regular expression \"{pattern}\":");
+        }
+        let (described, symbols): (Vec<SearchResult>, Vec<SearchResult>) = self
+            .modules()
+            .iter()
+            .flat_map(|module| module.search(kind, &regex))
+            .partition(|result| result.file.is_some());
+        let mut last_file = None;
+        for result in &described {
+            if result.file != last_file {
+                println!();
+                println!("File {}:", result.file.as_deref().unwrap_or_default());
+                last_file = result.file.clone();
+            }
+            match result.line_number {
+                0 => println!("\t{};", result.declaration),
+                line => println!("{}:\t{};", line, result.declaration),
+            }
+        }
+        if !symbols.is_empty() {
+            println!();
+            println!("Non-debugging symbols:");
+            for result in &symbols {
+                println!("{:#018x}  {}", result.address.unwrap_or(0), result.name);
+            }
+        }
+    }
+
+    /// Implements `rbreak REGEX`: sets a breakpoint on every function with debug info whose name
+    /// matches.
+    fn add_regex_breakpoints(&mut self, pattern: &str) {
+        let regex = match Regex::new(pattern.trim()) {
+            Ok(regex) => regex,
+            Err(e) => {
+                println!("Invalid regexp: {e}");
+                return;
+            }
+        };
+        let mut functions: Vec<SearchResult> = self
+            .modules()
+            .iter()
+            .flat_map(|module| module.search(SearchKind::Functions, &regex))
+            .filter(|result| result.file.is_some())
+            .collect();
+        // The same function can be found in several modules and units, and one breakpoint
+        // covers all of its copies
+        functions.sort_by(|a, b| (&a.file, &a.name).cmp(&(&b.file, &b.name)));
+        functions.dedup_by(|a, b| a.file == b.file && a.name == b.name);
+        for func in functions {
+            // Naming the file tells apart static functions of the same name
+            let location = format!("{}:{}", func.file.unwrap_or_default(), func.name);
+            self.add_breakpoint(&location, false, None);
+            println!("{};", func.declaration);
+        }
+    }
+
+    /// Implements `info symbol ADDRESS`, which names the function or variable at an address.
+    fn print_symbol_info(&self, expr: &str) {
+        if expr.is_empty() {
+            println!("Argument required (address).");
+            return;
+        }
+        let addr = match self.target() {
+            Some(target) => self.eval_address(target, expr),
+            None if expr.to_lowercase().starts_with("0x") => self.parse_address(expr),
+            None => expr.parse().ok(),
+        };
+        let addr = match addr {
+            Some(addr) => addr,
+            None => {
+                println!("Invalid address expression \"{expr}\"");
+                return;
+            }
+        };
+        let module = self.module_containing(addr);
+        match module.and_then(|module| module.get_symbol_for_addr(addr)) {
+            Some((name, offset)) => {
+                let mut description = name.to_string();
+                if offset > 0 {
+                    description += &format!(" + {}", offset);
+                }
+                if let Some(module) = module.filter(|module| module.path() != self.target) {
+                    description += &format!(" in {}", module.path());
+                }
+                println!("{}", description);
+            }
+            None => println!("No symbol matches {expr}."),
+        }
+    }
+
+    /// Lists the loaded shared libraries and where their code is, like gdb's `info sharedlibrary`.
+    fn print_shared_libraries(&self) {
+        if self.shared_libraries.iter().next().is_none() {
+            println!("No shared libraries loaded at this time.");
+            return;
+        }
+        println!(
+            "{:<18}  {:<18}  {:<10}  Shared Object Library",
+            "From", "To", "Syms Read"
+        );
+        let mut missing_debug_info = false;
+        for lib in self.shared_libraries.iter() {
+            let (from, to) = lib.debug_data.text_range();
+            let syms_read = if lib.debug_data.has_debug_info() {
+                "Yes"
+            } else {
+                missing_debug_info = true;
+                "Yes (*)"
+            };
+            println!(
+                "{:#018x}  {:#018x}  {:<10}  {}",
+                from,
+                to,
+                syms_read,
+                lib.debug_data.path()
+            );
+        }
+        if missing_debug_info {
+            println!("(*): Shared library is missing debugging information.");
+        }
+    }
+
+    /// Implements `thread N`, which selects the thread whose registers the other commands use.
+    fn select_thread(&mut self, num: &str) {
+        let core = match &mut self.core {
+            Some(core) => core,
+            None => {
+                println!("Only thread 1 exists.");
+                return;
+            }
+        };
+        match num.trim().parse::<usize>() {
+            Ok(n) if n >= 1 && n <= core.threads.len() => {
+                core.selected_thread = n - 1;
+                let rip = core.threads[n - 1].regs.rip as usize;
+                println!(
+                    "[Switching to thread {} (LWP {})]",
+                    n,
+                    core.threads[n - 1].pid
+                );
+                self.print_stop_location(rip);
+            }
+            _ => println!("Invalid thread ID: {num}"),
+        }
+    }
+
+    /// Prints the function and line the inferior is stopped at.
+    fn print_stop_location(&self, rip: usize) {
+        println!("Stopped at {}", self.describe_location(rip));
+    }
+
+    /// Describes an address as a function and line, falling back to the raw address when there's
+    /// no debug info for it (e.g. when the inferior was interrupted inside libc).
+    fn describe_location(&self, rip: usize) -> String {
+        let module = match self.module_containing(rip) {
+            Some(module) => module,
+            None => return format!("{:#x}", rip),
+        };
+        let line = module.get_line_from_addr(rip);
+        let func = module.get_function_from_addr(rip);
+        match (func, line) {
+            (Some(func), Some(line)) => format!("{} ({})", func, line),
+            (Some(func), None) => format!("{} ({:#x})", func, rip),
+            _ => format!("{:#x}", rip),
+        }
+    }
+
+    /// Returns the debug info of the executable followed by that of every loaded shared library.
+    fn modules(&self) -> Vec<&DwarfData> {
+        std::iter::once(&self.debug_data)
+            .chain(self.shared_libraries.iter().map(|lib| &lib.debug_data))
+            .collect()
+    }
+
+    /// Returns the debug info of the executable or shared library whose code contains `addr`.
+    fn module_containing(&self, addr: usize) -> Option<&DwarfData> {
+        self.modules()
+            .into_iter()
+            .find(|module| module.contains(addr))
+    }
+
+    /// Returns the runtime address of the ELF symbol `name` in the executable or a shared library.
+    fn get_symbol_address(&self, name: &str) -> Option<usize> {
+        self.modules()
+            .into_iter()
+            .find_map(|module| module.get_symbol_address(name))
+    }
+
+    /// Describes `addr` relative to the symbol containing it, the way gdb annotates addresses:
+    /// `main`, `main+4`.
+    fn describe_symbol(&self, addr: usize) -> Option<String> {
+        let (name, offset) = self.module_containing(addr)?.get_symbol_for_addr(addr)?;
+        if offset == 0 {
+            Some(name.to_string())
+        } else {
+            Some(format!("{}+{}", name, offset))
+        }
+    }
+}
+
+/// Splits a `FILE:LINE` or `FILE:FUNCTION` location at the colon after the file name, which is
+/// the last one that isn't half of a `::` in a qualified function name.
+fn split_file(location: &str) -> Option<(&str, &str)> {
+    let bytes = location.as_bytes();
+    let colon = (0..bytes.len()).rev().find(|&i| {
+        bytes[i] == b':' && bytes.get(i + 1) != Some(&b':') && (i == 0 || bytes[i - 1] != b':')
+    })?;
+    Some((&location[..colon], &location[colon + 1..]))
+}
+
+/// Names the session file of the target at `path` after its full path, with `%` and `/` escaped
+/// as `%25` and `%2F` so that different paths can't end up with the same name.
+fn session_file_name(path: &Path) -> String {
+    path.to_string_lossy()
+        .replace('%', "%25")
+        .replace('/', "%2F")
+}
+
+/// Splits a script written by `breakpoint_script` (and read back by `read_script`) into the
+/// commands that create each breakpoint, which are a line followed by its `commands` block, if
+/// it has one.
+fn breakpoint_commands(script: &[String]) -> Vec<&[String]> {
+    let mut breakpoints = Vec::new();
+    let mut start = 0;
+    while start < script.len() {
+        let mut end = start + 1;
+        if script.get(end).map(String::as_str) == Some("commands") {
+            end = script[end..]
+                .iter()
+                .position(|line| line == "end")
+                .map_or(script.len(), |i| end + i + 1);
+        }
+        breakpoints.push(&script[start..end]);
+        start = end;
+    }
+    breakpoints
+}
+
+/// Returns the commands in the script at `path`, leaving out blank lines and `#` comments.
+fn read_script(path: &str) -> std::io::Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Finds the member `name` of the object `this` points to, looking in its base classes too.
+fn member_of_this(
+    target: &dyn Target,
+    place: Place,
+    this: &Variable,
+    name: &str,
+) -> Option<(Place, Variable)> {
+    let this_addr = match place {
+        Place::Memory(addr) => target.read_word(addr).ok()?,
+        Place::Value(mut bytes) => {
+            bytes.resize(8, 0);
+            u64::from_le_bytes(bytes[..8].try_into().ok()?) as usize
+        }
+        Place::OptimizedOut => return None,
+    };
+    let class = match this.types.resolve(&this.entity_type)?.kind {
+        TypeKind::Pointer(Some(class)) => this.types.get(class)?,
+        _ => return None,
+    };
+    let (offset, type_offset) = find_member(&this.types, class, name)?;
+    let var = Variable {
+        name: name.to_string(),
+        entity_type: this.types.get(type_offset)?.clone(),
+        types: this.types.clone(),
+        location: Location::OptimizedOut,
+        line_number: this.line_number,
+    };
+    Some((Place::Memory(this_addr.checked_add(offset)?), var))
+}
+
+/// Returns the offset and type of the member `name` of `class`, which can be in one of its base
+/// classes or anonymous unions.
+fn find_member(types: &Types, class: &Type, name: &str) -> Option<(usize, usize)> {
+    let fields = match &class.kind {
+        TypeKind::Struct(fields) => fields,
+        _ => return None,
+    };
+    fields.members.iter().find_map(|member| {
+        if member.name == name {
+            Some((member.offset, member.type_offset))
+        } else if member.name.is_empty() {
+            let (offset, type_offset) = find_member(types, types.get(member.type_offset)?, name)?;
+            Some((member.offset + offset, type_offset))
+        } else {
+            None
+        }
+    })
 }
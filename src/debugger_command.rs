@@ -1,12 +1,60 @@
 pub enum DebuggerCommand {
     Quit,
     Run(Vec<String>),
+    /// Like `run`, but stop at the beginning of `main`
+    Start(Vec<String>),
+    /// Like `run`, but stop at the program's very first instruction
+    Starti(Vec<String>),
     Cont, // continue
+    /// `until [LOCATION]`: run until a later line of the current function, or LOCATION in the
+    /// current frame
+    Until(String),
+    /// `advance LOCATION`
+    Advance(String),
     Backtrace,
     Break(String),
+    /// `rbreak REGEX`: breakpoints on every function whose name matches
+    Rbreak(String),
+    /// `delete [N...]`: delete the given breakpoints, or all of them
+    Delete(String),
+    /// `clear LOCATION`: delete the breakpoints at LOCATION
+    Clear(String),
+    /// `tbreak LOCATION`: a breakpoint that's deleted once it's hit
+    Tbreak(String),
+    /// `dprintf LOCATION,"FORMAT",EXPR...`
+    Dprintf(String),
+    /// `commands [N]`, which is followed by the commands, up to `end`
+    Commands(String),
+    Print(String),
+    /// `x/FMT ADDRESS`: the format string (without the slash) and the address expression
+    Examine(String, String),
+    Info(String),
+    Thread(String),
+    /// Write a core file of the inferior, optionally to the given path
+    Gcore(Option<String>),
+    /// `save breakpoints FILE`
+    Save(String),
+    /// `source FILE`: run the commands in FILE
+    Source(String),
+    /// `catch EVENT [ARGS]`
+    Catch(String),
 }
 
 impl DebuggerCommand {
+    /// Parses a line the user typed. Most commands only care about its words, but the ones whose
+    /// arguments can contain string literals get the rest of the line as typed, so that spacing
+    /// inside quotes is kept.
+    pub fn from_line(line: &str) -> Option<DebuggerCommand> {
+        let line = line.trim();
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let rest = line[tokens.first()?.len()..].trim_start().to_string();
+        match tokens[0] {
+            "dprintf" => Some(DebuggerCommand::Dprintf(rest)),
+            "commands" => Some(DebuggerCommand::Commands(rest)),
+            _ => DebuggerCommand::from_tokens(&tokens),
+        }
+    }
+
     pub fn from_tokens(tokens: &[&str]) -> Option<DebuggerCommand> {
         match tokens[0] {
             "q" | "quit" => Some(DebuggerCommand::Quit),
@@ -16,17 +64,60 @@ impl DebuggerCommand {
                     args.iter().map(|s| s.to_string()).collect(),
                 ))
             }
+            "start" => Some(DebuggerCommand::Start(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "starti" => Some(DebuggerCommand::Starti(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
             "c" | "cont" | "continue" => Some(DebuggerCommand::Cont),
+            "u" | "until" => Some(DebuggerCommand::Until(tokens[1..].join(" "))),
+            "advance" => Some(DebuggerCommand::Advance(tokens[1..].join(" "))),
             "bt" | "back" | "backtrace" => Some(DebuggerCommand::Backtrace),
             "b" | "brk" | "break" => {
                 let arg = tokens[1..].join(" ");
                 Some(DebuggerCommand::Break(arg))
             }
+            "rbreak" => Some(DebuggerCommand::Rbreak(tokens[1..].join(" "))),
+            "d" | "delete" => Some(DebuggerCommand::Delete(tokens[1..].join(" "))),
+            "clear" => Some(DebuggerCommand::Clear(tokens[1..].join(" "))),
+            "tb" | "tbreak" => Some(DebuggerCommand::Tbreak(tokens[1..].join(" "))),
+            "p" | "print" => Some(DebuggerCommand::Print(tokens[1..].join(" "))),
+            "x" => Some(DebuggerCommand::Examine(
+                String::new(),
+                tokens[1..].join(" "),
+            )),
+            cmd if cmd.starts_with("x/") => Some(DebuggerCommand::Examine(
+                cmd[2..].to_string(),
+                tokens[1..].join(" "),
+            )),
+            "i" | "info" => Some(DebuggerCommand::Info(tokens[1..].join(" "))),
+            "t" | "thread" => Some(DebuggerCommand::Thread(tokens[1..].join(" "))),
+            "gcore" | "generate-core-file" => {
+                Some(DebuggerCommand::Gcore(tokens.get(1).map(|s| s.to_string())))
+            }
+            "catch" => Some(DebuggerCommand::Catch(tokens[1..].join(" "))),
+            "save" => Some(DebuggerCommand::Save(tokens[1..].join(" "))),
+            "source" => Some(DebuggerCommand::Source(tokens[1..].join(" "))),
             // Default case:
             _ => None,
         }
     }
 
+    /// Returns whether the command sets the program running again, after which the rest of a
+    /// breakpoint's commands are dropped, as in gdb.
+    pub fn resumes(&self) -> bool {
+        matches!(
+            self,
+            Self::Run(..)
+                | Self::Start(..)
+                | Self::Starti(..)
+                | Self::Cont
+                | Self::Until(..)
+                | Self::Advance(..)
+        )
+    }
+
     /// Returns `true` if the debugger command is [`Break`].
     ///
     /// [`Break`]: DebuggerCommand::Break
@@ -36,3 +127,73 @@ impl DebuggerCommand {
         matches!(self, Self::Break(..))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::DebuggerCommand;
+
+    #[test]
+    fn dprintf_keeps_spacing_in_format_string() {
+        let line = "dprintf  helper,\"x =  %d\tname = \\t%s\\n\", x,  name ";
+        match DebuggerCommand::from_line(line) {
+            Some(DebuggerCommand::Dprintf(args)) => {
+                assert_eq!(args, "helper,\"x =  %d\tname = \\t%s\\n\", x,  name")
+            }
+            _ => panic!("not parsed as dprintf"),
+        }
+    }
+
+    #[test]
+    fn only_running_commands_resume() {
+        for line in ["c", "continue", "run", "start", "until 5", "advance main"] {
+            assert!(
+                DebuggerCommand::from_line(line).unwrap().resumes(),
+                "{}",
+                line
+            );
+        }
+        for line in ["print x", "bt", "break main", "silent"] {
+            assert!(
+                !matches!(DebuggerCommand::from_line(line), Some(c) if c.resumes()),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn delete_takes_breakpoint_numbers() {
+        for (line, expected) in [("delete", ""), ("d 1 3", "1 3"), ("delete  2   4 ", "2 4")] {
+            match DebuggerCommand::from_line(line) {
+                Some(DebuggerCommand::Delete(numbers)) => assert_eq!(numbers, expected),
+                _ => panic!("{} not parsed as delete", line),
+            }
+        }
+    }
+
+    #[test]
+    fn clear_takes_location() {
+        for (line, expected) in [
+            ("clear main", "main"),
+            ("clear d.c:12", "d.c:12"),
+            ("clear", ""),
+        ] {
+            match DebuggerCommand::from_line(line) {
+                Some(DebuggerCommand::Clear(location)) => assert_eq!(location, expected),
+                _ => panic!("{} not parsed as clear", line),
+            }
+        }
+    }
+
+    #[test]
+    fn commands_takes_rest_of_line() {
+        match DebuggerCommand::from_line("commands 2") {
+            Some(DebuggerCommand::Commands(number)) => assert_eq!(number, "2"),
+            _ => panic!("not parsed as commands"),
+        }
+        match DebuggerCommand::from_line("commands") {
+            Some(DebuggerCommand::Commands(number)) => assert_eq!(number, ""),
+            _ => panic!("not parsed as commands"),
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! Turning the mangled symbol names compilers give functions back into the names in the source.
+
+/// Returns the source name of the function or variable with the symbol name `name`, or `name`
+/// itself if it isn't mangled (as in C) or we can't make sense of it. Rust symbols, in either
+/// the legacy or the v0 scheme, are shown without their hash, e.g. `prog::inspect`. C++ symbols
+/// (in the Itanium scheme gcc and clang use) are shown with their parameter types, which tell
+/// overloads apart, e.g. `geometry::Point::move(int, int)`.
+pub fn demangle(name: &str) -> String {
+    if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+        return format!("{:#}", demangled);
+    }
+    if name.starts_with("_Z") {
+        if let Ok(symbol) = cpp_demangle::Symbol::new(name) {
+            if let Ok(demangled) = symbol.demangle(&Default::default()) {
+                return demangled;
+            }
+        }
+    }
+    name.to_string()
+}
+
+/// Returns the C++ type with the mangled name `name`, as found in a `std::type_info`, e.g.
+/// `std::runtime_error` for `St13runtime_error`.
+pub fn demangle_type(name: &str) -> String {
+    // A type's name is mangled like that of its typeinfo name object, without the prefix
+    const PREFIX: &str = "typeinfo name for ";
+    cpp_demangle::Symbol::new(format!("_ZTS{}", name))
+        .ok()
+        .and_then(|symbol| symbol.demangle(&Default::default()).ok())
+        .and_then(|demangled| Some(demangled.strip_prefix(PREFIX)?.to_string()))
+        .unwrap_or_else(|| name.to_string())
+}
+
+/// Returns the last component of the qualified function or variable name `name`, leaving out
+/// any parameter list: `method` for `ns::Class<int>::method(int)`. Name indexes are keyed by
+/// these, since that's all DWARF's `DW_AT_name` has.
+pub fn base_name(name: &str) -> &str {
+    let name = without_parameters(name);
+    let mut depth = 0;
+    let mut start = 0;
+    let bytes = name.as_bytes();
+    for (i, c) in bytes.iter().enumerate() {
+        match c {
+            b'<' | b'(' => depth += 1,
+            b'>' | b')' => depth -= 1,
+            b':' if depth == 0 && i > 0 && bytes[i - 1] == b':' => start = i + 1,
+            _ => {}
+        }
+    }
+    &name[start..]
+}
+
+/// Returns `name` without the parameter list at its end, if it has one.
+pub fn without_parameters(name: &str) -> &str {
+    if !name.ends_with(')') {
+        return name;
+    }
+    let mut depth = 0;
+    for (i, c) in name.char_indices().rev() {
+        match c {
+            ')' => depth += 1,
+            '(' => {
+                depth -= 1;
+                // `operator()` ends in a parameter list that is part of its name
+                if depth == 0 && !name[..i].ends_with("operator") {
+                    return &name[..i];
+                }
+            }
+            _ => {}
+        }
+    }
+    name
+}
@@ -1,7 +1,18 @@
+use crate::debug_file;
+use crate::demangle::{base_name, demangle};
 use crate::gimli_wrapper;
+use crate::index_cache;
+use crate::name_index::{self, NameIndex};
+use crate::split_dwarf;
+use crate::tls::{self, TlsSegment};
 use addr2line::Context;
-use object::Object;
+use object::{Object, ObjectSection, ObjectSegment, SymbolKind};
+use regex::Regex;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryInto;
+use std::path::Path;
+use std::rc::Rc;
 use std::{fmt, fs};
 
 #[derive(Debug)]
@@ -10,14 +21,59 @@ pub enum Error {
     DwarfFormatError(gimli_wrapper::Error),
 }
 
+/// Debug information for an executable or shared library. Addresses in `File`s are link-time
+/// addresses, but the public methods take and return runtime addresses, translating with
+/// `load_bias`.
+///
+/// Big programs have far more debug info than we want to read at startup, so we only read the
+/// unit headers then, and index the units by name and address. Each unit's DIEs and lines are
+/// parsed the first time something asks about it.
 pub struct DwarfData {
-    files: Vec<File>,
-    addr2line: Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+    path: String,
+    sections: gimli_wrapper::Sections,
+    /// Where to find the split units of code built with -gsplit-dwarf, if they were packaged
+    package: Option<split_dwarf::Package>,
+    /// The compile units, sorted by offset
+    units: Vec<gimli_wrapper::UnitSummary>,
+    /// Link-time address ranges of the units' code, as (start, end, index into `units`), sorted
+    /// by start
+    unit_ranges: Vec<(usize, usize, usize)>,
+    /// Units that don't say what addresses they cover, which we have to look inside instead
+    unranged_units: Vec<usize>,
+    /// Indexes into `units`, by the file name (without directories) of the unit's source file and
+    /// of the other files in its line table
+    units_by_file: HashMap<String, Vec<usize>>,
+    /// Which units define each function and global variable
+    names: NameIndex,
+    /// The units parsed so far, by index into `units`
+    parsed_units: RefCell<HashMap<usize, Rc<File>>>,
+    /// `None` if addr2line can't read the DWARF (it doesn't know DWARF 5 skeleton units), in
+    /// which case we only use our own tables
+    addr2line: Option<Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>>,
+    /// ELF symbols (from `.symtab` and `.dynsym`), sorted by address. Unlike DWARF, these are
+    /// almost always present, so they are what lets us name functions in system libraries.
+    symbols: Vec<Symbol>,
+    /// Link-time entry point, from the ELF header
+    entry: usize,
+    /// Dynamic linker requested in `.interp`, if the executable is dynamically linked
+    interpreter: Option<String>,
+    /// Link-time addresses spanned by the loadable segments
+    address_range: (usize, usize),
+    /// Link-time addresses spanned by `.text`
+    text_range: (usize, usize),
+    /// The template for each thread's copy of the thread-local variables, if there are any
+    tls_segment: Option<TlsSegment>,
+    /// Call frame information, used to unwind through code built without frame pointers
+    eh_frame: Vec<u8>,
+    eh_frame_address: u64,
+    endian: gimli::RunTimeEndian,
+    /// Difference between runtime and link-time addresses (non-zero for PIE executables)
+    load_bias: usize,
 }
 
 impl fmt::Debug for DwarfData {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "DwarfData {{files: {:?}}}", self.files)
+        write!(f, "DwarfData {{units: {:?}}}", self.units)
     }
 }
 
@@ -27,71 +83,637 @@ impl From<gimli_wrapper::Error> for Error {
     }
 }
 
+/// Parses an ELF file. Only 64-bit ones are accepted: the section reading in `debug_section` and
+/// everything that deals with registers and pointers assume x86-64.
+fn parse_object(data: &[u8]) -> Result<object::File<'_>, String> {
+    let object = object::File::parse(data).map_err(|e| e.to_string())?;
+    if !object.is_64() {
+        return Err("32-bit ELF files are not supported".to_string());
+    }
+    Ok(object)
+}
+
 impl DwarfData {
     pub fn from_file(path: &str) -> Result<DwarfData, Error> {
         let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
         let mmap = unsafe { memmap::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? };
-        let object = object::File::parse(&mmap)
-            .map_err(|e| gimli_wrapper::Error::ObjectError(e.to_string()))?;
+        let object = parse_object(&mmap).map_err(gimli_wrapper::Error::ObjectError)?;
         let endian = if object.is_little_endian() {
             gimli::RunTimeEndian::Little
         } else {
             gimli::RunTimeEndian::Big
         };
+        // If the DWARF was split out into a separate file, read the debug sections (and the full
+        // symbol table, which is usually stripped too) from there. Everything else comes from the
+        // binary itself.
+        let debug_path = debug_file::find_debug_file(&object, path);
+        let debug_mmap = debug_path.as_ref().and_then(|debug_path| {
+            let file = fs::File::open(debug_path).ok()?;
+            unsafe { memmap::Mmap::map(&file).ok() }
+        });
+        // A debug file we can't parse is no reason not to debug the binary with what it has
+        let debug_object =
+            debug_mmap
+                .as_ref()
+                .and_then(|debug_mmap| match parse_object(debug_mmap) {
+                    Ok(debug_object) => Some((debug_object, &debug_mmap[..])),
+                    Err(e) => {
+                        println!(
+                            "Warning: ignoring debug file {}: {e}",
+                            debug_path.as_ref().unwrap().display()
+                        );
+                        None
+                    }
+                });
+        let (dwarf_object, dwarf_data): (&object::File, &[u8]) = match &debug_object {
+            Some((debug_object, debug_data)) => (debug_object, debug_data),
+            None => (&object, &mmap),
+        };
+        let sections = gimli_wrapper::Sections::load(dwarf_data, endian);
+        let package = split_dwarf::Package::open(path);
+
+        let package_path = split_dwarf::Package::path(path);
+        let mut indexed_files = vec![Path::new(path)];
+        if debug_object.is_some() {
+            indexed_files.extend(debug_path.as_deref());
+        }
+        if package.is_some() {
+            indexed_files.push(&package_path);
+        }
+        let cache_key = index_cache::CacheKey::new(&object, &indexed_files);
+        let (units, names) = match cache_key.as_ref().and_then(index_cache::load) {
+            Some(cached) => cached,
+            None => {
+                let (units, names) =
+                    index_units(&sections, package.as_ref(), dwarf_object.is_little_endian())?;
+                if let Some(key) = &cache_key {
+                    index_cache::save(key, &units, &names);
+                }
+                (units, names)
+            }
+        };
+
+        let mut unit_ranges = Vec::new();
+        let mut unranged_units = Vec::new();
+        let mut units_by_file: HashMap<String, Vec<usize>> = HashMap::new();
+        for (index, unit) in units.iter().enumerate() {
+            if unit.ranges.is_empty() {
+                unranged_units.push(index);
+            }
+            for (start, end) in &unit.ranges {
+                unit_ranges.push((*start, *end, index));
+            }
+            for path in unit.name.iter().chain(&unit.files) {
+                if let Some(file_name) = Path::new(path).file_name() {
+                    let indexes = units_by_file
+                        .entry(file_name.to_string_lossy().into_owned())
+                        .or_default();
+                    if indexes.last() != Some(&index) {
+                        indexes.push(index);
+                    }
+                }
+            }
+        }
+        unit_ranges.sort_unstable();
+
+        let mut symbols = elf_symbols(&object);
+        if let Some((debug_object, _)) = &debug_object {
+            symbols.extend(elf_symbols(debug_object));
+        }
+        // Aliases share an address. Put names like `__libc_malloc` before `malloc`, because when
+        // looking up an address the last match wins and the public name is the one people know.
+        symbols.sort_by(|a, b| {
+            (a.address, !a.name.starts_with('_'), &a.name).cmp(&(
+                b.address,
+                !b.name.starts_with('_'),
+                &b.name,
+            ))
+        });
+        symbols.dedup_by(|a, b| a.address == b.address && a.name == b.name);
+        let address_range = object.segments().filter(|segment| segment.size() > 0).fold(
+            (usize::MAX, 0),
+            |(start, end), segment| {
+                (
+                    start.min(segment.address() as usize),
+                    end.max((segment.address() + segment.size()) as usize),
+                )
+            },
+        );
+        let text_range = object.section_by_name(".text").map_or((0, 0), |text| {
+            (
+                text.address() as usize,
+                (text.address() + text.size()) as usize,
+            )
+        });
         Ok(DwarfData {
-            files: gimli_wrapper::load_file(&object, endian)?,
-            addr2line: Context::new(&object).map_err(gimli_wrapper::Error::from)?,
+            path: path.to_string(),
+            addr2line: addr2line_context(&sections).ok(),
+            sections,
+            package,
+            units,
+            unit_ranges,
+            unranged_units,
+            units_by_file,
+            names,
+            parsed_units: RefCell::new(HashMap::new()),
+            symbols,
+            entry: object.entry() as usize,
+            interpreter: object.section_data_by_name(".interp").map(|interp| {
+                String::from_utf8_lossy(&interp)
+                    .trim_end_matches('\0')
+                    .to_string()
+            }),
+            address_range,
+            text_range,
+            tls_segment: tls::tls_segment(&mmap),
+            eh_frame: object
+                .section_data_by_name(".eh_frame")
+                .map_or(vec![], |data| data.to_vec()),
+            eh_frame_address: object
+                .section_by_name(".eh_frame")
+                .map_or(0, |section| section.address()),
+            endian,
+            load_bias: 0,
         })
     }
 
-    #[allow(dead_code)]
-    fn get_target_file(&self, file: &str) -> Option<&File> {
-        self.files.iter().find(|f| {
-            f.name == file || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
+    /// Returns the path this was loaded from.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the PT_TLS segment, if the object has thread-local variables.
+    pub fn tls_segment(&self) -> Option<&TlsSegment> {
+        self.tls_segment.as_ref()
+    }
+
+    /// Returns `true` if there is any DWARF (as opposed to only ELF symbols).
+    pub fn has_debug_info(&self) -> bool {
+        !self.units.is_empty()
+    }
+
+    /// Returns the dynamic linker the executable asks for, e.g. "/lib64/ld-linux-x86-64.so.2".
+    pub fn interpreter(&self) -> Option<&str> {
+        self.interpreter.as_deref()
+    }
+
+    /// Returns `true` if `addr` is inside one of this object's loaded segments.
+    pub fn contains(&self, addr: usize) -> bool {
+        match addr.checked_sub(self.load_bias) {
+            Some(addr) => self.address_range.0 <= addr && addr < self.address_range.1,
+            None => false,
+        }
+    }
+
+    /// Returns the runtime start and end addresses of `.text`.
+    pub fn text_range(&self) -> (usize, usize) {
+        (
+            self.text_range.0 + self.load_bias,
+            self.text_range.1 + self.load_bias,
+        )
+    }
+
+    /// Returns the runtime address of the ELF symbol called `name`.
+    pub fn get_symbol_address(&self, name: &str) -> Option<usize> {
+        self.symbols
+            .iter()
+            .find(|sym| sym.name == name)
+            .map(|sym| sym.address + self.load_bias)
+    }
+
+    /// Returns the runtime address of the function called `name`, according to the ELF symbols.
+    /// This finds functions we have no DWARF for, like the ones in libc.
+    pub fn get_function_symbol_address(&self, name: &str) -> Option<usize> {
+        self.symbols
+            .iter()
+            .find(|sym| sym.is_function && sym.name == name)
+            .map(|sym| sym.address + self.load_bias)
+    }
+
+    /// Returns the runtime address of the runtime support function called `name` (such as
+    /// `rust_panic`), according to the ELF symbols. Newer versions of Rust mangle these into a
+    /// namespace of their own, so `name` can also be the last part of the demangled name.
+    pub fn get_runtime_function_address(&self, name: &str) -> Option<usize> {
+        self.symbols
+            .iter()
+            // Mangled names contain the identifiers they're made of
+            .filter(|sym| sym.is_function && sym.name.contains(name))
+            .find(|sym| {
+                sym.name == name || {
+                    let demangled = demangle(&sym.name);
+                    demangled.len() > name.len()
+                        && demangled.ends_with(name)
+                        && demangled[..demangled.len() - name.len()].ends_with("::")
+                }
+            })
+            .map(|sym| sym.address + self.load_bias)
+    }
+
+    /// Returns the name of the symbol (function or variable) covering `addr`, and how far into it
+    /// `addr` is.
+    pub fn get_symbol_for_addr(&self, addr: usize) -> Option<(&str, usize)> {
+        let sym = self.get_symbol_containing(addr, false)?;
+        Some((&sym.name, addr - self.load_bias - sym.address))
+    }
+
+    /// Returns the symbol covering `addr`. Function symbols without a size (common for
+    /// hand-written assembly) are taken to extend up to the next function.
+    fn get_symbol_containing(&self, addr: usize, functions_only: bool) -> Option<&Symbol> {
+        let addr = addr.checked_sub(self.load_bias)?;
+        let mut last_function = None;
+        let mut containing = None;
+        for sym in self
+            .symbols
+            .iter()
+            .filter(|sym| sym.is_function || !functions_only)
+            .take_while(|sym| sym.address <= addr)
+        {
+            if sym.is_function {
+                last_function = Some(sym);
+            }
+            if addr < sym.address + sym.size {
+                containing = Some(sym);
+            }
+        }
+        containing.or_else(|| last_function.filter(|sym| sym.size == 0 && addr < self.text_range.1))
+    }
+
+    /// Works out the registers of the caller of the frame described by `frame`, using the call
+    /// frame information in `.eh_frame`. `pc` is the address to look up, which for all but the
+    /// innermost frame should be inside the call instruction rather than the return address.
+    /// Returns `None` if there is no CFI covering `pc`, and a frame with `rip` 0 if the CFI says
+    /// this is the outermost frame.
+    pub fn unwind_frame(
+        &self,
+        pc: usize,
+        frame: &FrameRegisters,
+        read_word: &dyn Fn(usize) -> Option<usize>,
+    ) -> Option<FrameRegisters> {
+        use gimli::UnwindSection;
+        let eh_frame = gimli::EhFrame::new(&self.eh_frame, self.endian);
+        let bases = gimli::BaseAddresses::default()
+            .set_eh_frame(self.eh_frame_address)
+            .set_text(self.text_range.0 as u64);
+        let mut ctx = gimli::UninitializedUnwindContext::new();
+        let row = eh_frame
+            .unwind_info_for_address(
+                &bases,
+                &mut ctx,
+                pc.checked_sub(self.load_bias)? as u64,
+                gimli::EhFrame::cie_from_offset,
+            )
+            .ok()?;
+        let register = |reg: gimli::Register| match reg {
+            gimli::X86_64::RSP => Some(frame.rsp),
+            gimli::X86_64::RBP => Some(frame.rbp),
+            _ => None,
+        };
+        let cfa = match row.cfa() {
+            gimli::CfaRule::RegisterAndOffset {
+                register: reg,
+                offset,
+            } => (register(*reg)? as i64).checked_add(*offset)? as usize,
+            _ => return None,
+        };
+        // Registers the CFI says nothing about come back as `Undefined`. For callee-saved
+        // registers that means the function hasn't touched them (yet); for the return address it
+        // means there is no caller.
+        let recover = |reg: gimli::Register, current: usize| match row.register(reg) {
+            gimli::RegisterRule::Undefined | gimli::RegisterRule::SameValue => Some(current),
+            gimli::RegisterRule::Offset(offset) => {
+                read_word((cfa as i64).checked_add(offset)? as usize)
+            }
+            gimli::RegisterRule::ValOffset(offset) => {
+                Some((cfa as i64).checked_add(offset)? as usize)
+            }
+            _ => None,
+        };
+        let mut callee_saved = [None; 5];
+        for (saved, (reg, current)) in callee_saved
+            .iter_mut()
+            .zip(CALLEE_SAVED.iter().zip(frame.callee_saved))
+        {
+            *saved = current.and_then(|current| recover(*reg, current));
+        }
+        Some(FrameRegisters {
+            rip: recover(gimli::X86_64::RA, 0)?,
+            rsp: cfa,
+            rbp: recover(gimli::X86_64::RBP, frame.rbp)?,
+            callee_saved,
         })
     }
 
-    #[allow(dead_code)]
-    pub fn get_addr_for_line(&self, file: Option<&str>, line_number: usize) -> Option<usize> {
-        let target_file = match file {
-            Some(filename) => self.get_target_file(filename)?,
-            None => self.files.get(0)?,
+    /// Returns the link-time address of the entry point.
+    pub fn entry(&self) -> usize {
+        self.entry
+    }
+
+    pub fn load_bias(&self) -> usize {
+        self.load_bias
+    }
+
+    /// Tells us where the executable was actually loaded. See `Target::load_bias`.
+    pub fn set_load_bias(&mut self, load_bias: usize) {
+        self.load_bias = load_bias;
+    }
+
+    /// Returns the unit at `index` in `units`, parsing it if this is the first time it's needed.
+    fn unit(&self, index: usize) -> Option<Rc<File>> {
+        if let Some(file) = self.parsed_units.borrow().get(&index) {
+            return Some(file.clone());
+        }
+        let offset = self.units.get(index)?.offset;
+        let file =
+            Rc::new(gimli_wrapper::load_unit(&self.sections, self.package.as_ref(), offset).ok()?);
+        self.parsed_units.borrow_mut().insert(index, file.clone());
+        Some(file)
+    }
+
+    /// Returns the indexes of the units at `offsets` in `.debug_info`.
+    fn unit_indexes(&self, offsets: &[usize]) -> Vec<usize> {
+        offsets
+            .iter()
+            .filter_map(|offset| {
+                self.units
+                    .binary_search_by_key(offset, |unit| unit.offset)
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Returns the index of the unit for the source file `file`, which is the path the unit was
+    /// compiled from or the end of it (e.g. just the file name).
+    fn get_target_file(&self, file: &str) -> Option<usize> {
+        let file_name = Path::new(file).file_name()?.to_string_lossy();
+        self.units_by_file
+            .get(file_name.as_ref())?
+            .iter()
+            .copied()
+            .find(|index| {
+                let name = self.units[*index].name.as_deref().unwrap_or_default();
+                Path::new(name).ends_with(file)
+            })
+    }
+
+    /// Returns the unit a line number without a file name refers to, which (as in gdb) is the one
+    /// defining `main`, or failing that the one containing the entry point.
+    fn default_unit(&self) -> Option<Rc<File>> {
+        let main = self.get_addrs_for_function(None, "main").into_iter().next();
+        main.into_iter()
+            .chain(std::iter::once(self.entry + self.load_bias))
+            .find_map(|addr| self.get_unit_and_function(addr))
+            .map(|(file, _)| file)
+    }
+
+    /// Returns the indexes of the units with code from the source file `file` (see
+    /// `get_target_file`): the unit compiled from it, and any with rows for it in their line
+    /// table, as when it's a header.
+    fn units_with_lines_for(&self, file: &str) -> Vec<usize> {
+        let file_name = match Path::new(file).file_name() {
+            Some(file_name) => file_name.to_string_lossy(),
+            None => return Vec::new(),
         };
-        Some(
-            target_file
-                .lines
-                .iter()
-                .find(|line| line.number >= line_number)?
-                .address,
-        )
+        self.units_by_file
+            .get(file_name.as_ref())
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|index| {
+                let unit = &self.units[*index];
+                unit.name
+                    .iter()
+                    .chain(&unit.files)
+                    .any(|path| Path::new(path).ends_with(file))
+            })
+            .collect()
     }
 
+    /// Returns the runtime address of the code for line `line_number` of `file` (or of the
+    /// default unit's file), or for the next line after it that has code.
     #[allow(dead_code)]
-    pub fn get_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
-        match file {
-            Some(filename) => Some(
-                self.get_target_file(filename)?
-                    .functions
-                    .iter()
-                    .find(|func| func.name == func_name)?
-                    .address,
+    pub fn get_addr_for_line(&self, file: Option<&str>, line_number: usize) -> Option<usize> {
+        let (units, file) = match file {
+            Some(file) => (
+                self.units_with_lines_for(file)
+                    .into_iter()
+                    .filter_map(|index| self.unit(index))
+                    .collect(),
+                file.to_string(),
             ),
             None => {
-                for file in &self.files {
-                    if let Some(func) = file.functions.iter().find(|func| func.name == func_name) {
-                        return Some(func.address);
-                    }
-                }
-                None
+                let unit = self.default_unit()?;
+                let file = unit.name.clone();
+                (vec![unit], file)
             }
+        };
+        units
+            .iter()
+            .flat_map(|unit| unit.lines.iter())
+            .filter(|line| line.number >= line_number && Path::new(&line.file).ends_with(&file))
+            .min_by_key(|line| (line.number, line.address))
+            .map(|line| line.address + self.load_bias)
+    }
+
+    /// Returns the runtime addresses of the functions `query` names (see `Function::matches`), in
+    /// `file` if given. There can be several: C++ overloads, or methods of the same name in
+    /// different classes.
+    pub fn get_addrs_for_function(&self, file: Option<&str>, query: &str) -> Vec<usize> {
+        let mut addrs: Vec<usize> = self
+            .units_for_function(file, query)
+            .into_iter()
+            .filter_map(|index| self.unit(index))
+            .flat_map(|file| {
+                file.functions
+                    .iter()
+                    // Declarations have no code
+                    .filter(|func| func.text_length > 0 && func.matches(query))
+                    .map(|func| func.address)
+                    .collect::<Vec<usize>>()
+            })
+            .map(|addr| addr + self.load_bias)
+            .collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+        addrs
+    }
+
+    /// Returns the runtime addresses of the places the functions `query` names were inlined, in
+    /// `file` if given.
+    pub fn get_inlined_addrs_for_function(&self, file: Option<&str>, query: &str) -> Vec<usize> {
+        let mut addrs: Vec<usize> = self
+            .units_for_function(file, query)
+            .into_iter()
+            .filter_map(|index| self.unit(index))
+            .flat_map(|file| {
+                file.functions
+                    .iter()
+                    .flat_map(|func| &func.inlined_calls)
+                    .filter(|call| name_matches(&call.name, query))
+                    .filter_map(|call| call.entry())
+                    .collect::<Vec<usize>>()
+            })
+            .map(|addr| addr + self.load_bias)
+            .collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+        addrs
+    }
+
+    /// Returns the indexes of the units that may define the functions `query` names: the one for
+    /// `file` if given, and otherwise the ones the name index has for its base name.
+    fn units_for_function(&self, file: Option<&str>, query: &str) -> Vec<usize> {
+        match file {
+            Some(filename) => self.get_target_file(filename).into_iter().collect(),
+            None => match self.names.functions.get(base_name(query)) {
+                Some(offsets) => self.unit_indexes(offsets),
+                None => Vec::new(),
+            },
         }
     }
 
-    #[allow(dead_code)]
+    /// Returns the address just past the prologue of the function at `func_addr`: the second row
+    /// of the line table within the function, which is where gcc starts the function body. Until
+    /// then, the frame isn't set up, so backtraces and local variables would be wrong.
+    pub fn skip_prologue(&self, func_addr: usize) -> usize {
+        let (file, func) = match self.get_unit_and_function(func_addr) {
+            Some(found) => found,
+            None => return func_addr,
+        };
+        let func = &file.functions[func];
+        let start = func.address;
+        let end = func.address + func.text_length;
+        file.lines
+            .iter()
+            .map(|line| line.address)
+            .filter(|addr| start < *addr && *addr < end)
+            .min()
+            .map_or(func_addr, |addr| addr + self.load_bias)
+    }
+
+    /// Returns the runtime addresses after `addr` in the innermost function containing it (the
+    /// inlined copy it's in, if any) where the code for a line other than `addr`'s starts. Code
+    /// inlined into that function is skipped, as a call would be. For an inlined copy, the
+    /// addresses just past it, where its caller carries on, are included too. Running until one
+    /// of these is how `until` gets past the current line without going round a loop again.
+    pub fn get_later_line_addrs(&self, addr: usize) -> Vec<usize> {
+        let (file, func) = match self.get_unit_and_function(addr) {
+            Some(found) => found,
+            None => return Vec::new(),
+        };
+        let func = &file.functions[func];
+        let link_addr = addr - self.load_bias;
+        let inlined = innermost_inlined_call(func, link_addr);
+        let whole_function = [(func.address, func.address + func.text_length)];
+        let (ranges, depth) = match inlined {
+            Some(call) => (&call.ranges[..], call.depth),
+            None => (&whole_function[..], 0),
+        };
+        let in_frame = |addr: usize| {
+            ranges
+                .iter()
+                .any(|(start, end)| *start <= addr && addr < *end)
+        };
+        let nested: Vec<&InlinedCall> = func
+            .inlined_calls
+            .iter()
+            .filter(|call| call.depth > depth)
+            .filter(|call| call.ranges.iter().any(|range| in_frame(range.0)))
+            .collect();
+        let current = file
+            .lines
+            .iter()
+            .filter(|line| line.address <= link_addr)
+            .max_by_key(|line| line.address)
+            .map(|line| (&line.file, line.number));
+        // Several rows can share an address (the lines of an inlined call site, say); the last
+        // one is the line a stop there is reported at
+        let mut reported = BTreeMap::new();
+        for line in file.lines.iter() {
+            reported.insert(line.address, (&line.file, line.number));
+        }
+        let mut addrs: Vec<usize> = reported
+            .into_iter()
+            .filter(|(address, _)| link_addr < *address && in_frame(*address))
+            .filter(|(address, _)| !nested.iter().any(|call| call.contains(*address)))
+            .filter(|(_, line)| Some(*line) != current)
+            .map(|(address, _)| address + self.load_bias)
+            .collect();
+        addrs.extend(self.get_inlined_exit_addrs(addr));
+        addrs
+    }
+
+    /// If `addr` is in code that was inlined, returns the runtime addresses just past the
+    /// innermost inlined copy containing it, which is where leaving the inlined function gets
+    /// to. Otherwise returns nothing: leaving a real function is a return.
+    pub fn get_inlined_exit_addrs(&self, addr: usize) -> Vec<usize> {
+        let (file, func) = match self.get_unit_and_function(addr) {
+            Some(found) => found,
+            None => return Vec::new(),
+        };
+        let call = match innermost_inlined_call(&file.functions[func], addr - self.load_bias) {
+            Some(call) => call,
+            None => return Vec::new(),
+        };
+        call.ranges
+            .iter()
+            .map(|(_, end)| *end)
+            .filter(|end| !call.contains(*end))
+            .map(|end| end + self.load_bias)
+            .collect()
+    }
+
+    /// Returns the function whose code contains `addr`.
+    pub fn get_function_containing(&self, addr: usize) -> Option<Function> {
+        let (file, index) = self.get_unit_and_function(addr)?;
+        Some(file.functions[index].clone())
+    }
+
+    /// Returns the unit containing the function whose code contains `addr`, and the function's
+    /// index in it.
+    fn get_unit_and_function(&self, addr: usize) -> Option<(Rc<File>, usize)> {
+        let addr = addr.checked_sub(self.load_bias)?;
+        // Unit ranges don't overlap, so the only candidate is the last one starting at or before
+        // `addr`
+        let ranged = match self.unit_ranges.partition_point(|range| range.0 <= addr) {
+            0 => None,
+            i => Some(self.unit_ranges[i - 1]).filter(|range| addr < range.1),
+        };
+        ranged
+            .map(|range| range.2)
+            .into_iter()
+            .chain(self.unranged_units.iter().copied())
+            .find_map(|index| {
+                let file = self.unit(index)?;
+                let func = file.functions.iter().position(|func| {
+                    func.address <= addr && addr < func.address + func.text_length
+                })?;
+                Some((file, func))
+            })
+    }
+
+    /// Returns the global variable called `name`, if any compilation unit defines one.
+    pub fn get_global_variable(&self, name: &str) -> Option<Variable> {
+        self.unit_indexes(self.names.variables.get(base_name(name))?)
+            .into_iter()
+            .find_map(|index| {
+                self.unit(index)?
+                    .global_variables
+                    .iter()
+                    .find(|var| name_matches(&var.name, name))
+                    .cloned()
+            })
+    }
+
     pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
+        self.get_dwarf_line_from_addr(curr_addr)
+            .or_else(|| self.get_line_from_files(curr_addr))
+    }
+
+    fn get_dwarf_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
         let location = self
             .addr2line
-            .find_location(curr_addr.try_into().unwrap())
+            .as_ref()?
+            .find_location(curr_addr.checked_sub(self.load_bias)?.try_into().unwrap())
             .ok()??;
         Some(Line {
             file: location.file?.to_string(),
@@ -100,51 +722,286 @@ impl DwarfData {
         })
     }
 
-    #[allow(dead_code)]
+    /// Looks `curr_addr` up in our own line tables, for when addr2line can't (it doesn't look in
+    /// split DWARF): the last row at or before it in the same function.
+    fn get_line_from_files(&self, curr_addr: usize) -> Option<Line> {
+        let (file, func) = self.get_unit_and_function(curr_addr)?;
+        let func = &file.functions[func];
+        let addr = curr_addr - self.load_bias;
+        let line = file
+            .lines
+            .iter()
+            .filter(|line| func.address <= line.address && line.address <= addr)
+            .max_by_key(|line| line.address)?;
+        Some(Line {
+            file: line.file.clone(),
+            number: line.number,
+            address: curr_addr,
+        })
+    }
+
+    /// Returns the name of the function containing `curr_addr`, from DWARF if we have it and
+    /// otherwise from the ELF symbols. If the code there was inlined, that's the inlined function.
     pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
-        let frame = self
-            .addr2line
-            .find_frames(curr_addr.try_into().unwrap())
-            .ok()?
+        self.get_frames_from_addr(curr_addr)
+            .into_iter()
             .next()
-            .ok()??;
-        Some(frame.function?.raw_name().ok()?.to_string())
+            .map(|(name, _)| name)
     }
 
-    #[allow(dead_code)]
-    pub fn print(&self) {
-        for file in &self.files {
-            println!("------");
-            println!("{}", file.name);
-            println!("------");
-
-            println!("Global variables:");
-            for var in &file.global_variables {
-                println!(
-                    "  * {} ({}, located at {}, declared at line {})",
-                    var.name, var.entity_type.name, var.location, var.line_number
-                );
-            }
-
-            println!("Functions:");
-            for func in &file.functions {
-                println!(
-                    "  * {} (declared on line {}, located at {:#x}, {} bytes long)",
-                    func.name, func.line_number, func.address, func.text_length
-                );
-                for var in &func.variables {
-                    println!(
-                        "    * Variable: {} ({}, located at {}, declared at line {})",
-                        var.name, var.entity_type.name, var.location, var.line_number
-                    );
-                }
+    /// Returns the functions whose code is running at `curr_addr`, innermost first, with the line
+    /// each one is at: the function containing the address, preceded by the functions that were
+    /// inlined into it there, if any. Only the last of these has a stack frame of its own.
+    pub fn get_frames_from_addr(&self, curr_addr: usize) -> Vec<(String, Option<Line>)> {
+        let frames = self.get_dwarf_frames_from_addr(curr_addr);
+        if !frames.is_empty() {
+            return frames;
+        }
+        // addr2line doesn't look in split DWARF (.dwo files), but we do
+        if let Some(func) = self.get_function_containing(curr_addr) {
+            let addr = curr_addr - self.load_bias;
+            let mut calls: Vec<&InlinedCall> = func
+                .inlined_calls
+                .iter()
+                .filter(|call| call.contains(addr))
+                .collect();
+            calls.sort_by_key(|call| std::cmp::Reverse(call.depth));
+            let mut frames = Vec::new();
+            let mut line = self.get_line_from_addr(curr_addr);
+            for call in calls {
+                frames.push((call.name.clone(), line));
+                // The caller is at the line of the call
+                line = call.call_file.as_ref().map(|file| Line {
+                    file: file.clone(),
+                    number: call.call_line,
+                    address: curr_addr,
+                });
             }
+            frames.push((func.name.clone(), line));
+            return frames;
+        }
+        match self.get_symbol_containing(curr_addr, true) {
+            Some(sym) => vec![(demangle(&sym.name), None)],
+            None => Vec::new(),
+        }
+    }
+
+    fn get_dwarf_frames_from_addr(&self, curr_addr: usize) -> Vec<(String, Option<Line>)> {
+        let mut frames = Vec::new();
+        let mut iter = match self.addr2line.as_ref().and_then(|context| {
+            context
+                .find_frames(curr_addr.checked_sub(self.load_bias)? as u64)
+                .ok()
+        }) {
+            Some(iter) => iter,
+            None => return frames,
+        };
+        while let Ok(Some(frame)) = iter.next() {
+            let name = match frame.function.as_ref().and_then(|f| f.raw_name().ok()) {
+                Some(name) => demangle(&name),
+                None => break,
+            };
+            let line = frame.location.and_then(|location| {
+                Some(Line {
+                    file: location.file?.to_string(),
+                    number: location.line?.try_into().unwrap(),
+                    address: curr_addr,
+                })
+            });
+            frames.push((name, line));
+        }
+        frames
+    }
 
-            println!("Line numbers:");
-            for line in &file.lines {
-                println!("  * {} (at {:#x})", line.number, line.address);
+    /// Returns the functions with code, the global variables or the named types whose names
+    /// match `regex`, across all of the units, sorted by file and name. Functions and variables
+    /// also come from the ELF symbols, for those without debug info.
+    pub fn search(&self, kind: SearchKind, regex: &Regex) -> Vec<SearchResult> {
+        let mut results = Vec::new();
+        // Unlike everything else, this needs every unit parsed
+        for file in (0..self.units.len()).filter_map(|index| self.unit(index)) {
+            let found = |line_number, name: String, declaration| SearchResult {
+                file: Some(file.name.clone()),
+                line_number,
+                name,
+                declaration,
+                address: None,
+            };
+            match kind {
+                SearchKind::Functions => {
+                    for func in file.functions.iter().filter(|func| func.text_length > 0) {
+                        let name = match &func.linkage_name {
+                            Some(linkage_name) => demangle(linkage_name),
+                            None => func.name.clone(),
+                        };
+                        if regex.is_match(&name) {
+                            results.push(found(func.line_number, name.clone(), name));
+                        }
+                    }
+                }
+                SearchKind::Variables => {
+                    for var in &file.global_variables {
+                        if regex.is_match(&var.name) {
+                            let declaration = format!("{} {}", var.entity_type.name, var.name);
+                            results.push(found(var.line_number, var.name.clone(), declaration));
+                        }
+                    }
+                }
+                SearchKind::Types => {
+                    for entity_type in file.types.types.values() {
+                        // Pointers and arrays are named after what they're made of, and const
+                        // and volatile types after the type they qualify
+                        let derived = match entity_type.kind {
+                            TypeKind::Pointer(_) | TypeKind::Array { .. } => true,
+                            TypeKind::Alias(target) => {
+                                file.types.types.get(&target).map(|target| &target.name)
+                                    == Some(&entity_type.name)
+                            }
+                            _ => entity_type.name == "<anonymous>",
+                        };
+                        if !derived && regex.is_match(&entity_type.name) {
+                            let name = entity_type.name.clone();
+                            results.push(found(0, name.clone(), name));
+                        }
+                    }
+                }
+            }
+        }
+        if kind != SearchKind::Types {
+            let described: HashSet<String> =
+                results.iter().map(|result| result.name.clone()).collect();
+            for sym in &self.symbols {
+                if sym.is_function != (kind == SearchKind::Functions) {
+                    continue;
+                }
+                let name = demangle(&sym.name);
+                if regex.is_match(&name) && !described.contains(&name) {
+                    results.push(SearchResult {
+                        file: None,
+                        line_number: 0,
+                        name: name.clone(),
+                        declaration: name,
+                        address: Some(sym.address + self.load_bias),
+                    });
+                }
             }
         }
+        results.sort_by(|a, b| (&a.file, &a.name, a.address).cmp(&(&b.file, &b.name, b.address)));
+        results.dedup_by(|a, b| a.file == b.file && a.name == b.name && a.address == b.address);
+        results
+    }
+}
+
+/// Reads the unit headers and finds out which units define each function and global variable.
+/// We use the linker's or compiler's index of names if there is one, and otherwise (or for units
+/// it doesn't cover) look through the units ourselves.
+fn index_units(
+    sections: &gimli_wrapper::Sections,
+    package: Option<&split_dwarf::Package>,
+    little_endian: bool,
+) -> Result<(Vec<gimli_wrapper::UnitSummary>, NameIndex), Error> {
+    let units = gimli_wrapper::summarize_units(&sections.dwarf()?)?;
+    let mut names = name_index::from_debug_names(
+        sections.data(".debug_names"),
+        sections.data(".debug_str"),
+        little_endian,
+    )
+    .or_else(|| name_index::from_gdb_index(sections.data(".gdb_index")))
+    .unwrap_or_default();
+    let unindexed: Vec<usize> = units
+        .iter()
+        .map(|unit| unit.offset)
+        .filter(|offset| !names.units.contains(offset))
+        .collect();
+    gimli_wrapper::index_names(sections, package, &unindexed, &mut names)?;
+    Ok((units, names))
+}
+
+/// Sets up addr2line on the DWARF we've loaded. We hand it the sections ourselves (rather than
+/// using `Context::new`) so that compressed ones get decompressed, and so that it shares them
+/// with us.
+fn addr2line_context(
+    sections: &gimli_wrapper::Sections,
+) -> Result<
+    Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+    addr2line::gimli::Error,
+> {
+    let endian = match sections.endian() {
+        gimli::RunTimeEndian::Little => addr2line::gimli::RunTimeEndian::Little,
+        gimli::RunTimeEndian::Big => addr2line::gimli::RunTimeEndian::Big,
+    };
+    let section = |name: &str| addr2line::gimli::EndianRcSlice::new(sections.shared(name), endian);
+    Context::from_sections(
+        section(".debug_abbrev").into(),
+        section(".debug_addr").into(),
+        section(".debug_info").into(),
+        section(".debug_line").into(),
+        section(".debug_line_str").into(),
+        section(".debug_ranges").into(),
+        section(".debug_rnglists").into(),
+        section(".debug_str").into(),
+        section(".debug_str_offsets").into(),
+        section(""),
+    )
+}
+
+/// Reads the functions and variables from the `.symtab` and `.dynsym` of `object`.
+fn elf_symbols(object: &object::File) -> Vec<Symbol> {
+    object
+        .symbols()
+        .chain(object.dynamic_symbols())
+        .filter(|(_, sym)| !sym.is_undefined() && sym.address() != 0)
+        .filter(|(_, sym)| sym.kind() == SymbolKind::Text || sym.kind() == SymbolKind::Data)
+        .filter_map(|(_, sym)| {
+            Some(Symbol {
+                name: sym.name().filter(|name| !name.is_empty())?.to_string(),
+                address: sym.address() as usize,
+                size: sym.size() as usize,
+                is_function: sym.kind() == SymbolKind::Text,
+            })
+        })
+        .collect()
+}
+
+/// An ELF symbol table entry.
+#[derive(Debug, Clone)]
+struct Symbol {
+    name: String,
+    address: usize,
+    size: usize,
+    is_function: bool,
+}
+
+/// The registers we need to walk the stack from one frame to its caller.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameRegisters {
+    pub rip: usize,
+    pub rsp: usize,
+    pub rbp: usize,
+    /// The other registers a callee has to preserve (see `CALLEE_SAVED`), if we know them. In
+    /// callers, location expressions often refer to these.
+    pub callee_saved: [Option<usize>; 5],
+}
+
+/// rbx and r12-r15, in the order of `FrameRegisters::callee_saved`
+pub const CALLEE_SAVED: [gimli::Register; 5] = [
+    gimli::X86_64::RBX,
+    gimli::X86_64::R12,
+    gimli::X86_64::R13,
+    gimli::X86_64::R14,
+    gimli::X86_64::R15,
+];
+
+impl FrameRegisters {
+    /// Returns the registers of the innermost frame.
+    pub fn from_registers(regs: &libc::user_regs_struct) -> FrameRegisters {
+        FrameRegisters {
+            rip: regs.rip as usize,
+            rsp: regs.rsp as usize,
+            rbp: regs.rbp as usize,
+            callee_saved: [regs.rbx, regs.r12, regs.r13, regs.r14, regs.r15]
+                .map(|value| Some(value as usize)),
+        }
     }
 }
 
@@ -152,13 +1009,146 @@ impl DwarfData {
 pub struct Type {
     pub name: String,
     pub size: usize,
+    pub kind: TypeKind,
+}
+
+/// What a type is made of. Other types are referred to by the offset of their DIE in the unit,
+/// to be looked up in the unit's `Types`.
+#[derive(Debug, Clone, Default)]
+pub enum TypeKind {
+    /// An integer, floating point number, boolean or character, with its `DW_ATE_*` encoding
+    Base(u8),
+    /// A pointer or reference to a type, or to nothing in particular (`void *`)
+    Pointer(Option<usize>),
+    /// A struct, class or union
+    Struct(Struct),
+    /// An array of the element type, with the number of elements in each dimension (0 if
+    /// unknown)
+    Array { element: usize, counts: Vec<usize> },
+    /// A C-style enum's enumerators and their values
+    Enum(Vec<(String, i64)>),
+    /// A typedef, or a const or volatile version of another type
+    Alias(usize),
+    #[default]
+    Unknown,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Struct {
+    pub members: Vec<Member>,
+    /// The type parameters of a generic type, e.g. `T` for a Rust `Vec<T>`
+    pub template_params: Vec<(String, usize)>,
+    /// Set for Rust enums, which are structs holding one of several variants
+    pub variants: Option<VariantPart>,
 }
 
-impl Type {
-    pub fn new(name: String, size: usize) -> Self {
-        Type {
-            name: name,
-            size: size,
+#[derive(Debug, Clone, Default)]
+pub struct Member {
+    pub name: String,
+    pub type_offset: usize,
+    /// Offset from the start of the struct
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VariantPart {
+    /// The member saying which variant the value holds
+    pub discriminant: Option<Member>,
+    /// Each variant's discriminant value (`None` for the variant used for any other value) and
+    /// the member holding its fields
+    pub variants: Vec<(Option<u64>, Member)>,
+}
+
+/// The types defined in a unit, by DIE offset.
+#[derive(Debug, Default)]
+pub struct Types {
+    /// Whether the unit is Rust code, whose values we show the way Rust does
+    pub rust: bool,
+    pub types: HashMap<usize, Type>,
+}
+
+impl Types {
+    /// Returns the type at `offset`, looking through typedefs and qualifiers.
+    pub fn get(&self, offset: usize) -> Option<&Type> {
+        self.resolve(self.types.get(&offset)?)
+    }
+
+    /// Returns the type `entity_type` is an alias of, if it is one, or otherwise itself.
+    pub fn resolve<'a>(&'a self, mut entity_type: &'a Type) -> Option<&'a Type> {
+        // Bounded, in case of broken debug info with a cycle of typedefs
+        for _ in 0..16 {
+            match entity_type.kind {
+                TypeKind::Alias(target) => entity_type = self.types.get(&target)?,
+                _ => return Some(entity_type),
+            }
+        }
+        None
+    }
+
+    /// Fills in the sizes of typedefs and arrays, and the names of types C doesn't name (such
+    /// as `int *`), which can only be done once all of the unit's types have been read.
+    pub fn finish(&mut self) {
+        let offsets: Vec<usize> = self.types.keys().copied().collect();
+        for offset in offsets {
+            let name = self.name_of(offset, 0);
+            let size = self.size_of(offset, 0);
+            if let Some(entity_type) = self.types.get_mut(&offset) {
+                entity_type.name = name;
+                entity_type.size = size;
+            }
+        }
+    }
+
+    fn name_of(&self, offset: usize, depth: usize) -> String {
+        let entity_type = match self.types.get(&offset) {
+            Some(entity_type) if depth < 16 => entity_type,
+            _ => return "void".to_string(),
+        };
+        if !entity_type.name.is_empty() {
+            return entity_type.name.clone();
+        }
+        match entity_type.kind {
+            TypeKind::Pointer(Some(target)) => format!("{} *", self.name_of(target, depth + 1)),
+            TypeKind::Pointer(None) => "void *".to_string(),
+            TypeKind::Array {
+                element,
+                ref counts,
+            } => {
+                let mut name = format!("{} ", self.name_of(element, depth + 1));
+                for count in counts {
+                    match count {
+                        0 => name.push_str("[]"),
+                        count => name.push_str(&format!("[{}]", count)),
+                    }
+                }
+                name
+            }
+            TypeKind::Alias(target) => self.name_of(target, depth + 1),
+            _ => "<anonymous>".to_string(),
+        }
+    }
+
+    fn size_of(&self, offset: usize, depth: usize) -> usize {
+        let entity_type = match self.types.get(&offset) {
+            Some(entity_type) if depth < 16 => entity_type,
+            _ => return 0,
+        };
+        if entity_type.size != 0 {
+            return entity_type.size;
+        }
+        match entity_type.kind {
+            TypeKind::Pointer(_) => 8,
+            TypeKind::Array {
+                element,
+                ref counts,
+            } => counts
+                .iter()
+                .try_fold(self.size_of(element, depth + 1), |size, count| {
+                    size.checked_mul(*count)
+                })
+                .unwrap_or(0),
+            TypeKind::Alias(target) => self.size_of(target, depth + 1),
+            _ => 0,
         }
     }
 }
@@ -166,18 +1156,68 @@ impl Type {
 #[derive(Clone)]
 pub enum Location {
     Address(usize),
+    /// Offset from the function's `DW_AT_frame_base`
     FramePointerOffset(isize),
+    /// Any other location expression, for `location::locate` to evaluate
+    Expression(Expression),
+    /// A location list: where the variable is over ranges of link-time addresses. Outside of
+    /// them it's optimized out.
+    List(Vec<(usize, usize, Expression)>),
+    /// `DW_AT_const_value`: the compiler knows the value, so the variable isn't anywhere
+    Constant(Vec<u8>),
+    OptimizedOut,
 }
 
 impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
+        match self {
             Location::Address(addr) => write!(f, "Address({:#x})", addr),
             Location::FramePointerOffset(offset) => write!(f, "FramePointerOffset({})", offset),
+            Location::Expression(expression) => write!(f, "Expression({})", expression),
+            Location::List(entries) => {
+                write!(f, "List(")?;
+                for (i, (start, end, expression)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "[{:#x}, {:#x}): {}", start, end, expression)?;
+                }
+                write!(f, ")")
+            }
+            Location::Constant(bytes) => write!(f, "Constant({:02x?})", bytes),
+            Location::OptimizedOut => write!(f, "OptimizedOut"),
         }
     }
 }
 
+/// A DWARF expression, copied out of its unit along with what we need to evaluate it later.
+#[derive(Clone)]
+pub struct Expression {
+    pub bytecode: Vec<u8>,
+    pub encoding: gimli::Encoding,
+    /// The `.debug_addr` entries the expression refers to by index (`DW_OP_addrx` and
+    /// `DW_OP_constx`), as (index, address)
+    pub addresses: Vec<(u64, u64)>,
+}
+
+impl fmt::Debug for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.bytecode.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Debug for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt::Display::fmt(self, f)
@@ -189,6 +1229,8 @@ impl fmt::Debug for Location {
 pub struct Variable {
     pub name: String,
     pub entity_type: Type,
+    /// The types of the unit the variable is in, which its type's members and targets refer to
+    pub types: Rc<Types>,
     pub location: Location,
     pub line_number: usize, // Line number in source file
 }
@@ -196,10 +1238,98 @@ pub struct Variable {
 #[derive(Debug, Default, Clone)]
 pub struct Function {
     pub name: String,
+    /// The symbol name, which for C++ has the parameter types that tell overloads apart
+    pub linkage_name: Option<String>,
     pub address: usize,
     pub text_length: usize,
     pub line_number: usize, // Line number in source file
     pub variables: Vec<Variable>,
+    /// What `Location::FramePointerOffset` and `DW_OP_fbreg` are relative to
+    pub frame_base: Option<Location>,
+    /// The calls this function makes that the compiler described, which is how we find the
+    /// values parameters had on entry to the functions called (`DW_OP_entry_value`)
+    pub call_sites: Vec<CallSite>,
+    /// The calls the compiler inlined into this function, including ones inlined into inlined
+    /// code
+    pub inlined_calls: Vec<InlinedCall>,
+}
+
+impl Function {
+    /// Returns whether `query`, as given to `break`, names this function: its qualified name,
+    /// or the end of it (`method` or `Class::method` for `ns::Class::method`). C++ overloads can
+    /// be told apart by their parameter types, as in `method(int)`.
+    pub fn matches(&self, query: &str) -> bool {
+        if !query.ends_with(')') {
+            return name_matches(&self.name, query);
+        }
+        // Only the symbol name has the parameter types
+        let without_spaces = |name: &str| name.replace(' ', "");
+        match &self.linkage_name {
+            Some(linkage_name) => name_matches(
+                &without_spaces(&demangle(linkage_name)),
+                &without_spaces(query),
+            ),
+            None => false,
+        }
+    }
+}
+
+/// Returns the most deeply nested of the calls inlined into `func` whose code contains the
+/// link-time address `addr`.
+fn innermost_inlined_call(func: &Function, addr: usize) -> Option<&InlinedCall> {
+    func.inlined_calls
+        .iter()
+        .filter(|call| call.contains(addr))
+        .max_by_key(|call| call.depth)
+}
+
+/// Returns whether `query` is the qualified name `name`, or the end of it after a `::`.
+fn name_matches(name: &str, query: &str) -> bool {
+    match name.strip_suffix(query) {
+        Some(scope) => scope.is_empty() || scope.ends_with("::"),
+        None => false,
+    }
+}
+
+/// A call that the compiler inlined: a copy of the callee's code inside the caller.
+#[derive(Debug, Default, Clone)]
+pub struct InlinedCall {
+    /// The function called
+    pub name: String,
+    /// Link-time address ranges of the copy
+    pub ranges: Vec<(usize, usize)>,
+    /// Where the copy starts running, if not at its lowest address
+    pub entry: Option<usize>,
+    pub call_file: Option<String>,
+    pub call_line: usize,
+    /// How deep the call is in the function's DIEs: calls inlined into inlined code are deeper
+    pub depth: usize,
+}
+
+impl InlinedCall {
+    /// Returns the link-time address where a breakpoint on the inlined function should go. gcc
+    /// sometimes puts the entry point before the inlined code proper, where we'd report that
+    /// we're still in the caller, so we only use it if it's in the copy.
+    pub fn entry(&self) -> Option<usize> {
+        self.entry
+            .filter(|entry| self.contains(*entry))
+            .or_else(|| self.ranges.iter().map(|range| range.0).min())
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        self.ranges
+            .iter()
+            .any(|(start, end)| *start <= addr && addr < *end)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CallSite {
+    /// Link-time address the call returns to
+    pub return_address: usize,
+    /// The DWARF register each parameter is passed in, and an expression for its value (in the
+    /// caller's frame)
+    pub parameters: Vec<(u16, Expression)>,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -208,6 +1338,30 @@ pub struct File {
     pub global_variables: Vec<Variable>,
     pub functions: Vec<Function>,
     pub lines: Vec<Line>,
+    pub types: Rc<Types>,
+}
+
+/// What `DwarfData::search` looks for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchKind {
+    Functions,
+    Variables,
+    Types,
+}
+
+/// A function, global variable or type found by `DwarfData::search`.
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    /// The source file it's defined in, or `None` for ELF symbols we have no DWARF for
+    pub file: Option<String>,
+    /// 0 if unknown
+    pub line_number: usize,
+    /// The qualified name, with the parameter types of C++ functions
+    pub name: String,
+    /// How to show it: the name, plus the type for variables
+    pub declaration: String,
+    /// Runtime address of ELF symbols
+    pub address: Option<usize>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -0,0 +1,88 @@
+//! Working out what a Rust panic or C++ exception is about, from the arguments of the runtime
+//! functions `catch panic`, `catch throw` and `catch catch` stop at. The runtimes have no type
+//! information we could use, so this relies on the layout of their data structures on x86_64.
+
+use crate::inferior::Target;
+
+/// The runtime function a Rust panic goes through right before unwinding starts
+pub const RUST_PANIC: &str = "rust_panic";
+/// The function C++ `throw` calls with the new exception
+pub const CXA_THROW: &str = "__cxa_throw";
+/// The function a C++ `catch` block starts by calling
+pub const CXA_BEGIN_CATCH: &str = "__cxa_begin_catch";
+
+/// `exception_class` of exceptions thrown by C++ code compiled with gcc or clang ("GNUCC++\0")
+const GNU_CXX_EXCEPTION_CLASS: u64 = 0x474e_5543_432b_2b00;
+/// How far before its `_Unwind_Exception` header the `std::type_info *` of a `__cxa_exception`
+/// is: the type is its first field, and the header its last.
+const EXCEPTION_TYPE_OFFSET: usize = 80;
+/// Panic messages longer than this are most likely not a message at all
+const MAX_MESSAGE_LENGTH: usize = 64 * 1024;
+/// The payloads we know how to read are a few words long; a bigger size means it's something
+/// else (or not a size at all)
+const MAX_PAYLOAD_SIZE: usize = 8 * 8;
+
+/// Returns the message of the panic `rust_panic` was called for, if it can be made out. Its
+/// argument is a `&mut dyn PanicPayload`. `panic!` with a string literal passes a `&str`; with a
+/// format string, a struct whose `String` the panic hook filled in before we got here.
+pub fn panic_message(target: &dyn Target) -> Option<String> {
+    let regs = target.get_registers().ok()?;
+    let payload = regs.rdi as usize;
+    let payload_size = target.read_word((regs.rsi as usize).checked_add(8)?).ok()?;
+    if payload_size > MAX_PAYLOAD_SIZE {
+        return None;
+    }
+    let words: Vec<usize> = (0..payload_size / 8)
+        .map(|i| target.read_word(payload.checked_add(8 * i)?).ok())
+        .collect::<Option<_>>()?;
+    if let [pointer, length] = words[..] {
+        return read_utf8(target, pointer, length);
+    }
+    // A `String` is its capacity, pointer and length, with the first two either way round
+    // depending on the Rust version
+    words.windows(3).find_map(|string| {
+        let (capacity, pointer, length) = (string[0], string[1], string[2]);
+        [(capacity, pointer), (pointer, capacity)]
+            .iter()
+            .filter(|(capacity, _)| length <= *capacity)
+            .find_map(|(_, pointer)| read_utf8(target, *pointer, length))
+    })
+}
+
+/// Returns the (mangled) name of the type of the exception `__cxa_throw` was called with. Its
+/// second argument is the exception's `std::type_info`.
+pub fn thrown_type(target: &dyn Target) -> Option<String> {
+    let regs = target.get_registers().ok()?;
+    type_info_name(target, regs.rsi as usize)
+}
+
+/// Returns the (mangled) name of the type of the exception `__cxa_begin_catch` was called
+/// with. Its argument points to the `_Unwind_Exception` header at the end of the
+/// `__cxa_exception` describing the exception, unless it's a foreign (non-C++) exception.
+pub fn caught_type(target: &dyn Target) -> Option<String> {
+    let regs = target.get_registers().ok()?;
+    let header = regs.rdi as usize;
+    if target.read_word(header).ok()? as u64 != GNU_CXX_EXCEPTION_CLASS {
+        return None;
+    }
+    let type_info = target
+        .read_word(header.checked_sub(EXCEPTION_TYPE_OFFSET)?)
+        .ok()?;
+    type_info_name(target, type_info)
+}
+
+/// Reads the name in a `std::type_info`, which comes after its vtable pointer. Types local to a
+/// file have a `*` in front, which isn't part of the name.
+fn type_info_name(target: &dyn Target, type_info: usize) -> Option<String> {
+    let name = target.read_word(type_info + 8).ok()?;
+    let name = target.read_string(name).ok()?;
+    Some(name.trim_start_matches('*').to_string()).filter(|name| !name.is_empty())
+}
+
+fn read_utf8(target: &dyn Target, pointer: usize, length: usize) -> Option<String> {
+    if pointer == 0 || length > MAX_MESSAGE_LENGTH {
+        return None;
+    }
+    let bytes = target.read_memory(pointer, length).ok()?;
+    String::from_utf8(bytes).ok()
+}
@@ -6,240 +6,1349 @@
 //! This code is a huge mess. Please don't read it unless you're trying to do an extension :)
 
 use gimli::{UnitOffset, UnitSectionOffset};
-use object::Object;
-use std::borrow;
 //use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use crate::debug_section;
+use crate::dwarf_data::{
+    CallSite, Expression, File, Function, InlinedCall, Line, Location, Member, Struct, Type,
+    TypeKind, Types, Variable, VariantPart,
+};
+use crate::name_index::NameIndex;
+use crate::split_dwarf;
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Write;
+use std::rc::Rc;
 use std::{io, path};
 
-pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<Vec<File>, Error> {
-    // Load a section and return as `Cow<[u8]>`.
-    let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
-        Ok(object
-            .section_data_by_name(id.name())
-            .unwrap_or(borrow::Cow::Borrowed(&[][..])))
+/// `DW_OP_GNU_addr_index`, which DWARF 5 standardized as `DW_OP_addrx`
+const DW_OP_GNU_ADDR_INDEX: u8 = 0xfb;
+/// `DW_OP_GNU_const_index`, which DWARF 5 standardized as `DW_OP_constx`
+const DW_OP_GNU_CONST_INDEX: u8 = 0xfc;
+
+/// The sections `Sections` holds on to
+const DWARF_SECTIONS: &[&str] = &[
+    ".debug_abbrev",
+    ".debug_addr",
+    ".debug_aranges",
+    ".debug_info",
+    ".debug_line",
+    ".debug_line_str",
+    ".debug_loc",
+    ".debug_loclists",
+    ".debug_names",
+    ".debug_ranges",
+    ".debug_rnglists",
+    ".debug_str",
+    ".debug_str_offsets",
+    ".debug_types",
+    ".gdb_index",
+];
+
+/// The debug sections of an ELF file, decompressed if need be. We keep these around so that
+/// units can be parsed when they're first needed rather than all up front.
+pub struct Sections {
+    sections: HashMap<&'static str, Rc<[u8]>>,
+    endian: gimli::RunTimeEndian,
+}
+
+impl Sections {
+    pub fn load(data: &[u8], endian: gimli::RunTimeEndian) -> Sections {
+        Sections {
+            sections: DWARF_SECTIONS
+                .iter()
+                .filter_map(|name| {
+                    Some((*name, Rc::from(&*debug_section::section_data(data, name)?)))
+                })
+                .collect(),
+            endian,
+        }
+    }
+
+    /// Returns the contents of a section, which are empty if the file doesn't have it.
+    pub fn data(&self, name: &str) -> &[u8] {
+        self.sections.get(name).map_or(&[], |data| data)
+    }
+
+    /// Returns the contents of a section, for sharing with addr2line.
+    pub fn shared(&self, name: &str) -> Rc<[u8]> {
+        self.sections
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| Rc::from(&[][..]))
+    }
+
+    pub fn endian(&self) -> gimli::RunTimeEndian {
+        self.endian
+    }
+
+    pub fn dwarf(&self) -> Result<gimli::Dwarf<Slice<'_>>, Error> {
+        let load_section = |id: gimli::SectionId| -> Result<Slice, gimli::Error> {
+            Ok(gimli::EndianSlice::new(self.data(id.name()), self.endian))
+        };
+        // We don't have a supplementary object file
+        let load_section_sup = |_| Ok(gimli::EndianSlice::new(&[][..], self.endian));
+        Ok(gimli::Dwarf::load(load_section, load_section_sup)?)
+    }
+}
+
+/// What we need to know about a compile unit before parsing it: where it is in `.debug_info`,
+/// its name and the (link-time) addresses its code covers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnitSummary {
+    pub offset: usize,
+    pub name: Option<String>,
+    pub ranges: Vec<(usize, usize)>,
+    /// The `.dwo` file holding the rest of the unit, for code built with -gsplit-dwarf
+    pub dwo_path: Option<String>,
+    /// The source files in the unit's line table: its own, and the headers with code in it
+    pub files: Vec<String>,
+}
+
+/// Reads the unit headers and root DIEs, but none of the units' other DIEs.
+pub fn summarize_units(dwarf: &gimli::Dwarf<Slice>) -> Result<Vec<UnitSummary>, Error> {
+    use gimli::Section;
+    let mut summaries = Vec::new();
+    let mut input = dwarf.debug_info.reader().clone();
+    let mut offset = 0;
+    while !input.is_empty() {
+        if let Some(entry) = read_unit(dwarf, &mut input, &mut offset, false)? {
+            let dwo_path = match (entry.dwo_id, &entry.dwo_name) {
+                (Some(_), Some(dwo_name)) => {
+                    let comp_dir = entry
+                        .unit
+                        .comp_dir
+                        .map(|dir| dir.to_string_lossy().into_owned());
+                    let path = split_dwarf::DwoFile::path(comp_dir.as_deref(), dwo_name);
+                    Some(path.to_string_lossy().into_owned())
+                }
+                _ => None,
+            };
+            summaries.push(UnitSummary {
+                offset: entry.offset,
+                files: line_table_files(&entry.unit, dwarf),
+                name: entry.name,
+                ranges: entry.ranges,
+                dwo_path,
+            });
+        }
+    }
+    Ok(summaries)
+}
+
+/// Loads the functions, variables and lines of the unit at `offset` in `.debug_info`. For code
+/// built with -gsplit-dwarf, the binary only has a skeleton of each unit: the rest is in a .dwo
+/// file per unit, or in `package` if they were merged into a .dwp.
+pub fn load_unit(
+    sections: &Sections,
+    package: Option<&split_dwarf::Package>,
+    offset: usize,
+) -> Result<File, Error> {
+    let dwarf = sections.dwarf()?;
+    let entry = read_unit_at(&dwarf, offset)?;
+    let mut file = File {
+        name: entry
+            .name
+            .clone()
+            .unwrap_or_else(|| "<unknown>".to_string()),
+        ..Default::default()
     };
-    // Load a supplementary section. We don't have a supplementary object file,
-    // so always return an empty slice.
-    let load_section_sup = |_| Ok(borrow::Cow::Borrowed(&[][..]));
-
-    // Load all of the sections.
-    let dwarf_cow = gimli::Dwarf::load(&load_section, &load_section_sup)?;
-
-    // Borrow a `Cow<[u8]>` to create an `EndianSlice`.
-    let borrow_section: &dyn for<'a> Fn(
-        &'a borrow::Cow<[u8]>,
-    ) -> gimli::EndianSlice<'a, gimli::RunTimeEndian> =
-        &|section| gimli::EndianSlice::new(section, endian);
-
-    // Create `EndianSlice`s for all of the sections.
-    let dwarf = dwarf_cow.borrow(&borrow_section);
-
-    // Define a mapping from type offsets to type structs
-    let mut offset_to_type: HashMap<usize, Type> = HashMap::new();
-
-    let mut compilation_units: Vec<File> = Vec::new();
-
-    // Iterate over the compilation units.
-    let mut iter = dwarf.units();
-    while let Some(header) = iter.next()? {
-        let unit = dwarf.unit(header)?;
-
-        // Iterate over the Debugging Information Entries (DIEs) in the unit.
-        let mut depth = 0;
-        let mut entries = unit.entries();
-        while let Some((delta_depth, entry)) = entries.next_dfs()? {
-            depth += delta_depth;
-            // Update the offset_to_type mapping for types
-            // Update the variable list for formal params/variables
-            match entry.tag() {
-                gimli::DW_TAG_compile_unit => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
-                    } else {
-                        "<teunknown>".to_string()
-                    };
-                    compilation_units.push(File {
-                        name,
-                        global_variables: Vec::new(),
-                        functions: Vec::new(),
-                        lines: Vec::new(),
-                    });
+    // Without the split unit we still know the file's lines
+    with_debug_unit(
+        &entry,
+        &dwarf,
+        sections.endian(),
+        package,
+        |unit, dwarf, loc_dwo| load_entries(unit, dwarf, loc_dwo, &mut file),
+    )?;
+    load_lines(&entry.unit, &dwarf, &mut file)?;
+    Ok(file)
+}
+
+/// Adds the functions and global variables defined in the units at `offsets` to `names`. This
+/// is what we do for units that the binary has no name index for, which means looking at every
+/// top-level DIE.
+pub fn index_names(
+    sections: &Sections,
+    package: Option<&split_dwarf::Package>,
+    offsets: &[usize],
+    names: &mut NameIndex,
+) -> Result<(), Error> {
+    let dwarf = sections.dwarf()?;
+    for offset in offsets {
+        let entry = read_unit_at(&dwarf, *offset)?;
+        with_debug_unit(
+            &entry,
+            &dwarf,
+            sections.endian(),
+            package,
+            |unit, dwarf, _| {
+                let mut tree = unit.entries_tree(None)?;
+                index_children(tree.root()?, unit, dwarf, *offset, names)
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// Indexes the children of `node`, looking inside namespaces and types for functions and
+/// variables.
+fn index_children<R: Reader>(
+    node: gimli::EntriesTreeNode<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    unit_offset: usize,
+    names: &mut NameIndex,
+) -> Result<(), Error> {
+    let mut children = node.children();
+    while let Some(child) = children.next()? {
+        let entry = child.entry();
+        // C++ methods and static members defined outside their class have their name on the
+        // declaration inside it
+        let mut name = None;
+        for attr in attrs_with_origin(entry, unit)? {
+            if attr.name() == gimli::DW_AT_name {
+                if let Ok(DebugValue::Str(value)) = get_attr_value(&attr, unit, dwarf) {
+                    name = Some(value);
+                }
+            }
+        }
+        match entry.tag() {
+            // Skip declarations, which have no code. Functions that were inlined everywhere have
+            // none of their own either, but can still be broken on.
+            gimli::DW_TAG_subprogram
+                if entry.attr(gimli::DW_AT_low_pc)?.is_some()
+                    || entry.attr(gimli::DW_AT_ranges)?.is_some()
+                    || entry.attr(gimli::DW_AT_inline)?.is_some() =>
+            {
+                if let Some(name) = name {
+                    names.add_function(name, unit_offset);
+                }
+            }
+            // We never look inside functions, so these are all globals
+            gimli::DW_TAG_variable if entry.attr(gimli::DW_AT_location)?.is_some() => {
+                if let Some(name) = name {
+                    names.add_variable(name, unit_offset);
+                }
+            }
+            gimli::DW_TAG_namespace
+            | gimli::DW_TAG_structure_type
+            | gimli::DW_TAG_class_type
+            | gimli::DW_TAG_union_type => {
+                index_children(child, unit, dwarf, unit_offset, names)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+pub type Slice<'a> = gimli::EndianSlice<'a, gimli::RunTimeEndian>;
+
+/// A unit read from `.debug_info`. For a skeleton unit, and for the split unit it stands in for,
+/// `dwo_id` is the ID linking the two and `dwo_name` is the .dwo file holding the split unit.
+struct UnitEntry<R: Reader> {
+    offset: usize,
+    unit: gimli::Unit<R>,
+    /// The unit's source file. gcc leaves the name out of skeleton units, in which case this is
+    /// the first file in the line table.
+    name: Option<String>,
+    dwo_id: Option<u64>,
+    dwo_name: Option<String>,
+    /// Link-time addresses covered by the unit's code, from its root DIE
+    ranges: Vec<(usize, usize)>,
+}
+
+/// Reads the unit starting at `offset` in `.debug_info`.
+fn read_unit_at<R: Reader>(dwarf: &gimli::Dwarf<R>, offset: usize) -> Result<UnitEntry<R>, Error> {
+    use gimli::Section;
+    let mut input = dwarf.debug_info.reader().clone();
+    input.skip(offset)?;
+    let mut offset = offset;
+    read_unit(dwarf, &mut input, &mut offset, false)?
+        .ok_or_else(|| gimli::Error::MissingUnitDie.into())
+}
+
+/// Reads the header and root DIE of the unit at the start of `input`, which is at `offset` in
+/// `dwarf.debug_info`, and moves both past it. Returns `None` for type units, which only hold
+/// types that the compile units refer to. gimli's own unit iterator only knows about plain DWARF
+/// 5 compile units, so we parse the headers ourselves to also get skeleton and split units.
+/// `split` says this is a split unit (from a .dwo file or .dwp package), whose string and list
+/// offsets start after the sections' headers since there is no skeleton attribute saying where
+/// they start.
+fn read_unit<R: Reader>(
+    dwarf: &gimli::Dwarf<R>,
+    input: &mut R,
+    offset: &mut usize,
+    split: bool,
+) -> Result<Option<UnitEntry<R>>, Error> {
+    let unit_offset = gimli::DebugInfoOffset(*offset);
+    let (unit_length, format) = input.read_initial_length()?;
+    let mut rest = input.split(unit_length)?;
+    *offset += format.initial_length_size() as usize + unit_length;
+
+    let version = rest.read_u16()?;
+    let address_size;
+    let abbrev_offset;
+    let mut dwo_id = None;
+    if (2..=4).contains(&version) {
+        abbrev_offset = rest.read_offset(format)?;
+        address_size = rest.read_u8()?;
+    } else if version == 5 {
+        let unit_type = gimli::DwUt(rest.read_u8()?);
+        address_size = rest.read_u8()?;
+        abbrev_offset = rest.read_offset(format)?;
+        match unit_type {
+            gimli::DW_UT_compile | gimli::DW_UT_partial => {}
+            gimli::DW_UT_skeleton | gimli::DW_UT_split_compile => {
+                dwo_id = Some(rest.read_u64()?);
+            }
+            _ => return Ok(None),
+        }
+    } else {
+        return Err(gimli::Error::UnknownVersion(u64::from(version)).into());
+    }
+    let encoding = gimli::Encoding {
+        format,
+        version,
+        address_size,
+    };
+    let header = gimli::UnitHeader::new(
+        encoding,
+        unit_length,
+        gimli::DebugAbbrevOffset(abbrev_offset),
+        rest,
+    );
+
+    // The sizes of the .debug_str_offsets and .debug_{rng,loc}lists headers
+    let (str_offsets_base, lists_base) = if split && version == 5 {
+        match format {
+            gimli::Format::Dwarf32 => (8, 12),
+            gimli::Format::Dwarf64 => (16, 20),
+        }
+    } else {
+        (0, 0)
+    };
+    let mut unit = gimli::Unit {
+        offset: UnitSectionOffset::DebugInfoOffset(unit_offset),
+        abbreviations: header.abbreviations(&dwarf.debug_abbrev)?,
+        header,
+        name: None,
+        comp_dir: None,
+        low_pc: 0,
+        str_offsets_base: gimli::DebugStrOffsetsBase(str_offsets_base),
+        addr_base: gimli::DebugAddrBase(0),
+        loclists_base: gimli::DebugLocListsBase(lists_base),
+        rnglists_base: gimli::DebugRngListsBase(lists_base),
+        line_program: None,
+    };
+
+    let mut name = None;
+    let mut comp_dir = None;
+    let mut dwo_name = None;
+    let mut low_pc_index = None;
+    let mut high_pc = None;
+    let mut ranges_attr = None;
+    let mut line_program_offset = None;
+    {
+        let mut cursor = unit.header.entries(&unit.abbreviations);
+        cursor.next_dfs()?;
+        let root = cursor.current().ok_or(gimli::Error::MissingUnitDie)?;
+        let mut attrs = root.attrs();
+        while let Some(attr) = attrs.next()? {
+            match (attr.name(), attr.value()) {
+                (gimli::DW_AT_name, value) => name = Some(value),
+                (gimli::DW_AT_comp_dir, value) => comp_dir = Some(value),
+                (gimli::DW_AT_dwo_name, value) | (gimli::DW_AT_GNU_dwo_name, value) => {
+                    dwo_name = Some(value)
+                }
+                (gimli::DW_AT_GNU_dwo_id, gimli::AttributeValue::Udata(id))
+                | (gimli::DW_AT_GNU_dwo_id, gimli::AttributeValue::Data8(id)) => dwo_id = Some(id),
+                (gimli::DW_AT_low_pc, gimli::AttributeValue::Addr(address)) => {
+                    unit.low_pc = address
+                }
+                (gimli::DW_AT_low_pc, gimli::AttributeValue::DebugAddrIndex(index)) => {
+                    low_pc_index = Some(index)
+                }
+                (gimli::DW_AT_high_pc, value) => high_pc = Some(value),
+                (gimli::DW_AT_ranges, value) => ranges_attr = Some(value),
+                (gimli::DW_AT_stmt_list, gimli::AttributeValue::DebugLineRef(offset)) => {
+                    line_program_offset = Some(offset)
+                }
+                (
+                    gimli::DW_AT_str_offsets_base,
+                    gimli::AttributeValue::DebugStrOffsetsBase(base),
+                ) => unit.str_offsets_base = base,
+                (gimli::DW_AT_addr_base, gimli::AttributeValue::DebugAddrBase(base)) => {
+                    unit.addr_base = base
+                }
+                // The pre-DWARF 5 split DWARF extension
+                (gimli::DW_AT_GNU_addr_base, gimli::AttributeValue::SecOffset(base)) => {
+                    unit.addr_base = gimli::DebugAddrBase(base)
+                }
+                (gimli::DW_AT_loclists_base, gimli::AttributeValue::DebugLocListsBase(base)) => {
+                    unit.loclists_base = base
+                }
+                (gimli::DW_AT_rnglists_base, gimli::AttributeValue::DebugRngListsBase(base)) => {
+                    unit.rnglists_base = base
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if let Some(index) = low_pc_index {
+        unit.low_pc = dwarf.address(&unit, index)?;
+    }
+    unit.name = match name {
+        Some(value) => Some(dwarf.attr_string(&unit, value)?),
+        None => None,
+    };
+    unit.comp_dir = match comp_dir {
+        Some(value) => Some(dwarf.attr_string(&unit, value)?),
+        None => None,
+    };
+    let dwo_name = match dwo_name {
+        Some(value) => Some(
+            dwarf
+                .attr_string(&unit, value)?
+                .to_string_lossy()?
+                .into_owned(),
+        ),
+        None => None,
+    };
+    unit.line_program = match line_program_offset {
+        Some(offset) => Some(dwarf.debug_line.program(
+            offset,
+            unit.header.address_size(),
+            unit.comp_dir.clone(),
+            unit.name.clone(),
+        )?),
+        None => None,
+    };
+
+    let mut ranges = Vec::new();
+    if let Some(value) = ranges_attr {
+        // A unit whose code isn't contiguous lists its ranges instead of having a high_pc
+        if let Some(mut list) = dwarf.attr_ranges(&unit, value)? {
+            while let Some(range) = list.next()? {
+                if range.begin < range.end {
+                    ranges.push((range.begin as usize, range.end as usize));
                 }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
+            }
+        }
+    } else if let Some(value) = high_pc {
+        let end = match value {
+            gimli::AttributeValue::Addr(address) => Some(address),
+            gimli::AttributeValue::DebugAddrIndex(index) => Some(dwarf.address(&unit, index)?),
+            // Usually high_pc is the length
+            value => value.udata_value().map(|length| unit.low_pc + length),
+        };
+        if let Some(end) = end.filter(|end| *end > unit.low_pc) {
+            ranges.push((unit.low_pc as usize, end as usize));
+        }
+    }
+    let name = match &unit.name {
+        Some(name) => Some(source_file_name(&name.to_string_lossy()?)),
+        None => primary_file_name(&unit, dwarf)?,
+    };
+    Ok(Some(UnitEntry {
+        offset: unit_offset.0,
+        unit,
+        name,
+        dwo_id,
+        dwo_name,
+        ranges,
+    }))
+}
+
+/// Returns the source file a unit called `name` was compiled from. rustc names its units after
+/// the crate's root file and the codegen unit, e.g. `src/main.rs/@/prog.3b7f65fa-cgu.0`.
+fn source_file_name(name: &str) -> String {
+    match name.find("/@/") {
+        Some(end) => name[..end].to_string(),
+        None => name.to_string(),
+    }
+}
+
+/// Returns the path of the first file in `unit`'s line table, which is the main source file.
+fn primary_file_name<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<Option<String>, Error> {
+    let header = match &unit.line_program {
+        Some(program) => program.header(),
+        None => return Ok(None),
+    };
+    let file = match header.file_names().first() {
+        Some(file) => file,
+        None => return Ok(None),
+    };
+    let mut path = path::PathBuf::new();
+    if let Some(dir) = file.directory(header) {
+        path.push(dwarf.attr_string(unit, dir)?.to_string_lossy()?.as_ref());
+    }
+    path.push(
+        dwarf
+            .attr_string(unit, file.path_name())?
+            .to_string_lossy()?
+            .as_ref(),
+    );
+    Ok(Some(path.to_string_lossy().into_owned()))
+}
+
+/// Calls `f` on the unit holding the DIEs of the unit in `entry`: the split unit it stands in
+/// for if it's a skeleton, and otherwise itself. `f` also gets the unit's `.debug_loc.dwo`, which
+/// gimli has no way to read (see `gnu_split_locations`); it's empty for units that aren't split.
+/// Returns `None` if we can't find the split unit.
+fn with_debug_unit<T>(
+    entry: &UnitEntry<Slice>,
+    dwarf: &gimli::Dwarf<Slice>,
+    endian: gimli::RunTimeEndian,
+    package: Option<&split_dwarf::Package>,
+    f: impl for<'a> FnOnce(
+        &gimli::Unit<Slice<'a>>,
+        &gimli::Dwarf<Slice<'a>>,
+        Slice<'a>,
+    ) -> Result<T, Error>,
+) -> Result<Option<T>, Error> {
+    let dwo_id = match entry.dwo_id {
+        Some(dwo_id) => dwo_id,
+        None => {
+            let no_locations = gimli::EndianSlice::new(&[][..], endian);
+            return Ok(Some(f(&entry.unit, dwarf, no_locations)?));
+        }
+    };
+    let dwo_file;
+    let sections = match package.and_then(|package| package.sections(dwo_id)) {
+        Some(sections) => sections,
+        None => {
+            let comp_dir = entry
+                .unit
+                .comp_dir
+                .map(|dir| dir.to_string_lossy().into_owned());
+            dwo_file = match &entry.dwo_name {
+                Some(name) => split_dwarf::DwoFile::open(comp_dir.as_deref(), name),
+                None => None,
+            };
+            match &dwo_file {
+                Some(file) => file.sections(),
+                None => return Ok(None),
+            }
+        }
+    };
+
+    let load_section = |id: gimli::SectionId| -> Result<Slice, gimli::Error> {
+        let name = format!("{}.dwo", id.name());
+        let data = sections.get(name.as_str()).copied().unwrap_or(&[]);
+        Ok(gimli::EndianSlice::new(data, endian))
+    };
+    let load_section_sup = |_| Ok(gimli::EndianSlice::new(&[][..], endian));
+    let mut split_dwarf = gimli::Dwarf::load(load_section, load_section_sup)?;
+    // Addresses stay in the binary, where they can be relocated; split units refer to them by
+    // index
+    split_dwarf.debug_addr = dwarf.debug_addr;
+
+    use gimli::Section;
+    let mut input = split_dwarf.debug_info.reader().clone();
+    let mut offset = 0;
+    while !input.is_empty() {
+        let split_entry = match read_unit(&split_dwarf, &mut input, &mut offset, true)? {
+            Some(split_entry) if split_entry.dwo_id == Some(dwo_id) => split_entry,
+            _ => continue,
+        };
+        let unit = gimli::Unit {
+            low_pc: entry.unit.low_pc,
+            addr_base: entry.unit.addr_base,
+            ..split_entry.unit
+        };
+        let loc_dwo = load_section(gimli::SectionId::DebugLoc)?;
+        return Ok(Some(f(&unit, &split_dwarf, loc_dwo)?));
+    }
+    Ok(None)
+}
+
+/// Adds the functions, variables and base types described by `unit`'s DIEs to `file`.
+fn load_entries<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    loc_dwo: R,
+    file: &mut File,
+) -> Result<(), Error> {
+    // Iterate over the Debugging Information Entries (DIEs) in the unit.
+    let mut depth = 0;
+    // Split units from before DWARF 5 have location lists in a GNU format of their own, in
+    // `loc_dwo`
+    let mut gnu_loc_dwo = None;
+    // Depth of the inlined call we're in, if any. Its parameters and locals belong to the
+    // inlined function, not to the one we're adding variables to.
+    let mut inlined_depth = None;
+    let mut types = Types::default();
+    let mut c_language = false;
+    // The namespaces and types we're inside of, with their depths, for qualified names
+    let mut scopes: Vec<(isize, String)> = Vec::new();
+    // Depth of the function we're in, if any. Variables outside functions are globals, even
+    // inside a namespace.
+    let mut function_depth = None;
+    // The qualified names of the functions and variables seen so far, by DIE offset. Their
+    // definitions or inlined copies can be somewhere else entirely, e.g. a C++ method defined
+    // outside its class.
+    let mut qualified_names: HashMap<usize, String> = HashMap::new();
+    // Variables can refer to types defined after them, so their types are filled in once we've
+    // seen all of them: (index of the function, or None for globals, index of the variable,
+    // offset of the type)
+    let mut variable_types = Vec::new();
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        if matches!(inlined_depth, Some(inlined_depth) if depth <= inlined_depth) {
+            inlined_depth = None;
+        }
+        if matches!(function_depth, Some(function_depth) if depth <= function_depth) {
+            function_depth = None;
+        }
+        while matches!(scopes.last(), Some((scope_depth, _)) if depth <= *scope_depth) {
+            scopes.pop();
+        }
+        // Update the variable list for formal params/variables
+        match entry.tag() {
+            gimli::DW_TAG_compile_unit => {
+                if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
+                    if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, unit, dwarf) {
+                        file.name = source_file_name(&name);
+                    }
+                }
+                if entry.attr(gimli::DW_AT_GNU_dwo_id)?.is_some() {
+                    gnu_loc_dwo = Some(loc_dwo.clone());
+                }
+                if let Some(gimli::AttributeValue::Language(language)) =
+                    entry.attr_value(gimli::DW_AT_language)?
+                {
+                    types.rust = language == gimli::DW_LANG_Rust;
+                    c_language = matches!(
+                        language,
+                        gimli::DW_LANG_C
+                            | gimli::DW_LANG_C89
+                            | gimli::DW_LANG_C99
+                            | gimli::DW_LANG_C11
+                    );
+                }
+            }
+            gimli::DW_TAG_namespace => {
+                if let Some(name) = entry_name(entry, unit, dwarf)? {
+                    scopes.push((depth, name));
+                }
+            }
+            gimli::DW_TAG_base_type
+            | gimli::DW_TAG_pointer_type
+            | gimli::DW_TAG_reference_type
+            | gimli::DW_TAG_rvalue_reference_type
+            | gimli::DW_TAG_typedef
+            | gimli::DW_TAG_const_type
+            | gimli::DW_TAG_volatile_type
+            | gimli::DW_TAG_structure_type
+            | gimli::DW_TAG_class_type
+            | gimli::DW_TAG_union_type
+            | gimli::DW_TAG_array_type
+            | gimli::DW_TAG_enumeration_type => {
+                let entity_type = load_type(entry, unit, dwarf, &scope_name(&scopes), c_language)?;
+                // Types declared inside this one are named after it, except in C
+                if matches!(entity_type.kind, TypeKind::Struct(_) | TypeKind::Enum(_))
+                    && !entity_type.name.is_empty()
+                    && !c_language
+                {
+                    let name = entity_type.name.rsplit("::").next().unwrap_or("");
+                    scopes.push((depth, name.to_string()));
+                }
+                types.types.insert(entry.offset().0, entity_type);
+            }
+            gimli::DW_TAG_subprogram => {
+                let mut func: Function = Default::default();
+                let mut high_pc = None;
+                // Out-of-line copies of inlined functions get their name from the abstract one
+                for attr in attrs_with_origin(entry, unit)? {
+                    let val = get_attr_value(&attr, unit, dwarf);
+                    //println!("   {}: {:?}", attr.name(), val);
+                    match attr.name() {
+                        gimli::DW_AT_name => {
+                            if let Ok(DebugValue::Str(name)) = val {
+                                func.name = name;
+                            }
                         }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
+                        gimli::DW_AT_linkage_name | gimli::DW_AT_MIPS_linkage_name => {
+                            if let Ok(DebugValue::Str(name)) = val {
+                                func.linkage_name = Some(name);
+                            }
                         }
-                    } else {
-                        // TODO: report error?
-                        0
-                    };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
-                }
-                gimli::DW_TAG_subprogram => {
-                    let mut func: Function = Default::default();
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
-                        //println!("   {}: {:?}", attr.name(), val);
-                        match attr.name() {
-                            gimli::DW_AT_name => {
-                                if let Ok(DebugValue::Str(name)) = val {
-                                    func.name = name;
-                                }
+                        gimli::DW_AT_high_pc => {
+                            if let Ok(DebugValue::Uint(value)) = val {
+                                // Usually the length, but it can be the end address
+                                let is_address = matches!(
+                                    attr.value(),
+                                    gimli::AttributeValue::Addr(_)
+                                        | gimli::AttributeValue::DebugAddrIndex(_)
+                                );
+                                high_pc = Some((value, is_address));
                             }
-                            gimli::DW_AT_high_pc => {
-                                if let Ok(DebugValue::Uint(high_pc)) = val {
-                                    func.text_length = high_pc.try_into().unwrap();
+                        }
+                        gimli::DW_AT_ranges => {
+                            // Functions split into several pieces (e.g. with the unlikely
+                            // paths moved out to .text.unlikely) list their body first
+                            if let Some(mut ranges) = dwarf.attr_ranges(unit, attr.value())? {
+                                if let Some(range) = ranges.next()? {
+                                    func.address = range.begin.try_into().unwrap();
+                                    func.text_length =
+                                        (range.end - range.begin).try_into().unwrap();
                                 }
                             }
-                            gimli::DW_AT_low_pc => {
-                                //println!("low pc {:?}", attr.value());
-                                if let Ok(DebugValue::Uint(low_pc)) = val {
-                                    func.address = low_pc.try_into().unwrap();
-                                }
+                        }
+                        gimli::DW_AT_low_pc => {
+                            //println!("low pc {:?}", attr.value());
+                            if let Ok(DebugValue::Uint(low_pc)) = val {
+                                func.address = low_pc.try_into().unwrap();
                             }
-                            gimli::DW_AT_decl_line => {
-                                if let Ok(DebugValue::Uint(line_number)) = val {
-                                    func.line_number = line_number.try_into().unwrap();
-                                }
+                        }
+                        gimli::DW_AT_decl_line => {
+                            if let Ok(DebugValue::Uint(line_number)) = val {
+                                func.line_number = line_number.try_into().unwrap();
                             }
-                            _ => {}
                         }
+                        gimli::DW_AT_frame_base => {
+                            func.frame_base =
+                                get_location(&attr, unit, dwarf, gnu_loc_dwo.as_ref())?;
+                        }
+                        _ => {}
                     }
-                    compilation_units.last_mut().unwrap().functions.push(func);
-                }
-                gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
-                    let mut name = String::new();
-                    let mut entity_type: Option<Type> = None;
-                    let mut location: Option<Location> = None;
-                    let mut line_number = 0;
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
-                        //println!("   {}: {:?}", attr.name(), val);
-                        match attr.name() {
-                            gimli::DW_AT_name => {
-                                if let Ok(DebugValue::Str(attr_name)) = val {
-                                    name = attr_name;
-                                }
+                }
+                match high_pc {
+                    Some((end, true)) => {
+                        func.text_length = (end as usize).saturating_sub(func.address)
+                    }
+                    Some((length, false)) => func.text_length = length.try_into().unwrap(),
+                    None => {}
+                }
+                func.name = qualified_name(entry, &func.name, &scopes, &qualified_names)?;
+                qualified_names.insert(entry.offset().0, func.name.clone());
+                function_depth = Some(depth);
+                file.functions.push(func);
+            }
+            gimli::DW_TAG_inlined_subroutine => {
+                if inlined_depth.is_none() {
+                    inlined_depth = Some(depth);
+                }
+                let mut call = InlinedCall {
+                    ranges: entry_ranges(entry, unit, dwarf)?,
+                    depth: depth.try_into().unwrap(),
+                    ..Default::default()
+                };
+                for attr in attrs_with_origin(entry, unit)? {
+                    match (attr.name(), get_attr_value(&attr, unit, dwarf)) {
+                        (gimli::DW_AT_name, Ok(DebugValue::Str(name))) => {
+                            call.name = qualified_name(entry, &name, &scopes, &qualified_names)?
+                        }
+                        (gimli::DW_AT_entry_pc, Ok(DebugValue::Uint(entry))) => {
+                            call.entry = Some(entry.try_into().unwrap())
+                        }
+                        (gimli::DW_AT_call_file, _) => {
+                            if let Some(index) = attr.udata_value() {
+                                call.call_file = line_file_name(unit, dwarf, index)?;
                             }
-                            gimli::DW_AT_type => {
-                                if let Ok(DebugValue::Size(offset)) = val {
-                                    if let Some(dtype) = offset_to_type.get(&offset).clone() {
-                                        entity_type = Some(dtype.clone());
-                                    }
-                                }
+                        }
+                        (gimli::DW_AT_call_line, _) => {
+                            call.call_line = attr.udata_value().unwrap_or(0).try_into().unwrap()
+                        }
+                        _ => {}
+                    }
+                }
+                if call.call_file.is_none() {
+                    call.call_file = Some(file.name.clone());
+                }
+                if let Some(func) = file.functions.last_mut() {
+                    func.inlined_calls.push(call);
+                }
+            }
+            // Static members are declared in their class (in DWARF 4), and defined outside it
+            gimli::DW_TAG_member if entry.attr_value(gimli::DW_AT_declaration)?.is_some() => {
+                if let Some(name) = entry_name(entry, unit, dwarf)? {
+                    let name = qualified_name(entry, &name, &scopes, &qualified_names)?;
+                    qualified_names.insert(entry.offset().0, name);
+                }
+            }
+            // We don't show the variables of inlined functions
+            gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable if inlined_depth.is_some() => {}
+            gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
+                let mut name = String::new();
+                let mut type_offset = None;
+                let mut location: Option<Location> = None;
+                let mut line_number = 0;
+                let mut declaration = false;
+                for attr in attrs_with_origin(entry, unit)? {
+                    let val = get_attr_value(&attr, unit, dwarf);
+                    //println!("   {}: {:?}", attr.name(), val);
+                    match attr.name() {
+                        gimli::DW_AT_name => {
+                            if let Ok(DebugValue::Str(attr_name)) = val {
+                                name = attr_name;
                             }
-                            gimli::DW_AT_location => {
-                                if let Some(loc) = get_location(&attr, &unit) {
-                                    location = Some(loc);
-                                }
+                        }
+                        gimli::DW_AT_type => {
+                            if let gimli::AttributeValue::UnitRef(offset) = attr.value() {
+                                type_offset = Some(offset.0);
                             }
-                            gimli::DW_AT_decl_line => {
-                                if let Ok(DebugValue::Uint(num)) = val {
-                                    line_number = num;
-                                }
+                        }
+                        gimli::DW_AT_location => {
+                            location = get_location(&attr, unit, dwarf, gnu_loc_dwo.as_ref())?;
+                        }
+                        gimli::DW_AT_const_value => {
+                            location = constant_value(attr.value()).map(Location::Constant);
+                        }
+                        gimli::DW_AT_decl_line => {
+                            if let Ok(DebugValue::Uint(num)) = val {
+                                line_number = num;
                             }
-                            _ => {}
                         }
+                        gimli::DW_AT_declaration => declaration = true,
+                        _ => {}
+                    }
+                }
+                let local = function_depth.is_some();
+                if !local && !name.is_empty() {
+                    name = qualified_name(entry, &name, &scopes, &qualified_names)?;
+                    qualified_names.insert(entry.offset().0, name.clone());
+                }
+                // Locals the compiler got rid of have no location at all. Globals without one
+                // are declarations of variables defined elsewhere.
+                if location.is_none() && local && !declaration {
+                    location = Some(Location::OptimizedOut);
+                }
+                // Parameters of function declarations have no name, and are no use to us
+                if let (false, Some(type_offset), Some(location)) =
+                    (name.is_empty(), type_offset, location)
+                {
+                    let var = Variable {
+                        name,
+                        entity_type: Type::default(),
+                        types: Rc::default(),
+                        location,
+                        line_number: line_number.try_into().unwrap(),
+                    };
+                    if !local {
+                        variable_types.push((None, file.global_variables.len(), type_offset));
+                        file.global_variables.push(var);
+                    } else {
+                        let index = file.functions.len().checked_sub(1);
+                        if let (Some(index), Some(func)) = (index, file.functions.last_mut()) {
+                            variable_types.push((Some(index), func.variables.len(), type_offset));
+                            func.variables.push(var);
+                        }
+                    }
+                }
+            }
+            gimli::DW_TAG_call_site | gimli::DW_TAG_GNU_call_site => {
+                // DWARF 5 gives the return address; the GNU extension used low_pc for it
+                let return_address = match entry.attr_value(gimli::DW_AT_call_return_pc)? {
+                    Some(value) => Some(value),
+                    None => entry.attr_value(gimli::DW_AT_low_pc)?,
+                };
+                let return_address = match return_address {
+                    Some(gimli::AttributeValue::Addr(address)) => Some(address),
+                    Some(gimli::AttributeValue::DebugAddrIndex(index)) => {
+                        Some(dwarf.address(unit, index)?)
                     }
-                    if entity_type.is_some() && location.is_some() {
-                        let var = Variable {
-                            name,
-                            entity_type: entity_type.unwrap(),
-                            location: location.unwrap(),
-                            line_number: line_number.try_into().unwrap(),
-                        };
-                        if depth == 1 {
-                            compilation_units
-                                .last_mut()
-                                .unwrap()
-                                .global_variables
-                                .push(var);
-                        } else if depth > 1 {
-                            compilation_units
-                                .last_mut()
-                                .unwrap()
-                                .functions
-                                .last_mut()
-                                .unwrap()
-                                .variables
-                                .push(var);
+                    _ => None,
+                };
+                if let (Some(return_address), Some(func)) =
+                    (return_address, file.functions.last_mut())
+                {
+                    func.call_sites.push(CallSite {
+                        return_address: return_address.try_into().unwrap(),
+                        parameters: Vec::new(),
+                    });
+                }
+            }
+            gimli::DW_TAG_call_site_parameter | gimli::DW_TAG_GNU_call_site_parameter => {
+                let register = match entry.attr_value(gimli::DW_AT_location)? {
+                    Some(gimli::AttributeValue::Exprloc(expression)) => {
+                        match single_operation(&expression, unit.encoding()) {
+                            Some(gimli::Operation::Register { register }) => Some(register.0),
+                            _ => None,
                         }
                     }
+                    _ => None,
+                };
+                let value = match entry.attr_value(gimli::DW_AT_call_value)? {
+                    Some(value) => Some(value),
+                    None => entry.attr_value(gimli::DW_AT_GNU_call_site_value)?,
+                };
+                let value = match value {
+                    Some(gimli::AttributeValue::Exprloc(expression)) => {
+                        Some(copy_expression(expression.0, unit, dwarf)?)
+                    }
+                    _ => None,
+                };
+                let call_site = file
+                    .functions
+                    .last_mut()
+                    .and_then(|func| func.call_sites.last_mut());
+                if let (Some(register), Some(value), Some(call_site)) = (register, value, call_site)
+                {
+                    call_site.parameters.push((register, value));
                 }
-                // NOTE: :You may consider supporting other types by extending this
-                // match statement
-                _ => {}
             }
+            // NOTE: :You may consider supporting other types by extending this
+            // match statement
+            _ => {}
         }
+    }
+
+    types.finish();
+    let types = Rc::new(types);
+    for (function, index, type_offset) in variable_types {
+        let var = match function {
+            Some(function) => &mut file.functions[function].variables[index],
+            None => &mut file.global_variables[index],
+        };
+        // Variables of types we don't know how to show are left out, as before
+        var.entity_type = types.types.get(&type_offset).cloned().unwrap_or_default();
+        var.types = types.clone();
+    }
+    file.types = types;
+    let known = |var: &Variable| !matches!(var.entity_type.kind, TypeKind::Unknown);
+    file.global_variables.retain(known);
+    for func in &mut file.functions {
+        func.variables.retain(known);
+    }
+
+    Ok(())
+}
+
+/// Returns the names of the namespaces and types in `scopes` joined into a qualified name.
+fn scope_name(scopes: &[(isize, String)]) -> String {
+    let names: Vec<&str> = scopes.iter().map(|(_, name)| name.as_str()).collect();
+    names.join("::")
+}
 
-        // Get line numbers
-        if let Some(program) = unit.line_program.clone() {
-            // Iterate over the line program rows.
-            let mut rows = program.rows();
-            while let Some((header, row)) = rows.next_row()? {
-                if !row.end_sequence() {
-                    // Determine the path. Real applications should cache this for performance.
-                    let mut path = path::PathBuf::new();
-                    if let Some(file) = row.file(header) {
-                        if let Some(dir) = file.directory(header) {
-                            path.push(dwarf.attr_string(&unit, dir)?.to_string_lossy().as_ref());
+/// Returns the qualified name of the function or variable `entry`, whose own name is `name`:
+/// the one the DIE it defines or is an instance of got, if we've seen that, and otherwise `name`
+/// inside the namespaces and types in `scopes`.
+fn qualified_name<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    name: &str,
+    scopes: &[(isize, String)],
+    qualified_names: &HashMap<usize, String>,
+) -> Result<String, Error> {
+    if let Some(origin) = origin_offset(entry)? {
+        if let Some(qualified) = qualified_names.get(&origin.0) {
+            return Ok(qualified.clone());
+        }
+    }
+    Ok(match scope_name(scopes) {
+        scope if scope.is_empty() || name.is_empty() => name.to_string(),
+        scope => format!("{}::{}", scope, name),
+    })
+}
+
+/// Returns `entry`'s `DW_AT_name`, if it has one.
+fn entry_name<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<Option<String>, Error> {
+    match entry.attr(gimli::DW_AT_name)? {
+        Some(attr) => match get_attr_value(&attr, unit, dwarf)? {
+            DebugValue::Str(name) => Ok(Some(name)),
+            _ => Ok(None),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Returns the offset of the type `entry` has or refers to, if any.
+fn type_offset<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+) -> Result<Option<usize>, Error> {
+    match entry.attr_value(gimli::DW_AT_type)? {
+        Some(gimli::AttributeValue::UnitRef(offset)) => Ok(Some(offset.0)),
+        _ => Ok(None),
+    }
+}
+
+/// Reads the type DIE `entry`. `scope` is the qualified name of the namespace or type it's
+/// declared in. C (`c_language`) names its structs, unions and enums with a keyword in front.
+fn load_type<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    scope: &str,
+    c_language: bool,
+) -> Result<Type, Error> {
+    let mut name = entry_name(entry, unit, dwarf)?.unwrap_or_default();
+    let size = match entry.attr_value(gimli::DW_AT_byte_size)? {
+        Some(value) => value.udata_value().unwrap_or(0).try_into().unwrap(),
+        None => 0,
+    };
+    let target = type_offset(entry)?;
+    let kind = match entry.tag() {
+        gimli::DW_TAG_base_type => match entry.attr_value(gimli::DW_AT_encoding)? {
+            Some(gimli::AttributeValue::Encoding(encoding)) => TypeKind::Base(encoding.0),
+            _ => TypeKind::Base(0),
+        },
+        gimli::DW_TAG_pointer_type
+        | gimli::DW_TAG_reference_type
+        | gimli::DW_TAG_rvalue_reference_type => TypeKind::Pointer(target),
+        gimli::DW_TAG_typedef | gimli::DW_TAG_const_type | gimli::DW_TAG_volatile_type => {
+            match target {
+                Some(target) => TypeKind::Alias(target),
+                None => TypeKind::Unknown,
+            }
+        }
+        gimli::DW_TAG_array_type => match target {
+            Some(element) => TypeKind::Array {
+                element,
+                counts: array_counts(entry, unit)?,
+            },
+            None => TypeKind::Unknown,
+        },
+        gimli::DW_TAG_enumeration_type => TypeKind::Enum(load_enumerators(entry, unit, dwarf)?),
+        _ => TypeKind::Struct(load_struct(entry, unit, dwarf)?),
+    };
+    if matches!(kind, TypeKind::Struct(_) | TypeKind::Enum(_)) && !name.is_empty() {
+        if !scope.is_empty() {
+            name = format!("{}::{}", scope, name);
+        } else if c_language {
+            let keyword = match entry.tag() {
+                gimli::DW_TAG_union_type => "union",
+                gimli::DW_TAG_enumeration_type => "enum",
+                _ => "struct",
+            };
+            name = format!("{} {}", keyword, name);
+        }
+    }
+    Ok(Type { name, size, kind })
+}
+
+/// Returns the number of elements in each dimension of the array type `entry`, from its
+/// subrange children. Arrays of unknown size (`int x[]`) count as having none.
+fn array_counts<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+) -> Result<Vec<usize>, Error> {
+    let mut counts = Vec::new();
+    let mut tree = unit.entries_tree(Some(entry.offset()))?;
+    let mut children = tree.root()?.children();
+    while let Some(child) = children.next()? {
+        let child = child.entry();
+        if child.tag() != gimli::DW_TAG_subrange_type {
+            continue;
+        }
+        let count = match (
+            child.attr_value(gimli::DW_AT_count)?,
+            child.attr_value(gimli::DW_AT_upper_bound)?,
+        ) {
+            (Some(count), _) => count.udata_value().unwrap_or(0),
+            (None, Some(upper_bound)) => upper_bound
+                .udata_value()
+                .map_or(0, |bound| bound.checked_add(1).unwrap_or(0)),
+            (None, None) => 0,
+        };
+        counts.push(count as usize);
+    }
+    Ok(counts)
+}
+
+fn load_enumerators<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<Vec<(String, i64)>, Error> {
+    let mut enumerators = Vec::new();
+    let mut tree = unit.entries_tree(Some(entry.offset()))?;
+    let mut children = tree.root()?.children();
+    while let Some(child) = children.next()? {
+        let child = child.entry();
+        if child.tag() != gimli::DW_TAG_enumerator {
+            continue;
+        }
+        let value = match child.attr_value(gimli::DW_AT_const_value)? {
+            Some(gimli::AttributeValue::Sdata(value)) => value,
+            Some(value) => value.udata_value().unwrap_or(0) as i64,
+            None => continue,
+        };
+        if let Some(name) = entry_name(child, unit, dwarf)? {
+            enumerators.push((name, value));
+        }
+    }
+    Ok(enumerators)
+}
+
+/// Reads the members of a struct, class or union, leaving out static members and methods. C++
+/// base classes are members without a name.
+fn load_struct<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<Struct, Error> {
+    let mut result = Struct::default();
+    let mut tree = unit.entries_tree(Some(entry.offset()))?;
+    let mut children = tree.root()?.children();
+    while let Some(child) = children.next()? {
+        match child.entry().tag() {
+            gimli::DW_TAG_member | gimli::DW_TAG_inheritance => {
+                if let Some(member) = load_member(child.entry(), unit, dwarf)? {
+                    result.members.push(member);
+                }
+            }
+            gimli::DW_TAG_template_type_parameter => {
+                let name = entry_name(child.entry(), unit, dwarf)?;
+                if let (Some(name), Some(offset)) = (name, type_offset(child.entry())?) {
+                    result.template_params.push((name, offset));
+                }
+            }
+            gimli::DW_TAG_variant_part => {
+                let discriminant = match child.entry().attr_value(gimli::DW_AT_discr)? {
+                    Some(gimli::AttributeValue::UnitRef(offset)) => Some(offset),
+                    _ => None,
+                };
+                let mut variant_part = VariantPart::default();
+                let mut variants = child.children();
+                while let Some(variant) = variants.next()? {
+                    match variant.entry().tag() {
+                        gimli::DW_TAG_member if Some(variant.entry().offset()) == discriminant => {
+                            variant_part.discriminant = load_member(variant.entry(), unit, dwarf)?;
+                        }
+                        gimli::DW_TAG_variant => {
+                            let value = variant
+                                .entry()
+                                .attr_value(gimli::DW_AT_discr_value)?
+                                .and_then(|value| value.udata_value());
+                            let mut members = variant.children();
+                            while let Some(member) = members.next()? {
+                                if member.entry().tag() != gimli::DW_TAG_member {
+                                    continue;
+                                }
+                                if let Some(member) = load_member(member.entry(), unit, dwarf)? {
+                                    variant_part.variants.push((value, member));
+                                }
+                            }
                         }
-                        path.push(
-                            dwarf
-                                .attr_string(&unit, file.path_name())?
-                                .to_string_lossy()
-                                .as_ref(),
-                        );
+                        _ => {}
                     }
+                }
+                result.variants = Some(variant_part);
+            }
+            _ => {}
+        }
+    }
+    Ok(result)
+}
 
-                    // Get the File
-                    let file = compilation_units
-                        .iter_mut()
-                        .find(|f| f.name == path.as_os_str().to_str().unwrap());
-
-                    // Determine line/column. DWARF line/column is never 0, so we use that
-                    // but other applications may want to display this differently.
-                    let line = row.line().unwrap_or(0);
-
-                    if let Some(file) = file {
-                        file.lines.push(Line {
-                            file: file.name.clone(),
-                            number: line.try_into().unwrap(),
-                            address: row.address().try_into().unwrap(),
-                        });
-                    }
+/// Reads a data member, returning `None` for static ones, which aren't stored in the value.
+fn load_member<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<Option<Member>, Error> {
+    if entry.attr_value(gimli::DW_AT_external)?.is_some()
+        || entry.attr_value(gimli::DW_AT_declaration)?.is_some()
+    {
+        return Ok(None);
+    }
+    let type_offset = match type_offset(entry)? {
+        Some(type_offset) => type_offset,
+        None => return Ok(None),
+    };
+    let offset = match entry.attr_value(gimli::DW_AT_data_member_location)? {
+        Some(value) => value.udata_value().unwrap_or(0).try_into().unwrap(),
+        // Union members all start at the beginning
+        None => 0,
+    };
+    Ok(Some(Member {
+        name: entry_name(entry, unit, dwarf)?.unwrap_or_default(),
+        type_offset,
+        offset,
+    }))
+}
+
+/// Returns the attributes of `entry`, preceded by those of the DIEs it completes: the one it's a
+/// concrete instance of (`DW_AT_abstract_origin`), which is where inlined functions and their
+/// parameters keep their names, types and lines, and the declaration it defines
+/// (`DW_AT_specification`), which is where C++ methods and static members defined outside their
+/// class keep theirs.
+fn attrs_with_origin<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+) -> Result<Vec<gimli::Attribute<R>>, Error> {
+    let mut attrs = Vec::new();
+    if let Some(origin) = origin_offset(entry)? {
+        add_origin_attrs(unit, origin, &mut attrs, 0)?;
+    }
+    let mut entry_attrs = entry.attrs();
+    while let Some(attr) = entry_attrs.next()? {
+        attrs.push(attr);
+    }
+    Ok(attrs)
+}
+
+/// Returns the DIE `entry` is an instance or the definition of, if any.
+fn origin_offset<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+) -> Result<Option<UnitOffset>, Error> {
+    for name in [gimli::DW_AT_abstract_origin, gimli::DW_AT_specification] {
+        if let Some(gimli::AttributeValue::UnitRef(offset)) = entry.attr_value(name)? {
+            return Ok(Some(offset));
+        }
+    }
+    Ok(None)
+}
+
+/// Adds the attributes of the DIE at `offset`, preceded by those of its own origin, to `attrs`.
+/// An inlined C++ method's abstract instance is the definition of a declaration, for example.
+fn add_origin_attrs<R: Reader>(
+    unit: &gimli::Unit<R>,
+    offset: UnitOffset,
+    attrs: &mut Vec<gimli::Attribute<R>>,
+    depth: usize,
+) -> Result<(), Error> {
+    let origin = unit.entry(offset)?;
+    // Bounded, in case of broken debug info with a cycle of origins
+    if depth < 4 {
+        if let Some(next) = origin_offset(&origin)? {
+            add_origin_attrs(unit, next, attrs, depth + 1)?;
+        }
+    }
+    let mut origin_attrs = origin.attrs();
+    while let Some(attr) = origin_attrs.next()? {
+        // The origin says it's inlined or just declared, but the instance has code or storage
+        if attr.name() != gimli::DW_AT_inline && attr.name() != gimli::DW_AT_declaration {
+            attrs.push(attr);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the (link-time) address ranges covered by `entry`'s code.
+fn entry_ranges<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<Vec<(usize, usize)>, Error> {
+    let mut ranges = Vec::new();
+    if let Some(value) = entry.attr_value(gimli::DW_AT_ranges)? {
+        if let Some(mut list) = dwarf.attr_ranges(unit, value)? {
+            while let Some(range) = list.next()? {
+                if range.begin < range.end {
+                    ranges.push((range.begin as usize, range.end as usize));
                 }
             }
         }
+        return Ok(ranges);
     }
-    Ok(compilation_units)
+    let low_pc = match entry.attr_value(gimli::DW_AT_low_pc)? {
+        Some(gimli::AttributeValue::Addr(address)) => address,
+        Some(gimli::AttributeValue::DebugAddrIndex(index)) => dwarf.address(unit, index)?,
+        _ => return Ok(ranges),
+    };
+    let high_pc = match entry.attr_value(gimli::DW_AT_high_pc)? {
+        Some(gimli::AttributeValue::Addr(address)) => address,
+        Some(gimli::AttributeValue::DebugAddrIndex(index)) => dwarf.address(unit, index)?,
+        Some(value) => match value.udata_value() {
+            Some(length) => low_pc + length,
+            None => return Ok(ranges),
+        },
+        None => return Ok(ranges),
+    };
+    if low_pc < high_pc {
+        ranges.push((low_pc as usize, high_pc as usize));
+    }
+    Ok(ranges)
+}
+
+/// Returns the path of file number `index` in the unit's line table.
+fn line_file_name<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    index: u64,
+) -> Result<Option<String>, Error> {
+    let header = match &unit.line_program {
+        Some(program) => program.header(),
+        None => return Ok(None),
+    };
+    let entry = match header.file(index) {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+    let mut path = path::PathBuf::new();
+    if let Some(dir) = entry.directory(header) {
+        path.push(dwarf.attr_string(unit, dir)?.to_string_lossy()?.as_ref());
+    }
+    path.push(
+        dwarf
+            .attr_string(unit, entry.path_name())?
+            .to_string_lossy()?
+            .as_ref(),
+    );
+    Ok(Some(path.to_string_lossy().into_owned()))
+}
+
+/// Returns the paths of the files in the unit's line table, leaving out any we can't read.
+fn line_table_files<R: Reader>(unit: &gimli::Unit<R>, dwarf: &gimli::Dwarf<R>) -> Vec<String> {
+    let header = match &unit.line_program {
+        Some(program) => program.header(),
+        None => return Vec::new(),
+    };
+    // DWARF 5 numbers files from 0, earlier versions from 1
+    let first = if header.version() >= 5 { 0 } else { 1 };
+    let mut files = Vec::new();
+    for index in first..first + header.file_names().len() as u64 {
+        if let Ok(Some(path)) = line_file_name(unit, dwarf, index) {
+            if !files.contains(&path) {
+                files.push(path);
+            }
+        }
+    }
+    files
+}
+
+/// Returns the bytes of a `DW_AT_const_value`, little-endian for numbers.
+fn constant_value<R: Reader>(value: gimli::AttributeValue<R>) -> Option<Vec<u8>> {
+    match value {
+        gimli::AttributeValue::Block(block) => Some(block.to_slice().ok()?.into_owned()),
+        gimli::AttributeValue::Sdata(value) => Some(value.to_le_bytes().to_vec()),
+        value => Some(value.udata_value()?.to_le_bytes().to_vec()),
+    }
+}
+
+/// Adds the rows of `unit`'s line number program that are for its main source file to `file`.
+fn load_lines<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    file: &mut File,
+) -> Result<(), Error> {
+    let program = match unit.line_program.clone() {
+        Some(program) => program,
+        None => return Ok(()),
+    };
+    // Rows for code from headers (inline functions, say) are kept too, under the header's path
+    let mut paths = HashMap::new();
+    let mut rows = program.rows();
+    while let Some((_, row)) = rows.next_row()? {
+        if row.end_sequence() {
+            continue;
+        }
+        if let Entry::Vacant(entry) = paths.entry(row.file_index()) {
+            entry.insert(
+                line_file_name(unit, dwarf, row.file_index())?.unwrap_or_else(|| file.name.clone()),
+            );
+        }
+        // DWARF line numbers are never 0, so 0 means we don't know
+        let line = row.line().unwrap_or(0);
+        file.lines.push(Line {
+            file: paths[&row.file_index()].clone(),
+            number: line.try_into().unwrap(),
+            address: row.address().try_into().unwrap(),
+        });
+    }
+    Ok(())
 }
 
 #[derive(Debug, Clone)]
@@ -290,25 +1399,189 @@ impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where
 
 trait Reader: gimli::Reader<Offset = usize> + Send + Sync {}
 
-fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<Location> {
-    if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
-        let encoding = unit.encoding();
-        let mut pc = data.0.clone();
-        if pc.len() > 0 {
-            if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
-                match op {
-                    gimli::Operation::FrameOffset { offset } => {
-                        return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
-                    }
-                    gimli::Operation::Address { address } => {
-                        return Some(Location::Address(address.try_into().unwrap()));
-                    }
-                    _ => {}
-                }
+/// Reads a `DW_AT_location` or `DW_AT_frame_base`: an expression, or a reference to a location
+/// list. `gnu_loc_dwo` is the `.debug_loc.dwo` of a pre-DWARF 5 split unit.
+fn get_location<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    gnu_loc_dwo: Option<&R>,
+) -> Result<Option<Location>, Error> {
+    if let gimli::AttributeValue::Exprloc(expression) = attr.value() {
+        // The common cases (locals at an offset from the frame base, and globals at a fixed
+        // address) don't need the evaluator
+        match single_operation(&expression, unit.encoding()) {
+            Some(gimli::Operation::FrameOffset { offset }) => {
+                return Ok(Some(Location::FramePointerOffset(
+                    offset.try_into().unwrap(),
+                )));
+            }
+            Some(gimli::Operation::Address { address }) => {
+                return Ok(Some(Location::Address(address.try_into().unwrap())));
+            }
+            Some(gimli::Operation::AddressIndex { index }) => {
+                let address = dwarf.address(unit, index)?;
+                return Ok(Some(Location::Address(address.try_into().unwrap())));
+            }
+            _ => {}
+        }
+        let expression = copy_expression(expression.0, unit, dwarf)?;
+        // A lone DW_OP_GNU_addr_index, which copying turned into DW_OP_addrx
+        if let ([opcode, index @ ..], [(_, address)]) = (
+            expression.bytecode.as_slice(),
+            expression.addresses.as_slice(),
+        ) {
+            if *opcode == gimli::DW_OP_addrx.0 && index.len() == uleb128_length(index) {
+                return Ok(Some(Location::Address((*address).try_into().unwrap())));
+            }
+        }
+        return Ok(Some(Location::Expression(expression)));
+    }
+    // gimli would read .debug_loc.dwo as if it were .debug_loc, which it isn't
+    if let Some(loc_dwo) = gnu_loc_dwo {
+        let offset = match attr.value() {
+            gimli::AttributeValue::LocationListsRef(offset) => offset.0,
+            gimli::AttributeValue::SecOffset(offset) => offset,
+            _ => return Ok(None),
+        };
+        return Ok(Some(Location::List(gnu_split_locations(
+            loc_dwo, offset, unit, dwarf,
+        )?)));
+    }
+    let mut locations = match dwarf.attr_locations(unit, attr.value())? {
+        Some(locations) => locations,
+        None => return Ok(None),
+    };
+    let mut entries = Vec::new();
+    while let Some(entry) = locations.next()? {
+        entries.push((
+            entry.range.begin.try_into().unwrap(),
+            entry.range.end.try_into().unwrap(),
+            copy_expression(entry.data.0, unit, dwarf)?,
+        ));
+    }
+    Ok(Some(Location::List(entries)))
+}
+
+/// Reads the location list at `offset` in `.debug_loc.dwo`, in the format GCC used for split
+/// DWARF before DWARF 5: each entry is a kind byte, then its start and end as indexes into
+/// `.debug_addr` (or a start index and a 4-byte length), then an expression with a 2-byte length.
+fn gnu_split_locations<R: Reader>(
+    loc_dwo: &R,
+    offset: usize,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<Vec<(usize, usize, Expression)>, Error> {
+    const DW_LLE_GNU_END_OF_LIST_ENTRY: u8 = 0;
+    const DW_LLE_GNU_BASE_ADDRESS_SELECTION_ENTRY: u8 = 1;
+    const DW_LLE_GNU_START_END_ENTRY: u8 = 2;
+    const DW_LLE_GNU_START_LENGTH_ENTRY: u8 = 3;
+    let address = |index: u64| -> Result<usize, Error> {
+        let address = dwarf.address(unit, gimli::DebugAddrIndex(index as usize))?;
+        Ok(address as usize)
+    };
+    let mut input = loc_dwo.clone();
+    input.skip(offset)?;
+    let mut entries = Vec::new();
+    loop {
+        let (start, end) = match input.read_u8()? {
+            DW_LLE_GNU_END_OF_LIST_ENTRY => break,
+            // Only matters for offset pairs, which GCC never emitted here
+            DW_LLE_GNU_BASE_ADDRESS_SELECTION_ENTRY => {
+                input.read_uleb128()?;
+                continue;
+            }
+            DW_LLE_GNU_START_END_ENTRY => {
+                let start = address(input.read_uleb128()?)?;
+                (start, address(input.read_uleb128()?)?)
+            }
+            DW_LLE_GNU_START_LENGTH_ENTRY => {
+                let start = address(input.read_uleb128()?)?;
+                let length = input.read_u32()? as usize;
+                (
+                    start,
+                    start
+                        .checked_add(length)
+                        .ok_or(gimli::Error::InvalidAddressRange)?,
+                )
+            }
+            _ => return Err(gimli::Error::InvalidAddressRange.into()),
+        };
+        let length = input.read_u16()?;
+        let bytecode = input.split(length as usize)?;
+        entries.push((start, end, copy_expression(bytecode, unit, dwarf)?));
+    }
+    Ok(entries)
+}
+
+/// Returns the operation in `expression` if there is exactly one.
+fn single_operation<R: Reader>(
+    expression: &gimli::Expression<R>,
+    encoding: gimli::Encoding,
+) -> Option<gimli::Operation<R>> {
+    let mut pc = expression.0.clone();
+    let operation = gimli::Operation::parse(&mut pc, encoding).ok()?;
+    if pc.is_empty() {
+        Some(operation)
+    } else {
+        None
+    }
+}
+
+/// Returns the number of bytes in the ULEB128 number at the start of `bytes`.
+fn uleb128_length(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .position(|byte| byte & 0x80 == 0)
+        .map_or(bytes.len(), |end| end + 1)
+}
+
+/// Copies an expression out of `unit` so that it can be evaluated once the unit is gone, looking
+/// up the addresses it refers to by index while we still can.
+fn copy_expression<R: Reader>(
+    bytecode: R,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<Expression, Error> {
+    let mut copy = bytecode.to_slice()?.into_owned();
+    let mut addresses = Vec::new();
+    let mut pc = bytecode;
+    while !pc.is_empty() {
+        let offset = copy.len() - pc.len();
+        // gimli doesn't know the GNU split DWARF spellings of DW_OP_addrx and DW_OP_constx,
+        // which take the same operand
+        let gnu_opcode = match copy[offset] {
+            DW_OP_GNU_ADDR_INDEX => Some(gimli::DW_OP_addrx),
+            DW_OP_GNU_CONST_INDEX => Some(gimli::DW_OP_constx),
+            _ => None,
+        };
+        if let Some(opcode) = gnu_opcode {
+            copy[offset] = opcode.0;
+        }
+        let mut op_pc = pc.clone();
+        if gnu_opcode.is_some() {
+            op_pc.read_u8()?;
+            let index = op_pc.read_uleb128()?;
+            let address = dwarf.address(unit, gimli::DebugAddrIndex(index.try_into().unwrap()))?;
+            addresses.push((index, address));
+            pc = op_pc;
+            continue;
+        }
+        match gimli::Operation::parse(&mut pc, unit.encoding()) {
+            Ok(gimli::Operation::AddressIndex { index })
+            | Ok(gimli::Operation::ConstantIndex { index }) => {
+                addresses.push((index.0 as u64, dwarf.address(unit, index)?));
             }
+            Ok(_) => {}
+            // Leave operations we can't parse for the evaluator to fail on
+            Err(_) => break,
         }
     }
-    None
+    Ok(Expression {
+        bytecode: copy,
+        encoding: unit.encoding(),
+        addresses,
+    })
 }
 
 // based on dwarf_dump.rs
@@ -349,6 +1622,17 @@ fn get_attr_value<R: Reader>(
             dump_file_index(w, value, unit, dwarf)?;
             Ok(DebugValue::Str(w.to_string()))
         }
+        // DWARF 5 refers to strings and addresses by index (DW_FORM_strx and DW_FORM_addrx), so
+        // that split units don't need relocations
+        gimli::AttributeValue::DebugStrOffsetsIndex(_) => Ok(DebugValue::Str(
+            dwarf
+                .attr_string(unit, attr.value())?
+                .to_string_lossy()?
+                .into_owned(),
+        )),
+        gimli::AttributeValue::DebugAddrIndex(index) => {
+            Ok(DebugValue::Uint(dwarf.address(unit, index)?))
+        }
         gimli::AttributeValue::DebugLineStrRef(offset) => {
             if let Ok(s) = dwarf.debug_line_str.get_str(offset) {
                 Ok(DebugValue::Str(format!("{}", s.to_string_lossy()?)))
@@ -0,0 +1,356 @@
+//! Saves the indexes that `DwarfData` builds at startup so that the next time we debug the same
+//! binary we can skip reading the units: the unit summaries, which give the address ranges and
+//! the source files with lines in each unit, and the name index of functions and variables.
+//! Caches live in `$XDG_CACHE_HOME/deet`, one per binary, named after its build ID. A cache is
+//! thrown away when any file the indexes were read from (the binary, its separate debug file,
+//! its `.dwp` or one of its `.dwo` files) has been modified since it was written.
+
+use crate::debug_file;
+use crate::gimli_wrapper::UnitSummary;
+use crate::name_index::NameIndex;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::UNIX_EPOCH;
+
+/// Start of every cache file. Bump the number when the format changes.
+const MAGIC: &[u8] = b"deet index 6\0";
+
+/// Where the cache for a binary is, and what the files its debug info came from must look like
+/// for the cache to be valid.
+pub struct CacheKey {
+    path: PathBuf,
+    /// Modification times of the files, in seconds and nanoseconds since the epoch
+    mtimes: Vec<(u64, u32)>,
+}
+
+impl CacheKey {
+    /// Returns the key for `object`, whose debug info comes from `files`: the binary, then the
+    /// separate debug file and `.dwp` package if there are any. The `.dwo` files of split units
+    /// are checked separately, since we only know which they are once we've read the units.
+    pub fn new(object: &object::File, files: &[&Path]) -> Option<CacheKey> {
+        let name = match debug_file::build_id(object) {
+            Some(build_id) => build_id.iter().map(|b| format!("{:02x}", b)).collect(),
+            // Without a build ID, we keep one cache per path
+            None => {
+                let mut hasher = DefaultHasher::new();
+                fs::canonicalize(files.first()?).ok()?.hash(&mut hasher);
+                format!("path-{:016x}", hasher.finish())
+            }
+        };
+        let mtimes = files
+            .iter()
+            .map(|file| mtime(file))
+            .collect::<Option<Vec<_>>>()?;
+        Some(CacheKey {
+            path: debug_file::cache_directory()?
+                .join("deet")
+                .join(format!("{}.index", name)),
+            mtimes,
+        })
+    }
+}
+
+/// Returns when `path` was last modified, in seconds and nanoseconds since the epoch.
+fn mtime(path: &Path) -> Option<(u64, u32)> {
+    let mtime = fs::metadata(path).ok()?.modified().ok()?;
+    let mtime = mtime.duration_since(UNIX_EPOCH).ok()?;
+    Some((mtime.as_secs(), mtime.subsec_nanos()))
+}
+
+/// Reads the cached indexes for `key`, if there are any and they're still valid.
+pub fn load(key: &CacheKey) -> Option<(Vec<UnitSummary>, NameIndex)> {
+    let data = fs::read(&key.path).ok()?;
+    let mut reader = Reader {
+        data: &data,
+        offset: 0,
+    };
+    if reader.bytes(MAGIC.len())? != MAGIC {
+        return None;
+    }
+    let mut mtimes = Vec::new();
+    for _ in 0..reader.u64()? {
+        mtimes.push((reader.u64()?, reader.u32()?));
+    }
+    if mtimes != key.mtimes {
+        return None;
+    }
+
+    let mut units = Vec::new();
+    for _ in 0..reader.u64()? {
+        let offset = reader.u64()? as usize;
+        let name = match reader.u8()? {
+            0 => None,
+            _ => Some(reader.string()?),
+        };
+        let mut ranges = Vec::new();
+        for _ in 0..reader.u64()? {
+            ranges.push((reader.u64()? as usize, reader.u64()? as usize));
+        }
+        let dwo_path = match reader.u8()? {
+            0 => None,
+            _ => {
+                let path = reader.string()?;
+                let dwo_mtime = (reader.u64()?, reader.u32()?);
+                // A .dwo file that has gone missing doesn't invalidate the cache, since we
+                // didn't read anything from it either
+                if mtime(Path::new(&path)).unwrap_or((0, 0)) != dwo_mtime {
+                    return None;
+                }
+                Some(path)
+            }
+        };
+        let mut files = Vec::new();
+        for _ in 0..reader.u64()? {
+            files.push(reader.string()?);
+        }
+        units.push(UnitSummary {
+            offset,
+            name,
+            ranges,
+            dwo_path,
+            files,
+        });
+    }
+    let names = NameIndex {
+        functions: reader.names()?,
+        variables: reader.names()?,
+        units: reader.offsets()?.into_iter().collect(),
+    };
+    Some((units, names))
+}
+
+/// Writes the indexes for `key`. Failing to is fine: we'll just build them again next time.
+pub fn save(key: &CacheKey, units: &[UnitSummary], names: &NameIndex) {
+    let mut data = MAGIC.to_vec();
+    put_u64(&mut data, key.mtimes.len() as u64);
+    for (secs, nanos) in &key.mtimes {
+        put_u64(&mut data, *secs);
+        data.extend_from_slice(&nanos.to_le_bytes());
+    }
+
+    put_u64(&mut data, units.len() as u64);
+    for unit in units {
+        put_u64(&mut data, unit.offset as u64);
+        match &unit.name {
+            Some(name) => {
+                data.push(1);
+                put_str(&mut data, name);
+            }
+            None => data.push(0),
+        }
+        put_u64(&mut data, unit.ranges.len() as u64);
+        for (start, end) in &unit.ranges {
+            put_u64(&mut data, *start as u64);
+            put_u64(&mut data, *end as u64);
+        }
+        match &unit.dwo_path {
+            Some(path) => {
+                data.push(1);
+                put_str(&mut data, path);
+                let (secs, nanos) = mtime(Path::new(path)).unwrap_or((0, 0));
+                put_u64(&mut data, secs);
+                data.extend_from_slice(&nanos.to_le_bytes());
+            }
+            None => data.push(0),
+        }
+        put_u64(&mut data, unit.files.len() as u64);
+        for file in &unit.files {
+            put_str(&mut data, file);
+        }
+    }
+    put_names(&mut data, &names.functions);
+    put_names(&mut data, &names.variables);
+    put_offsets(&mut data, &names.units.iter().copied().collect::<Vec<_>>());
+
+    // Write to a temporary file and rename it, so that another deet reading the cache never
+    // sees half of it
+    let dir = match key.path.parent() {
+        Some(dir) => dir,
+        None => return,
+    };
+    let temp = dir.join(format!(".{}.tmp", process::id()));
+    if fs::create_dir_all(dir).is_err() {
+        return;
+    }
+    // A failed write can still leave part of the file behind
+    if fs::write(&temp, &data).is_err() || fs::rename(&temp, &key.path).is_err() {
+        fs::remove_file(&temp).ok();
+    }
+}
+
+fn put_u64(data: &mut Vec<u8>, value: u64) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_str(data: &mut Vec<u8>, s: &str) {
+    put_u64(data, s.len() as u64);
+    data.extend_from_slice(s.as_bytes());
+}
+
+fn put_offsets(data: &mut Vec<u8>, offsets: &[usize]) {
+    put_u64(data, offsets.len() as u64);
+    for offset in offsets {
+        put_u64(data, *offset as u64);
+    }
+}
+
+fn put_names(data: &mut Vec<u8>, names: &HashMap<String, Vec<usize>>) {
+    put_u64(data, names.len() as u64);
+    for (name, offsets) in names {
+        put_str(data, name);
+        put_offsets(data, offsets);
+    }
+}
+
+/// Reads what the `put_*` functions wrote, returning `None` if the data ends too early.
+struct Reader<'data> {
+    data: &'data [u8],
+    offset: usize,
+}
+
+impl<'data> Reader<'data> {
+    fn bytes(&mut self, len: usize) -> Option<&'data [u8]> {
+        let bytes = self.data.get(self.offset..self.offset.checked_add(len)?)?;
+        self.offset += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u64()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec()).ok()
+    }
+
+    fn offsets(&mut self) -> Option<Vec<usize>> {
+        let mut offsets = Vec::new();
+        for _ in 0..self.u64()? {
+            offsets.push(self.u64()? as usize);
+        }
+        Some(offsets)
+    }
+
+    fn names(&mut self) -> Option<HashMap<String, Vec<usize>>> {
+        let mut names = HashMap::new();
+        for _ in 0..self.u64()? {
+            names.insert(self.string()?, self.offsets()?);
+        }
+        Some(names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a key for a cache in a fresh directory of its own.
+    fn test_key(test: &str, mtimes: Vec<(u64, u32)>) -> CacheKey {
+        let dir = std::env::temp_dir().join(format!("deet-index-cache-{}-{}", test, process::id()));
+        fs::remove_dir_all(&dir).ok();
+        CacheKey {
+            path: dir.join("test.index"),
+            mtimes,
+        }
+    }
+
+    fn test_indexes(dwo_path: Option<String>) -> (Vec<UnitSummary>, NameIndex) {
+        let units = vec![
+            UnitSummary {
+                offset: 0,
+                name: Some("main.c".to_string()),
+                ranges: vec![(0x1000, 0x1100), (0x2000, 0x2010)],
+                dwo_path,
+                files: vec!["/src/main.c".to_string(), "/src/util.h".to_string()],
+            },
+            UnitSummary {
+                offset: 0x80,
+                name: None,
+                ranges: Vec::new(),
+                dwo_path: None,
+                files: Vec::new(),
+            },
+        ];
+        let mut names = NameIndex::default();
+        names.add_function("main".to_string(), 0);
+        names.add_function("helper".to_string(), 0x80);
+        names.add_variable("counter".to_string(), 0);
+        names.units.insert(0);
+        (units, names)
+    }
+
+    #[test]
+    fn round_trip() {
+        let key = test_key("round-trip", vec![(1_600_000_000, 5), (1_600_000_001, 0)]);
+        let (units, names) = test_indexes(None);
+        save(&key, &units, &names);
+
+        let (loaded_units, loaded_names) = load(&key).unwrap();
+        assert_eq!(loaded_units, units);
+        assert_eq!(loaded_names.functions, names.functions);
+        assert_eq!(loaded_names.variables, names.variables);
+        assert_eq!(loaded_names.units, names.units);
+        fs::remove_dir_all(key.path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn modified_file_invalidates_cache() {
+        let key = test_key("modified", vec![(1_600_000_000, 5)]);
+        let (units, names) = test_indexes(None);
+        save(&key, &units, &names);
+
+        let rebuilt = CacheKey {
+            path: key.path.clone(),
+            mtimes: vec![(1_600_000_000, 6)],
+        };
+        assert!(load(&rebuilt).is_none());
+        let with_debug_file = CacheKey {
+            path: key.path.clone(),
+            mtimes: vec![(1_600_000_000, 5), (1_600_000_000, 5)],
+        };
+        assert!(load(&with_debug_file).is_none());
+        assert!(load(&key).is_some());
+        fs::remove_dir_all(key.path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn new_dwo_file_invalidates_cache() {
+        let key = test_key("dwo", vec![(1_600_000_000, 0)]);
+        let dir = key.path.parent().unwrap();
+        let dwo_path = dir.join("main.dwo");
+        let (units, names) = test_indexes(Some(dwo_path.to_string_lossy().into_owned()));
+        // The .dwo file doesn't exist yet when the cache is written
+        save(&key, &units, &names);
+        assert!(load(&key).is_some());
+
+        fs::write(&dwo_path, b"").unwrap();
+        assert!(load(&key).is_none());
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn truncated_cache_is_ignored() {
+        let key = test_key("truncated", vec![(1_600_000_000, 0)]);
+        let (units, names) = test_indexes(None);
+        save(&key, &units, &names);
+
+        let data = fs::read(&key.path).unwrap();
+        fs::write(&key.path, &data[..data.len() - 1]).unwrap();
+        assert!(load(&key).is_none());
+        fs::remove_dir_all(key.path.parent().unwrap()).ok();
+    }
+}
@@ -5,12 +5,21 @@ use nix::sys::signal;
 use nix::sys::signal::Signal::SIGTRAP;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
+use std::convert::TryInto;
 use std::mem::size_of;
 use std::os::unix::process::CommandExt;
 use std::process::Child;
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
 
-use crate::dwarf_data::DwarfData;
+use crate::core_file::{CoreDump, CoreThread, ProcessInfo};
+use crate::dwarf_data::{DwarfData, FrameRegisters};
+use crate::memory_map;
+use crate::siginfo::SigInfo;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileExt;
 
 #[derive(Debug)]
 pub enum Status {
@@ -26,6 +35,164 @@ pub enum Status {
     Signaled(signal::Signal),
 }
 
+/// Longest string `Target::read_string` will read.
+const MAX_STRING_LENGTH: usize = 4096;
+
+/// Most frames `Target::print_backtrace` will print, in case the stack is corrupt.
+const MAX_BACKTRACE_DEPTH: usize = 256;
+
+/// Pid of the inferior while it is running (between `PTRACE_CONT` and the matching `waitpid`), or 0
+/// otherwise. The SIGINT handler reads it, so it has to be a plain atomic.
+static RUNNING_PID: AtomicI32 = AtomicI32::new(0);
+
+/// Set by the SIGINT handler when the user presses ctrl+c while the inferior is running.
+static INTERRUPT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// SIGINT handler for the debugger process. If the inferior is running, this makes it stop so that
+/// we get back to the prompt instead of the signal killing it.
+///
+/// When the inferior shares our process group (the usual case), the terminal has already sent it
+/// the SIGINT and ptrace will report that as a stop, so there is nothing left to do. Otherwise
+/// (e.g. the inferior called setsid), we forward a SIGSTOP ourselves.
+pub extern "C" fn handle_sigint(_: libc::c_int) {
+    let pid = RUNNING_PID.load(Ordering::SeqCst);
+    if pid <= 0 {
+        return;
+    }
+    INTERRUPT_REQUESTED.store(true, Ordering::SeqCst);
+    // Only async-signal-safe calls in here, so go straight to libc
+    unsafe {
+        if libc::getpgid(pid) != libc::getpgrp() {
+            libc::kill(pid, libc::SIGSTOP);
+        }
+    }
+}
+
+/// Something whose registers and memory we can inspect: either a live inferior or a core file.
+pub trait Target {
+    /// Reads `len` bytes of memory starting at `addr`.
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error>;
+
+    fn get_registers(&self) -> Result<libc::user_regs_struct, nix::Error>;
+
+    /// Returns the signal information for the signal that last stopped the process.
+    fn get_siginfo(&self) -> Result<SigInfo, nix::Error>;
+
+    /// Returns the raw auxiliary vector the kernel passed to the process.
+    fn get_auxv(&self) -> Result<Vec<u8>, nix::Error>;
+
+    /// Returns how far the executable was loaded from the addresses it was linked at. This is 0
+    /// for non-PIE executables. We compare the entry point the kernel reports in the auxiliary
+    /// vector (AT_ENTRY) with the one in the ELF header.
+    fn load_bias(&self, debug_data: &DwarfData) -> usize {
+        self.get_auxv()
+            .ok()
+            .and_then(|auxv| auxv_value(&auxv, libc::AT_ENTRY))
+            .map_or(0, |entry| (entry as usize).wrapping_sub(debug_data.entry()))
+    }
+
+    /// Reads one machine word at `addr`.
+    fn read_word(&self, addr: usize) -> Result<usize, nix::Error> {
+        let bytes = self.read_memory(addr, size_of::<usize>())?;
+        Ok(usize::from_ne_bytes(bytes.as_slice().try_into().unwrap()))
+    }
+
+    /// Reads a NUL-terminated string at `addr`.
+    fn read_string(&self, addr: usize) -> Result<String, nix::Error> {
+        let mut bytes = Vec::new();
+        while bytes.len() < MAX_STRING_LENGTH {
+            let word = self.read_memory(addr + bytes.len(), size_of::<usize>())?;
+            match word.iter().position(|b| *b == 0) {
+                Some(end) => {
+                    bytes.extend_from_slice(&word[..end]);
+                    break;
+                }
+                None => bytes.extend_from_slice(&word),
+            }
+        }
+        Ok(String::from_utf8_lossy(&bytes).to_string())
+    }
+
+    /// Works out the registers of the caller of `frame`, where `pc` is the address to look up
+    /// its CFI by (see `print_backtrace`). Without CFI we follow the frame pointer.
+    fn unwind(
+        &self,
+        modules: &[&DwarfData],
+        pc: usize,
+        frame: &FrameRegisters,
+    ) -> Option<FrameRegisters> {
+        let read_word = |addr| self.read_word(addr).ok();
+        let module = modules.iter().find(|module| module.contains(pc));
+        match module.and_then(|module| module.unwind_frame(pc, frame, &read_word)) {
+            Some(caller) => Some(caller),
+            None => Some(FrameRegisters {
+                rip: read_word(frame.rbp.checked_add(8)?)?,
+                rsp: frame.rbp.checked_add(16)?,
+                rbp: read_word(frame.rbp)?,
+                // Without CFI, we can't tell whether the function saved them somewhere
+                callee_saved: [None; 5],
+            }),
+        }
+    }
+
+    /// Walks the stack and prints one line per frame, stopping at `main` or when we can't unwind
+    /// any further. `modules` are the executable and the shared libraries loaded with it.
+    ///
+    /// Each frame is unwound using the call frame information of the module its code belongs to,
+    /// which works for code built without frame pointers (like most of libc). Without CFI we fall
+    /// back to following the frame pointer chain.
+    fn print_backtrace(&self, modules: &[&DwarfData]) -> Result<(), nix::Error> {
+        let regs = self.get_registers()?;
+        let mut frame = FrameRegisters::from_registers(&regs);
+        // For the innermost frame, rip is the next instruction to run. For the others, it's a
+        // return address, which can belong to the line (or even function) after the call, so we
+        // look up the byte before it instead.
+        let mut pc = frame.rip;
+        for _ in 0..MAX_BACKTRACE_DEPTH {
+            // Functions inlined into this one get frames of their own
+            let functions = modules
+                .iter()
+                .find(|module| module.contains(pc))
+                .map_or_else(Vec::new, |module| module.get_frames_from_addr(pc));
+            for (f, line) in &functions {
+                match line {
+                    Some(line) => println!("{} ({})", f, line),
+                    None => println!("{} ({:#x})", f, frame.rip),
+                }
+            }
+            match functions.last() {
+                Some((f, _)) if f == "main" => break,
+                Some(_) => {}
+                None => println!("?? ({:#x})", frame.rip),
+            }
+            let caller = match self.unwind(modules, pc, &frame) {
+                Some(caller) => caller,
+                None => break,
+            };
+            // The stack grows down, so callers' frames must be at higher addresses
+            if caller.rip == 0 || caller.rsp <= frame.rsp {
+                break;
+            }
+            frame = caller;
+            pc = frame.rip - 1;
+        }
+        Ok(())
+    }
+}
+
+/// Looks up `key` (one of the `AT_*` constants) in a raw auxiliary vector.
+pub fn auxv_value(auxv: &[u8], key: u64) -> Option<u64> {
+    auxv.chunks_exact(16)
+        .map(|entry| {
+            (
+                u64::from_ne_bytes(entry[..8].try_into().unwrap()),
+                u64::from_ne_bytes(entry[8..].try_into().unwrap()),
+            )
+        })
+        .find(|(entry_key, _)| *entry_key == key)
+        .map(|(_, value)| value)
+}
+
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
 /// pre_exec with Command to call this in the child process.
 fn child_traceme() -> Result<(), std::io::Error> {
@@ -35,12 +202,14 @@ fn child_traceme() -> Result<(), std::io::Error> {
 
 pub struct Inferior {
     child: Child,
+    /// Maps the address of each breakpoint we've inserted to the byte that the `0xcc` replaced.
+    breakpoints: HashMap<usize, u8>,
 }
 
 impl Inferior {
     /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
     /// an error is encountered.
-    pub fn new(target: &str, args: &Vec<String>, breakpoints: &[usize]) -> Option<Inferior> {
+    pub fn new(target: &str, args: &Vec<String>) -> Option<Inferior> {
         let mut cmd = Command::new(target);
         unsafe {
             cmd.pre_exec(child_traceme);
@@ -49,19 +218,12 @@ impl Inferior {
             Ok(c) => c,
             Err(e) => panic!("{}", e),
         };
-        let mut inferior = Inferior { child };
+        let inferior = Inferior {
+            child,
+            breakpoints: HashMap::new(),
+        };
         match inferior.wait(None) {
-            Ok(status) => {
-                if let Status::Stopped(sig, _) = status {
-                    if sig == SIGTRAP {
-                        println!("{sig} detected. setting breakpoints if any...");
-                        for addr in breakpoints {
-                            inferior.set_breakpoint(*addr).unwrap();
-                        }
-                    }
-                }
-                Some(inferior)
-            }
+            Ok(_) => Some(inferior),
             Err(e) => {
                 println!("E: {e:?}");
                 None
@@ -70,7 +232,31 @@ impl Inferior {
     }
 
     pub fn set_breakpoint(&mut self, addr: usize) -> Result<u8, nix::Error> {
-        self.write_byte(addr, 0xcc)
+        if let Some(orig_byte) = self.breakpoints.get(&addr) {
+            return Ok(*orig_byte);
+        }
+        let orig_byte = self.write_byte(addr, 0xcc)?;
+        self.breakpoints.insert(addr, orig_byte);
+        Ok(orig_byte)
+    }
+
+    /// Returns `true` if we've inserted a breakpoint at `addr`.
+    pub fn has_breakpoint(&self, addr: usize) -> bool {
+        self.breakpoints.contains_key(&addr)
+    }
+
+    /// Takes out the breakpoint at `addr`, putting back the byte it replaced.
+    pub fn remove_breakpoint(&mut self, addr: usize) -> Result<(), nix::Error> {
+        if let Some(orig_byte) = self.breakpoints.remove(&addr) {
+            self.write_byte(addr, orig_byte)?;
+        }
+        Ok(())
+    }
+
+    /// Stops tracking the breakpoint at `addr` without restoring the original byte. This is for
+    /// breakpoints in code that has been unmapped (e.g. by `dlclose`), which we can't write to.
+    pub fn forget_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.remove(&addr);
     }
 
     /// Returns the pid of this inferior.
@@ -101,48 +287,169 @@ impl Inferior {
         })
     }
 
-    pub fn cont(&self) -> Result<Status, nix::Error> {
+    /// Continues the inferior and waits for it to stop or exit. Any signal that stopped the
+    /// inferior (including the SIGINT/SIGSTOP from a ctrl+c) is suppressed rather than delivered.
+    pub fn cont(&mut self) -> Result<Status, nix::Error> {
+        if let Some(status) = self.step_over_breakpoint()? {
+            return Ok(status);
+        }
+        INTERRUPT_REQUESTED.store(false, Ordering::SeqCst);
         ptrace::cont(self.pid(), None)?;
-        self.wait(None)
+        RUNNING_PID.store(self.pid().as_raw(), Ordering::SeqCst);
+        let status = self.wait(None);
+        RUNNING_PID.store(0, Ordering::SeqCst);
+        match status? {
+            // After hitting one of our breakpoints, rip points just past the 0xcc. Move it back so
+            // that it points at the breakpoint's instruction.
+            Status::Stopped(SIGTRAP, rip)
+                if self.breakpoints.contains_key(&rip.wrapping_sub(1)) =>
+            {
+                let mut regs = ptrace::getregs(self.pid())?;
+                regs.rip = (rip - 1) as u64;
+                ptrace::setregs(self.pid(), regs)?;
+                Ok(Status::Stopped(SIGTRAP, rip - 1))
+            }
+            status => Ok(status),
+        }
     }
 
-    pub fn kill(&mut self) -> Result<Status, nix::Error> {
-        println!("killing running inferior (pid {})", self.pid());
-        match self.child.kill() {
-            Ok(_) => self.wait(None),
-            _ => Err(nix::Error::ECHILD),
+    /// If the inferior is stopped on one of our breakpoints, executes the original instruction
+    /// and then puts the breakpoint back, so that continuing doesn't trap straight away. Returns
+    /// the status if the inferior exited or was stopped by some other signal while doing so.
+    fn step_over_breakpoint(&mut self) -> Result<Option<Status>, nix::Error> {
+        let rip = ptrace::getregs(self.pid())?.rip as usize;
+        let orig_byte = match self.breakpoints.get(&rip) {
+            Some(orig_byte) => *orig_byte,
+            None => return Ok(None),
+        };
+        self.write_byte(rip, orig_byte)?;
+        ptrace::step(self.pid(), None)?;
+        match self.wait(None)? {
+            // The step finished
+            Status::Stopped(SIGTRAP, _) => {
+                self.write_byte(rip, 0xcc)?;
+                Ok(None)
+            }
+            // A signal arrived (or the instruction faulted) first, which the caller should hear
+            // about
+            status @ Status::Stopped(..) => {
+                self.write_byte(rip, 0xcc)?;
+                Ok(Some(status))
+            }
+            status => Ok(Some(status)),
+        }
+    }
+
+    /// Returns `true` if the last stop was caused by the user pressing ctrl+c.
+    pub fn was_interrupted(status: &Status) -> bool {
+        match status {
+            Status::Stopped(signal::Signal::SIGINT, _) => true,
+            Status::Stopped(signal::Signal::SIGSTOP, _) => {
+                INTERRUPT_REQUESTED.load(Ordering::SeqCst)
+            }
+            _ => false,
         }
     }
 
-    pub fn print_backtrace(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
-        let regs = ptrace::getregs(self.pid())?;
-        let mut current_stack_frame = regs.rbp as usize;
-        // let stack_pointer = regs.rsp as usize;
-        let mut instruction_ptr = regs.rip as usize;
-        loop {
-            let func_name = debug_data.get_function_from_addr(instruction_ptr);
-            match &func_name {
-                Some(f) => {
-                    println!(
-                        "{} ({})",
-                        f,
-                        debug_data.get_line_from_addr(instruction_ptr).unwrap() // danger of panicing at runtime
-                    );
+    /// Writes an ELF core file of the (stopped) inferior to `path`, with the original bytes in
+    /// place of any breakpoints. We only trace the main thread, so that is the only one dumped.
+    pub fn gcore(&self, path: &str) -> io::Result<()> {
+        let pid = self.pid();
+        let regs = ptrace::getregs(pid)?;
+        let mut fpregs: libc::user_fpregs_struct = unsafe { std::mem::zeroed() };
+        let fpregs_ok = unsafe {
+            libc::ptrace(
+                libc::PTRACE_GETFPREGS,
+                pid.as_raw(),
+                std::ptr::null_mut::<libc::c_void>(),
+                &mut fpregs as *mut libc::user_fpregs_struct as *mut libc::c_void,
+            )
+        } == 0;
+        let fpregs = if fpregs_ok {
+            Some(
+                unsafe {
+                    std::slice::from_raw_parts(
+                        &fpregs as *const libc::user_fpregs_struct as *const u8,
+                        size_of::<libc::user_fpregs_struct>(),
+                    )
                 }
-                None => break,
+                .to_vec(),
+            )
+        } else {
+            None
+        };
+        let siginfo = self.get_siginfo().ok();
+
+        // /proc/<pid>/stat looks like "pid (comm) state ppid pgrp session ..."
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+        let comm_end = stat.rfind(')').unwrap_or(0);
+        let fname = stat[stat.find('(').map_or(0, |i| i + 1)..comm_end].to_string();
+        let fields: Vec<&str> = stat[comm_end + 1..].split_whitespace().collect();
+        let field = |i: usize| fields.get(i).and_then(|f| f.parse().ok()).unwrap_or(0);
+        let psargs = fs::read(format!("/proc/{}/cmdline", pid))?
+            .split(|b| *b == 0)
+            .map(|arg| String::from_utf8_lossy(arg).to_string())
+            .collect::<Vec<String>>()
+            .join(" ");
+        // The real uid and gid are the first of the four ids on the Uid:/Gid: lines of
+        // /proc/<pid>/status
+        let status = fs::read_to_string(format!("/proc/{}/status", pid))?;
+        let id = |key: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(key))
+                .and_then(|ids| ids.split_whitespace().next())
+                .and_then(|id| id.parse().ok())
+                .unwrap_or(0)
+        };
+
+        let dump = CoreDump {
+            process: ProcessInfo {
+                pid: pid.as_raw(),
+                ppid: field(1),
+                pgrp: field(2),
+                sid: field(3),
+                uid: id("Uid:"),
+                gid: id("Gid:"),
+                state: fields.first().map_or(b'R', |state| state.as_bytes()[0]),
+                fname,
+                psargs: psargs.trim_end().to_string(),
+            },
+            threads: vec![CoreThread {
+                pid: pid.as_raw(),
+                signal: siginfo.map_or(0, |info| info.signo),
+                regs,
+                fpregs,
+            }],
+            siginfo,
+            auxv: fs::read(format!("/proc/{}/auxv", pid))?,
+            mappings: memory_map::read_maps(pid)?
+                .into_iter()
+                // [vsyscall] can't be read through /proc/<pid>/mem
+                .filter(|m| m.is_readable() && m.path != "[vsyscall]")
+                .collect(),
+        };
+
+        let mem = fs::File::open(format!("/proc/{}/mem", pid))?;
+        dump.write(path, &mut |addr, buf| {
+            if mem.read_exact_at(buf, addr as u64).is_err() {
+                return false;
             }
-            if let Some(func_name) = func_name {
-                if func_name == "main" {
-                    break;
+            for (bp_addr, orig_byte) in &self.breakpoints {
+                if *bp_addr >= addr && *bp_addr < addr + buf.len() {
+                    buf[*bp_addr - addr] = *orig_byte;
                 }
             }
-            instruction_ptr =
-                ptrace::read(self.pid(), (current_stack_frame + 8) as ptrace::AddressType)?
-                    as usize;
-            current_stack_frame =
-                ptrace::read(self.pid(), current_stack_frame as ptrace::AddressType)? as usize;
+            true
+        })
+    }
+
+    pub fn kill(&mut self) -> Result<Status, nix::Error> {
+        println!("killing running inferior (pid {})", self.pid());
+        match self.child.kill() {
+            Ok(_) => self.wait(None),
+            _ => Err(nix::Error::ECHILD),
         }
-        Ok(())
     }
 
     fn align_addr_to_word(&self, addr: usize) -> usize {
@@ -176,3 +483,87 @@ impl Inferior {
         Ok(orig_byte as u8)
     }
 }
+
+impl Target for Inferior {
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = Vec::with_capacity(len);
+        let mut word_addr = self.align_addr_to_word(addr);
+        while word_addr < addr + len {
+            let word = ptrace::read(self.pid(), word_addr as ptrace::AddressType)? as u64;
+            for (i, byte) in word.to_ne_bytes().iter().enumerate() {
+                let byte_addr = word_addr + i;
+                if byte_addr >= addr && byte_addr < addr + len {
+                    bytes.push(*byte);
+                }
+            }
+            word_addr += size_of::<usize>();
+        }
+        Ok(bytes)
+    }
+
+    fn get_registers(&self) -> Result<libc::user_regs_struct, nix::Error> {
+        ptrace::getregs(self.pid())
+    }
+
+    fn get_siginfo(&self) -> Result<SigInfo, nix::Error> {
+        Ok(SigInfo::from_raw(&ptrace::getsiginfo(self.pid())?))
+    }
+
+    fn get_auxv(&self) -> Result<Vec<u8>, nix::Error> {
+        fs::read(format!("/proc/{}/auxv", self.pid())).or(Err(nix::Error::ESRCH))
+    }
+}
+
+/// Returns the general purpose registers by name, in the order `info registers` shows them.
+pub fn named_registers(regs: &libc::user_regs_struct) -> Vec<(&'static str, u64)> {
+    vec![
+        ("rax", regs.rax),
+        ("rbx", regs.rbx),
+        ("rcx", regs.rcx),
+        ("rdx", regs.rdx),
+        ("rsi", regs.rsi),
+        ("rdi", regs.rdi),
+        ("rbp", regs.rbp),
+        ("rsp", regs.rsp),
+        ("r8", regs.r8),
+        ("r9", regs.r9),
+        ("r10", regs.r10),
+        ("r11", regs.r11),
+        ("r12", regs.r12),
+        ("r13", regs.r13),
+        ("r14", regs.r14),
+        ("r15", regs.r15),
+        ("rip", regs.rip),
+        ("eflags", regs.eflags),
+        ("cs", regs.cs),
+        ("ss", regs.ss),
+        ("ds", regs.ds),
+        ("es", regs.es),
+        ("fs", regs.fs),
+        ("gs", regs.gs),
+        ("fs_base", regs.fs_base),
+        ("gs_base", regs.gs_base),
+    ]
+}
+
+/// Looks up a register by name (without the leading `$`). Also accepts the `pc`, `sp` and `fp`
+/// aliases.
+pub fn register_value(regs: &libc::user_regs_struct, name: &str) -> Option<u64> {
+    let name = match name {
+        "pc" => "rip",
+        "sp" => "rsp",
+        "fp" => "rbp",
+        other => other,
+    };
+    named_registers(regs)
+        .into_iter()
+        .find(|(reg_name, _)| *reg_name == name)
+        .map(|(_, value)| value)
+}
+
+/// Prints the general purpose registers the way `info registers` shows them.
+pub fn print_registers(regs: &libc::user_regs_struct) {
+    for (name, value) in named_registers(regs) {
+        println!("{:<8} {:#018x} {}", name, value, value as i64);
+    }
+}
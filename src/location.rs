@@ -0,0 +1,378 @@
+//! Works out where a variable is in a stack frame, given its DWARF location: a fixed address, an
+//! offset from the frame base, a location list, or an arbitrary DWARF expression, which we run
+//! with gimli's evaluator and feed with the frame's registers and memory.
+
+use crate::dwarf_data::{DwarfData, Expression, FrameRegisters, Function, Location, CALLEE_SAVED};
+use crate::inferior::Target;
+use crate::tls;
+
+/// How many `DW_OP_entry_value`s deep we'll go looking for values in callers' frames
+const MAX_ENTRY_VALUE_DEPTH: usize = 8;
+
+/// Where a variable is right now.
+#[derive(Debug)]
+pub enum Place {
+    /// In memory at this (runtime) address
+    Memory(usize),
+    /// Not in memory: in a register, or computed by the location expression. The bytes are
+    /// little-endian, and may be longer or shorter than the variable.
+    Value(Vec<u8>),
+    /// Nowhere: the compiler didn't keep it around at this point of the program
+    OptimizedOut,
+}
+
+/// A stack frame whose variables we're looking at.
+pub struct Frame<'a> {
+    target: &'a dyn Target,
+    modules: &'a [&'a DwarfData],
+    registers: FrameRegisters,
+    /// Every register, which we only know in the innermost frame. In callers we only know what
+    /// unwinding recovers.
+    all_registers: Option<libc::user_regs_struct>,
+    /// Address to look code up by: `rip` in the innermost frame, and the byte before the return
+    /// address (inside the call instruction) in the others
+    pc: usize,
+}
+
+impl<'a> Frame<'a> {
+    /// Returns the frame the target is stopped in.
+    pub fn innermost(
+        target: &'a dyn Target,
+        modules: &'a [&'a DwarfData],
+    ) -> Result<Frame<'a>, nix::Error> {
+        let regs = target.get_registers()?;
+        Ok(Frame {
+            target,
+            modules,
+            registers: FrameRegisters::from_registers(&regs),
+            all_registers: Some(regs),
+            pc: regs.rip as usize,
+        })
+    }
+
+    /// Returns the frame of the function that called this one.
+    pub fn caller(&self) -> Option<Frame<'a>> {
+        let registers = self.target.unwind(self.modules, self.pc, &self.registers)?;
+        // The stack grows down, so callers' frames must be at higher addresses
+        if registers.rip == 0 || registers.rsp <= self.registers.rsp {
+            return None;
+        }
+        Some(Frame {
+            target: self.target,
+            modules: self.modules,
+            registers,
+            all_registers: None,
+            pc: registers.rip - 1,
+        })
+    }
+
+    /// Returns the debug info of the executable or shared library the frame's code is in.
+    pub fn module(&self) -> Option<&'a DwarfData> {
+        self.modules
+            .iter()
+            .find(|module| module.contains(self.pc))
+            .copied()
+    }
+
+    /// Returns the function the frame is running.
+    pub fn function(&self) -> Option<Function> {
+        self.module()?.get_function_containing(self.pc)
+    }
+
+    /// Returns the value of DWARF register `register` (numbered as in the x86-64 psABI).
+    fn register(&self, register: u16) -> Option<u64> {
+        match register {
+            6 => return Some(self.registers.rbp as u64),
+            7 => return Some(self.registers.rsp as u64),
+            16 => return Some(self.registers.rip as u64),
+            _ => {}
+        }
+        if let Some(i) = CALLEE_SAVED.iter().position(|reg| reg.0 == register) {
+            return self.registers.callee_saved[i].map(|value| value as u64);
+        }
+        let regs = self.all_registers.as_ref()?;
+        Some(match register {
+            0 => regs.rax,
+            1 => regs.rdx,
+            2 => regs.rcx,
+            3 => regs.rbx,
+            4 => regs.rsi,
+            5 => regs.rdi,
+            8 => regs.r8,
+            9 => regs.r9,
+            10 => regs.r10,
+            11 => regs.r11,
+            12 => regs.r12,
+            13 => regs.r13,
+            14 => regs.r14,
+            15 => regs.r15,
+            _ => return None,
+        })
+    }
+
+    /// Returns the canonical frame address: the value of rsp just before the call that created
+    /// this frame, which is what the caller's rsp unwinds to.
+    fn cfa(&self) -> Option<usize> {
+        self.target
+            .unwind(self.modules, self.pc, &self.registers)
+            .map(|caller| caller.rsp)
+    }
+
+    /// Returns the address `func`'s `DW_AT_frame_base` evaluates to in this frame.
+    fn frame_base(&self, func: &Function, depth: usize) -> Option<usize> {
+        let frame_base = match &func.frame_base {
+            Some(frame_base) => locate_at_depth(frame_base, self, func, depth),
+            // Without one, assume the usual frame pointer setup: past the saved rbp and the
+            // return address
+            None => return self.registers.rbp.checked_add(16),
+        };
+        match frame_base {
+            Place::Memory(addr) => Some(addr),
+            Place::Value(bytes) => Some(to_u64(&bytes) as usize),
+            Place::OptimizedOut => None,
+        }
+    }
+
+    /// Returns the value DWARF register `register` had when `func` (which this frame is running)
+    /// was called. Unless we're still at the first instruction of the function, that's up to the
+    /// caller's call site information.
+    fn entry_value(&self, func: &Function, register: u16, depth: usize) -> Option<u64> {
+        let module = self.module()?;
+        if self.pc == func.address + module.load_bias() {
+            return self.register(register);
+        }
+        if depth >= MAX_ENTRY_VALUE_DEPTH {
+            return None;
+        }
+        let caller = self.caller()?;
+        let caller_module = caller.module()?;
+        let caller_func = caller.function()?;
+        let return_address = caller.registers.rip - caller_module.load_bias();
+        let call_site = caller_func
+            .call_sites
+            .iter()
+            .find(|call_site| call_site.return_address == return_address)?;
+        let (_, value) = call_site
+            .parameters
+            .iter()
+            .find(|(parameter, _)| *parameter == register)?;
+        // Call site values are DWARF expressions for the value itself, so what reads as an
+        // address is the value
+        match evaluate(
+            value,
+            &caller,
+            caller.module(),
+            Some(&caller_func),
+            depth + 1,
+        ) {
+            Place::Memory(value) => Some(value as u64),
+            Place::Value(bytes) => Some(to_u64(&bytes)),
+            Place::OptimizedOut => None,
+        }
+    }
+}
+
+/// Works out where a variable of `func` with location `location` is in `frame`.
+pub fn locate(location: &Location, frame: &Frame, func: &Function) -> Place {
+    locate_at_depth(location, frame, func, 0)
+}
+
+fn locate_at_depth(location: &Location, frame: &Frame, func: &Function, depth: usize) -> Place {
+    let load_bias = frame.module().map_or(0, |module| module.load_bias());
+    match location {
+        Location::Address(addr) => Place::Memory(addr + load_bias),
+        Location::FramePointerOffset(offset) => match frame.frame_base(func, depth) {
+            Some(frame_base) => Place::Memory((frame_base as isize + offset) as usize),
+            None => Place::OptimizedOut,
+        },
+        Location::Expression(expression) => {
+            evaluate(expression, frame, frame.module(), Some(func), depth)
+        }
+        Location::List(entries) => {
+            let pc = frame.pc - load_bias;
+            match entries
+                .iter()
+                .find(|(start, end, _)| *start <= pc && pc < *end)
+            {
+                Some((_, _, expression)) => {
+                    evaluate(expression, frame, frame.module(), Some(func), depth)
+                }
+                None => Place::OptimizedOut,
+            }
+        }
+        Location::Constant(bytes) => Place::Value(bytes.clone()),
+        Location::OptimizedOut => Place::OptimizedOut,
+    }
+}
+
+/// Works out where a global variable of `module` with location `location` is. Only
+/// thread-local variables depend on `frame`: they're in the thread it belongs to.
+pub fn locate_global(location: &Location, frame: &Frame, module: &DwarfData) -> Place {
+    match location {
+        Location::Address(addr) => Place::Memory(addr + module.load_bias()),
+        Location::Expression(expression) => evaluate(expression, frame, Some(module), None, 0),
+        Location::Constant(bytes) => Place::Value(bytes.clone()),
+        _ => Place::OptimizedOut,
+    }
+}
+
+/// Runs a DWARF location expression in `frame`, for a variable of `module` and (unless it's a
+/// global) `func`. Anything we can't provide the evaluator with (references to other DIEs, or
+/// things globals can't refer to) makes the variable optimized out.
+fn evaluate(
+    expression: &Expression,
+    frame: &Frame,
+    module: Option<&DwarfData>,
+    func: Option<&Function>,
+    depth: usize,
+) -> Place {
+    let load_bias = module.map_or(0, |module| module.load_bias()) as u64;
+    let bytecode = gimli::EndianSlice::new(&expression.bytecode, gimli::LittleEndian);
+    let mut evaluation = gimli::Evaluation::new(bytecode, expression.encoding);
+    let mut result = evaluation.evaluate();
+    loop {
+        let requirement = match result {
+            Ok(requirement) => requirement,
+            Err(_) => return Place::OptimizedOut,
+        };
+        result = match requirement {
+            gimli::EvaluationResult::Complete => break,
+            gimli::EvaluationResult::RequiresMemory { address, size, .. } => {
+                match frame.target.read_memory(address as usize, size as usize) {
+                    Ok(bytes) => {
+                        evaluation.resume_with_memory(gimli::Value::Generic(to_u64(&bytes)))
+                    }
+                    Err(_) => return Place::OptimizedOut,
+                }
+            }
+            gimli::EvaluationResult::RequiresRegister { register, .. } => {
+                match frame.register(register.0) {
+                    Some(value) => evaluation.resume_with_register(gimli::Value::Generic(value)),
+                    None => return Place::OptimizedOut,
+                }
+            }
+            gimli::EvaluationResult::RequiresFrameBase => {
+                match func.and_then(|func| frame.frame_base(func, depth)) {
+                    Some(frame_base) => evaluation.resume_with_frame_base(frame_base as u64),
+                    None => return Place::OptimizedOut,
+                }
+            }
+            gimli::EvaluationResult::RequiresCallFrameCfa => match frame.cfa() {
+                Some(cfa) => evaluation.resume_with_call_frame_cfa(cfa as u64),
+                None => return Place::OptimizedOut,
+            },
+            gimli::EvaluationResult::RequiresEntryValue(entry_expression) => {
+                // Compilers only ask for the entry values of registers
+                let mut pc = entry_expression.0;
+                let register = match gimli::Operation::parse(&mut pc, expression.encoding) {
+                    Ok(gimli::Operation::Register { register }) if pc.is_empty() => register,
+                    _ => return Place::OptimizedOut,
+                };
+                match func.and_then(|func| frame.entry_value(func, register.0, depth)) {
+                    Some(value) => evaluation.resume_with_entry_value(gimli::Value::Generic(value)),
+                    None => return Place::OptimizedOut,
+                }
+            }
+            gimli::EvaluationResult::RequiresRelocatedAddress(address) => {
+                evaluation.resume_with_relocated_address(address + load_bias)
+            }
+            gimli::EvaluationResult::RequiresIndexedAddress { index, relocate } => {
+                let address = expression
+                    .addresses
+                    .iter()
+                    .find(|(i, _)| *i == index.0 as u64)
+                    .map(|(_, address)| *address);
+                match address {
+                    Some(address) if relocate => {
+                        evaluation.resume_with_indexed_address(address + load_bias)
+                    }
+                    Some(address) => evaluation.resume_with_indexed_address(address),
+                    None => return Place::OptimizedOut,
+                }
+            }
+            gimli::EvaluationResult::RequiresTls(offset) => {
+                match module.and_then(|module| {
+                    tls::tls_address(frame.target, frame.modules, module, offset as usize)
+                }) {
+                    Some(address) => evaluation.resume_with_tls(address as u64),
+                    None => return Place::OptimizedOut,
+                }
+            }
+            // We don't read the types DW_OP_convert and friends refer to, so treat everything as
+            // a machine word
+            gimli::EvaluationResult::RequiresBaseType(_) => {
+                evaluation.resume_with_base_type(gimli::ValueType::Generic)
+            }
+            _ => return Place::OptimizedOut,
+        };
+    }
+
+    let pieces = evaluation.result();
+    if let [piece] = pieces.as_slice() {
+        if piece.size_in_bits.is_none() {
+            return match &piece.location {
+                gimli::Location::Address { address } => Place::Memory(*address as usize),
+                location => match piece_bytes(location, frame, 8) {
+                    Some(bytes) => Place::Value(bytes),
+                    None => Place::OptimizedOut,
+                },
+            };
+        }
+    }
+    // The variable is split up (DW_OP_piece), so put it back together
+    let mut bytes = Vec::new();
+    for piece in &pieces {
+        let size = piece
+            .size_in_bits
+            .map_or(8, |bits| (bits / 8 + u64::from(bits % 8 != 0)) as usize);
+        match piece_bytes(&piece.location, frame, size) {
+            Some(piece) => bytes.extend(piece.into_iter().chain(std::iter::repeat(0)).take(size)),
+            None => return Place::OptimizedOut,
+        }
+    }
+    Place::Value(bytes)
+}
+
+/// Returns the contents of a piece of a variable, `size` bytes of it if it's in memory.
+fn piece_bytes(
+    location: &gimli::Location<gimli::EndianSlice<gimli::LittleEndian>>,
+    frame: &Frame,
+    size: usize,
+) -> Option<Vec<u8>> {
+    match location {
+        gimli::Location::Address { address } => {
+            frame.target.read_memory(*address as usize, size).ok()
+        }
+        gimli::Location::Register { register } => {
+            Some(frame.register(register.0)?.to_le_bytes().to_vec())
+        }
+        gimli::Location::Value { value } => Some(value_bytes(*value)),
+        gimli::Location::Bytes { value } => Some(value.to_vec()),
+        gimli::Location::Empty | gimli::Location::ImplicitPointer { .. } => None,
+    }
+}
+
+/// Returns the little-endian bytes of a value off the DWARF stack.
+fn value_bytes(value: gimli::Value) -> Vec<u8> {
+    match value {
+        gimli::Value::Generic(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::I8(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::U8(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::I16(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::U16(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::I32(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::U32(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::I64(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::U64(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::F32(value) => value.to_le_bytes().to_vec(),
+        gimli::Value::F64(value) => value.to_le_bytes().to_vec(),
+    }
+}
+
+/// Reads up to 8 little-endian bytes as an integer.
+fn to_u64(bytes: &[u8]) -> u64 {
+    let mut raw = [0u8; 8];
+    let len = bytes.len().min(8);
+    raw[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(raw)
+}
@@ -1,24 +1,100 @@
+mod core_file;
+mod debug_file;
+mod debug_section;
 mod debugger;
 mod debugger_command;
+mod demangle;
 mod dwarf_data;
+mod exception;
 mod gimli_wrapper;
+mod index_cache;
 mod inferior;
+mod location;
+mod memory_map;
+mod name_index;
+mod printf;
+mod shared_library;
+mod siginfo;
+mod split_dwarf;
+mod tls;
+mod value;
 
 use crate::debugger::Debugger;
 use nix::sys::signal::{signal, SigHandler, Signal};
 use std::env;
 
+/// A command to run at startup, from the command line, in the order given.
+enum StartupCommand {
+    /// `-x FILE`
+    Script(String),
+    /// `-ex COMMAND`
+    Command(String),
+}
+
+fn usage(program: &str) -> ! {
+    println!(
+        "Usage: {} [-x <script>] [-ex <command>] [--batch] <target program> [--core <core file>]",
+        program
+    );
+    println!(
+        "       {} [options] --args <target program> [arguments...]",
+        program
+    );
+    std::process::exit(0x100);
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <target program>", args[0]);
-        std::process::exit(0x100);
+    let mut target = None;
+    let mut core_path = None;
+    let mut program_args = Vec::new();
+    let mut startup_commands = Vec::new();
+    let mut batch = false;
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        let mut value = || match options.next() {
+            Some(value) => value.clone(),
+            None => usage(&args[0]),
+        };
+        match option.as_str() {
+            "-x" => startup_commands.push(StartupCommand::Script(value())),
+            "-ex" => startup_commands.push(StartupCommand::Command(value())),
+            "--core" => core_path = Some(value()),
+            "--batch" => batch = true,
+            "--args" => {
+                target = Some(value());
+                program_args = options.by_ref().cloned().collect();
+            }
+            option if option.starts_with('-') => usage(&args[0]),
+            _ if target.is_none() => target = Some(option.clone()),
+            _ => usage(&args[0]),
+        }
     }
-    let target = &args[1];
+    let target = match target {
+        Some(target) => target,
+        None => usage(&args[0]),
+    };
 
-    // Disable handling of ctrl+c in this process (so that ctrl+c only gets delivered to child
-    // processes)
-    unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
+    // Don't let ctrl+c kill the debugger. While the inferior is running, the handler makes sure it
+    // stops so that we can return to the prompt. (Unlike SigIgn, a handler is reset by exec, so the
+    // inferior still sees the default SIGINT disposition.)
+    unsafe { signal(Signal::SIGINT, SigHandler::Handler(inferior::handle_sigint)) }
+        .expect("Error installing SIGINT handler");
 
-    Debugger::new(target).run();
+    let mut debugger = Debugger::new(&target, core_path.as_deref());
+    debugger.set_args(program_args);
+    if batch {
+        debugger.set_batch();
+    }
+    debugger.load_init_files();
+    for command in &startup_commands {
+        match command {
+            StartupCommand::Script(path) => debugger.queue_script(path),
+            StartupCommand::Command(command) => debugger.queue_command(command),
+        }
+    }
+    let exit_status = debugger.run();
+    if batch {
+        std::process::exit(exit_status);
+    }
 }
@@ -0,0 +1,84 @@
+use nix::unistd::Pid;
+use std::fmt;
+use std::fs;
+use std::io;
+
+/// One line of `/proc/<pid>/maps`.
+#[derive(Debug, Clone)]
+pub struct MemoryMapping {
+    pub start: usize,
+    pub end: usize,
+    /// Permissions as printed by the kernel, e.g. "r-xp"
+    pub perms: String,
+    /// Offset into the mapped file
+    pub offset: usize,
+    /// Backing file, or a pseudo-path like "[stack]". Empty for anonymous mappings.
+    pub path: String,
+}
+
+impl MemoryMapping {
+    pub fn contains(&self, addr: usize) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.perms.starts_with('r')
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.perms.chars().nth(1) == Some('w')
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.perms.chars().nth(2) == Some('x')
+    }
+
+    /// Parses a single line of a maps file, e.g.
+    /// `00400000-00401000 r-xp 00000000 08:01 1234    /deet/samples/segfault`
+    fn parse(line: &str) -> Option<MemoryMapping> {
+        let mut fields = line.split_whitespace();
+        let mut range = fields.next()?.split('-');
+        let start = usize::from_str_radix(range.next()?, 16).ok()?;
+        let end = usize::from_str_radix(range.next()?, 16).ok()?;
+        let perms = fields.next()?.to_string();
+        let offset = usize::from_str_radix(fields.next()?, 16).ok()?;
+        let _device = fields.next()?;
+        let _inode = fields.next()?;
+        let path = fields.collect::<Vec<&str>>().join(" ");
+        Some(MemoryMapping {
+            start,
+            end,
+            perms,
+            offset,
+            path,
+        })
+    }
+}
+
+impl fmt::Display for MemoryMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#x}-{:#x} {} {}",
+            self.start,
+            self.end,
+            self.perms,
+            if self.path.is_empty() {
+                "<anonymous>"
+            } else {
+                &self.path
+            }
+        )
+    }
+}
+
+/// Reads the memory mappings of a running process.
+pub fn read_maps(pid: Pid) -> io::Result<Vec<MemoryMapping>> {
+    let contents = fs::read_to_string(format!("/proc/{}/maps", pid))?;
+    Ok(contents.lines().filter_map(MemoryMapping::parse).collect())
+}
+
+/// Returns the mapping containing `addr`, if any.
+pub fn find_mapping(maps: &[MemoryMapping], addr: usize) -> Option<&MemoryMapping> {
+    maps.iter().find(|m| m.contains(addr))
+}
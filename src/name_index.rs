@@ -0,0 +1,385 @@
+//! Reads the name indexes that compilers and linkers can add to a binary, which say which
+//! compile units define each function and global variable: DWARF 5's `.debug_names` (e.g.
+//! `clang -gpubnames`) and gdb's `.gdb_index` (e.g. `gdb-add-index`, `ld.lld --gdb-index`). With
+//! one of these we don't have to look inside every unit to find a name.
+
+use crate::demangle::base_name;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+
+const DW_TAG_SUBPROGRAM: u64 = 0x2e;
+const DW_TAG_VARIABLE: u64 = 0x34;
+const DW_IDX_COMPILE_UNIT: u64 = 1;
+
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_FLAG: u64 = 0x0c;
+const DW_FORM_SDATA: u64 = 0x0d;
+const DW_FORM_UDATA: u64 = 0x0f;
+const DW_FORM_REF1: u64 = 0x11;
+const DW_FORM_REF2: u64 = 0x12;
+const DW_FORM_REF4: u64 = 0x13;
+const DW_FORM_REF8: u64 = 0x14;
+const DW_FORM_REF_UDATA: u64 = 0x15;
+const DW_FORM_FLAG_PRESENT: u64 = 0x19;
+const DW_FORM_REF_SIG8: u64 = 0x20;
+
+/// Symbol kinds in the CU vectors of a version 7 or later `.gdb_index`
+const GDB_INDEX_SYMBOL_KIND_VARIABLE: u32 = 2;
+const GDB_INDEX_SYMBOL_KIND_FUNCTION: u32 = 3;
+
+/// For each function and global variable name, the `.debug_info` offsets of the compile units
+/// that define something by that name. Names are keyed without their namespaces, classes and
+/// parameters (see `demangle::base_name`), since some indexes have qualified names and some
+/// don't.
+#[derive(Debug, Default)]
+pub struct NameIndex {
+    pub functions: HashMap<String, Vec<usize>>,
+    pub variables: HashMap<String, Vec<usize>>,
+    /// The units whose names are in the index. Linking objects built with and without an index
+    /// gives a `.debug_names` that only covers some of them.
+    pub units: HashSet<usize>,
+}
+
+impl NameIndex {
+    pub fn add_function(&mut self, name: String, unit_offset: usize) {
+        add(&mut self.functions, name, unit_offset);
+    }
+
+    pub fn add_variable(&mut self, name: String, unit_offset: usize) {
+        add(&mut self.variables, name, unit_offset);
+    }
+}
+
+fn add(names: &mut HashMap<String, Vec<usize>>, name: String, unit_offset: usize) {
+    let units = names.entry(base_name(&name).to_string()).or_default();
+    if !units.contains(&unit_offset) {
+        units.push(unit_offset);
+    }
+}
+
+/// Little or big-endian reads from a section, which all return `None` past its end.
+struct Section<'data> {
+    data: &'data [u8],
+    little_endian: bool,
+}
+
+impl<'data> Section<'data> {
+    fn bytes<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+        self.data.get(offset..offset + N)?.try_into().ok()
+    }
+
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        let bytes = self.bytes(offset)?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        let bytes = self.bytes(offset)?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn u64_at(&self, offset: usize) -> Option<u64> {
+        let bytes = self.bytes(offset)?;
+        Some(if self.little_endian {
+            u64::from_le_bytes(bytes)
+        } else {
+            u64::from_be_bytes(bytes)
+        })
+    }
+
+    /// Reads a 4 or 8-byte offset, depending on the DWARF format.
+    fn offset_at(&self, offset: usize, offset_size: usize) -> Option<usize> {
+        if offset_size == 8 {
+            Some(self.u64_at(offset)? as usize)
+        } else {
+            Some(self.u32_at(offset)? as usize)
+        }
+    }
+
+    fn uleb128_at(&self, offset: &mut usize) -> Option<u64> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = *self.data.get(*offset)?;
+            *offset += 1;
+            if shift < 64 {
+                value |= u64::from(byte & 0x7f) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+    }
+
+    fn str_at(&self, offset: usize) -> Option<String> {
+        let bytes = self.data.get(offset..)?;
+        let len = bytes.iter().position(|b| *b == 0)?;
+        Some(String::from_utf8_lossy(&bytes[..len]).into_owned())
+    }
+}
+
+/// Reads `.debug_names`, whose names are in `.debug_str`. The section can hold several name
+/// tables (one per compile unit, unless the linker merged them).
+pub fn from_debug_names(
+    debug_names: &[u8],
+    debug_str: &[u8],
+    little_endian: bool,
+) -> Option<NameIndex> {
+    let section = Section {
+        data: debug_names,
+        little_endian,
+    };
+    let strings = Section {
+        data: debug_str,
+        little_endian,
+    };
+    if debug_names.is_empty() {
+        return None;
+    }
+    let mut index = NameIndex::default();
+    let mut table = 0;
+    while table < debug_names.len() {
+        let (unit_length, offset_size, header) = match section.u32_at(table)? {
+            0xffff_ffff => (section.u64_at(table + 4)? as usize, 8, table + 12),
+            length => (length as usize, 4, table + 4),
+        };
+        let end = header + unit_length;
+        if section.u16_at(header)? != 5 {
+            return None;
+        }
+        let cu_count = section.u32_at(header + 4)? as usize;
+        let local_tu_count = section.u32_at(header + 8)? as usize;
+        let foreign_tu_count = section.u32_at(header + 12)? as usize;
+        let bucket_count = section.u32_at(header + 16)? as usize;
+        let name_count = section.u32_at(header + 20)? as usize;
+        let abbrev_table_size = section.u32_at(header + 24)? as usize;
+        let augmentation_size = section.u32_at(header + 28)? as usize;
+
+        let cu_list = header + 32 + ((augmentation_size + 3) & !3);
+        let buckets = cu_list + offset_size * (cu_count + local_tu_count) + 8 * foreign_tu_count;
+        let hashes = buckets + 4 * bucket_count;
+        let string_offsets = hashes + if bucket_count > 0 { 4 * name_count } else { 0 };
+        let entry_offsets = string_offsets + offset_size * name_count;
+        let abbrevs = entry_offsets + offset_size * name_count;
+        let entry_pool = abbrevs + abbrev_table_size;
+
+        let units = (0..cu_count)
+            .map(|i| section.offset_at(cu_list + i * offset_size, offset_size))
+            .collect::<Option<Vec<usize>>>()?;
+        index.units.extend(&units);
+
+        // Abbreviation code -> (tag, [(DW_IDX_*, DW_FORM_*)])
+        let mut abbreviations = HashMap::new();
+        let mut offset = abbrevs;
+        loop {
+            let code = section.uleb128_at(&mut offset)?;
+            if code == 0 {
+                break;
+            }
+            let tag = section.uleb128_at(&mut offset)?;
+            let mut attributes = Vec::new();
+            loop {
+                let idx = section.uleb128_at(&mut offset)?;
+                let form = section.uleb128_at(&mut offset)?;
+                if idx == 0 && form == 0 {
+                    break;
+                }
+                attributes.push((idx, form));
+            }
+            abbreviations.insert(code, (tag, attributes));
+        }
+
+        for i in 0..name_count {
+            let name_offset = section.offset_at(string_offsets + i * offset_size, offset_size)?;
+            let name = strings.str_at(name_offset)?;
+            let mut entry =
+                entry_pool + section.offset_at(entry_offsets + i * offset_size, offset_size)?;
+            loop {
+                let code = section.uleb128_at(&mut entry)?;
+                if code == 0 {
+                    break;
+                }
+                let (tag, attributes) = abbreviations.get(&code)?;
+                // An index covering a single unit can leave the unit out
+                let mut unit = if cu_count == 1 { Some(0) } else { None };
+                for (idx, form) in attributes {
+                    let value = read_form(&section, &mut entry, *form)?;
+                    if *idx == DW_IDX_COMPILE_UNIT {
+                        unit = Some(value as usize);
+                    }
+                }
+                if let Some(unit_offset) = unit.and_then(|unit| units.get(unit)) {
+                    match *tag {
+                        DW_TAG_SUBPROGRAM => index.add_function(name.clone(), *unit_offset),
+                        DW_TAG_VARIABLE => index.add_variable(name.clone(), *unit_offset),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        table = end;
+    }
+    Some(index)
+}
+
+/// Reads an attribute value in a `.debug_names` entry, returning it if it's an integer.
+fn read_form(section: &Section, offset: &mut usize, form: u64) -> Option<u64> {
+    let (value, size) = match form {
+        DW_FORM_DATA1 | DW_FORM_REF1 | DW_FORM_FLAG => (u64::from(*section.data.get(*offset)?), 1),
+        DW_FORM_DATA2 | DW_FORM_REF2 => (u64::from(section.u16_at(*offset)?), 2),
+        DW_FORM_DATA4 | DW_FORM_REF4 => (u64::from(section.u32_at(*offset)?), 4),
+        DW_FORM_DATA8 | DW_FORM_REF8 | DW_FORM_REF_SIG8 => (section.u64_at(*offset)?, 8),
+        DW_FORM_UDATA | DW_FORM_REF_UDATA | DW_FORM_SDATA => {
+            return section.uleb128_at(offset);
+        }
+        DW_FORM_FLAG_PRESENT => (1, 0),
+        _ => return None,
+    };
+    *offset += size;
+    Some(value)
+}
+
+/// Reads a version 7 or 8 `.gdb_index`, which is always little-endian.
+pub fn from_gdb_index(gdb_index: &[u8]) -> Option<NameIndex> {
+    let section = Section {
+        data: gdb_index,
+        little_endian: true,
+    };
+    let version = section.u32_at(0)?;
+    // Earlier versions don't say whether a symbol is a function or a variable
+    if version != 7 && version != 8 {
+        return None;
+    }
+    let cu_list = section.u32_at(4)? as usize;
+    let types_list = section.u32_at(8)? as usize;
+    let symbol_table = section.u32_at(16)? as usize;
+    let constant_pool = section.u32_at(20)? as usize;
+
+    // Each CU is its offset and length, 8 bytes each
+    let units = (cu_list..types_list)
+        .step_by(16)
+        .map(|entry| Some(section.u64_at(entry)? as usize))
+        .collect::<Option<Vec<usize>>>()?;
+
+    let mut index = NameIndex::default();
+    index.units.extend(&units);
+    // The symbol table is a hash table of (name offset, CU vector offset) slots; empty ones are
+    // all zeros
+    for slot in (symbol_table..constant_pool).step_by(8) {
+        let name_offset = section.u32_at(slot)? as usize;
+        let vector_offset = section.u32_at(slot + 4)? as usize;
+        if name_offset == 0 && vector_offset == 0 {
+            continue;
+        }
+        let name = section.str_at(constant_pool + name_offset)?;
+        let vector = constant_pool + vector_offset;
+        let count = section.u32_at(vector)? as usize;
+        for i in 0..count {
+            // The CU index is in the low 24 bits, and the symbol kind in bits 28-30
+            let entry = section.u32_at(vector + 4 + 4 * i)?;
+            let unit_offset = match units.get((entry & 0x00ff_ffff) as usize) {
+                Some(offset) => *offset,
+                // Type units are numbered after the compile units
+                None => continue,
+            };
+            match (entry >> 28) & 7 {
+                GDB_INDEX_SYMBOL_KIND_FUNCTION => index.add_function(name.clone(), unit_offset),
+                GDB_INDEX_SYMBOL_KIND_VARIABLE => index.add_variable(name.clone(), unit_offset),
+                _ => {}
+            }
+        }
+    }
+    Some(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a little-endian 32-bit `.debug_names` table without a hash table, for the units at
+    /// `units`. `names` are (offset in `.debug_str`, entries), where each entry is the bytes
+    /// following its offset in the entry pool.
+    fn debug_names(units: &[u32], abbrevs: &[u8], names: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&5u16.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        for field in &[units.len(), 0, 0, 0, names.len(), abbrevs.len(), 0] {
+            header.extend_from_slice(&(*field as u32).to_le_bytes());
+        }
+        units
+            .iter()
+            .for_each(|unit| header.extend_from_slice(&unit.to_le_bytes()));
+        names
+            .iter()
+            .for_each(|(name, _)| header.extend_from_slice(&name.to_le_bytes()));
+        let mut pool = Vec::new();
+        for (_, entries) in names {
+            header.extend_from_slice(&(pool.len() as u32).to_le_bytes());
+            pool.extend_from_slice(entries);
+        }
+        header.extend_from_slice(abbrevs);
+        header.extend_from_slice(&pool);
+
+        let mut section = (header.len() as u32).to_le_bytes().to_vec();
+        section.extend_from_slice(&header);
+        section
+    }
+
+    #[test]
+    fn reads_functions_and_variables_by_unit() {
+        let debug_str = b"\0ns::foo\0counter\0";
+        // Abbreviation 1 is a function and 2 a variable, both with a one-byte unit number
+        let abbrevs = [
+            1,
+            DW_TAG_SUBPROGRAM as u8,
+            DW_IDX_COMPILE_UNIT as u8,
+            DW_FORM_DATA1 as u8,
+            0,
+            0,
+            2,
+            DW_TAG_VARIABLE as u8,
+            DW_IDX_COMPILE_UNIT as u8,
+            DW_FORM_DATA1 as u8,
+            0,
+            0,
+            0,
+        ];
+        let names = [(1, vec![1, 0, 1, 1, 0]), (9, vec![2, 1, 0])];
+        let section = debug_names(&[0, 0x40], &abbrevs, &names);
+
+        let index = from_debug_names(&section, debug_str, true).unwrap();
+        assert_eq!(index.functions["foo"], vec![0, 0x40]);
+        assert_eq!(index.variables["counter"], vec![0x40]);
+        assert!(!index.functions.contains_key("counter"));
+        assert_eq!(index.units, [0, 0x40].iter().copied().collect());
+    }
+
+    #[test]
+    fn single_unit_index_can_leave_out_the_unit() {
+        let debug_str = b"\0main\0";
+        let abbrevs = [1, DW_TAG_SUBPROGRAM as u8, 0, 0, 0];
+        let section = debug_names(&[0x80], &abbrevs, &[(1, vec![1, 0])]);
+
+        let index = from_debug_names(&section, debug_str, true).unwrap();
+        assert_eq!(index.functions["main"], vec![0x80]);
+    }
+
+    #[test]
+    fn rejects_truncated_table() {
+        let abbrevs = [1, DW_TAG_SUBPROGRAM as u8, 0, 0, 0];
+        let section = debug_names(&[0], &abbrevs, &[(1, vec![1, 0])]);
+        assert!(from_debug_names(&section[..section.len() - 1], b"\0main\0", true).is_none());
+    }
+}
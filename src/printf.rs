@@ -0,0 +1,400 @@
+//! C `printf`-style formatting for `dprintf`, whose format strings are written the way they
+//! would be in the program being debugged.
+
+/// A value to format, as the program would pass it to `printf`.
+#[derive(Debug, Clone)]
+pub enum Arg {
+    /// Integers, characters, booleans, enums and pointers (other than to `char`)
+    Int(i64),
+    Float(f64),
+    /// A `char *`, with the string it points to if it could be read
+    CharPointer(u64, Option<String>),
+    /// A `char` array, up to its first NUL
+    Str(String),
+}
+
+/// Parses the C string literal at the start of `text` (after any whitespace), returning its
+/// contents with escape sequences replaced, and the rest of `text`.
+pub fn parse_string_literal(text: &str) -> Option<(String, &str)> {
+    let text = text.trim_start().strip_prefix('"')?;
+    let mut result = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((result, &text[i + 1..])),
+            '\\' => result.push(match chars.next()?.1 {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                'a' => '\x07',
+                'e' => '\x1b',
+                '0' => '\0',
+                c => c,
+            }),
+            c => result.push(c),
+        }
+    }
+    None
+}
+
+/// Writes `text` as a C string literal, which `parse_string_literal` reads back.
+pub fn quote(text: &str) -> String {
+    let mut result = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            '\r' => result.push_str("\\r"),
+            '\x07' => result.push_str("\\a"),
+            '\x1b' => result.push_str("\\e"),
+            '\0' => result.push_str("\\0"),
+            '"' | '\\' => {
+                result.push('\\');
+                result.push(c);
+            }
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// The largest field width or precision we'll pad to, well past anything a real format uses
+const MAX_FIELD_WIDTH: usize = 4096;
+
+/// One `%` conversion: `%[flags][width][.precision][length]conversion`
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+    /// Width of integers in bits, from the length modifier (`int` by default)
+    bits: u32,
+    conversion: char,
+}
+
+/// Formats `args` the way C's `printf(format, args...)` would.
+pub fn format(format: &str, args: &[Arg]) -> Result<String, String> {
+    let mut output = String::new();
+    let mut args = args.iter();
+    let mut used = 0;
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            output.push('%');
+            continue;
+        }
+        let mut spec = Spec {
+            bits: 32,
+            ..Default::default()
+        };
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                '-' => spec.left = true,
+                '0' => spec.zero = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alternate = true,
+                _ => break,
+            }
+            chars.next();
+        }
+        spec.width = take_number(&mut chars)?.unwrap_or(0);
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            spec.precision = Some(take_number(&mut chars)?.unwrap_or(0));
+        }
+        while let Some(&length) = chars.peek() {
+            spec.bits = match length {
+                'h' if spec.bits == 16 => 8,
+                'h' => 16,
+                'l' | 'j' | 'z' | 't' | 'L' | 'q' => 64,
+                _ => break,
+            };
+            chars.next();
+        }
+        spec.conversion = chars.next().ok_or("Bad format string")?;
+        let arg = args
+            .next()
+            .ok_or_else(|| format!("Missing argument {} in format string", used))?;
+        used += 1;
+        let text = convert(&spec, arg)?;
+        output.push_str(&pad(&spec, text));
+    }
+    if args.next().is_some() {
+        return Err("Extra arguments at end of format string".to_string());
+    }
+    Ok(output)
+}
+
+/// Reads a field width or precision, if there's one next.
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<Option<usize>, String> {
+    let mut number = None;
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        number = Some(number.unwrap_or(0) * 10 + digit as usize);
+        if number > Some(MAX_FIELD_WIDTH) {
+            return Err("Field width or precision too large in format string".to_string());
+        }
+        chars.next();
+    }
+    Ok(number)
+}
+
+/// Formats one argument, without padding it to the field width.
+fn convert(spec: &Spec, arg: &Arg) -> Result<String, String> {
+    let integer = match arg {
+        Arg::Int(value) => Some(*value),
+        Arg::CharPointer(address, _) => Some(*address as i64),
+        Arg::Float(_) | Arg::Str(_) => None,
+    };
+    let mask = |value: i64| {
+        if spec.bits >= 64 {
+            value as u64
+        } else {
+            value as u64 & ((1 << spec.bits) - 1)
+        }
+    };
+    let value_error = || format!("Value can't be converted to %{}.", spec.conversion);
+    Ok(match spec.conversion {
+        'd' | 'i' => {
+            let value = integer.ok_or_else(value_error)?;
+            // Sign-extend from the argument's width
+            let shift = 64 - spec.bits;
+            let value = (value << shift) >> shift;
+            let digits = with_precision(value.unsigned_abs().to_string(), spec.precision);
+            format!("{}{}", sign(spec, value < 0), digits)
+        }
+        'u' => with_precision(
+            mask(integer.ok_or_else(value_error)?).to_string(),
+            spec.precision,
+        ),
+        'x' | 'X' | 'o' => {
+            let value = mask(integer.ok_or_else(value_error)?);
+            let digits = match spec.conversion {
+                'x' => format!("{:x}", value),
+                'X' => format!("{:X}", value),
+                _ => format!("{:o}", value),
+            };
+            let digits = with_precision(digits, spec.precision);
+            match spec.conversion {
+                _ if !spec.alternate || value == 0 => digits,
+                'x' => format!("0x{}", digits),
+                'X' => format!("0X{}", digits),
+                _ if digits.starts_with('0') => digits,
+                _ => format!("0{}", digits),
+            }
+        }
+        'p' => format!("{:#x}", integer.ok_or_else(value_error)? as u64),
+        'c' => (mask(integer.ok_or_else(value_error)?) as u8 as char).to_string(),
+        's' => {
+            let text = match arg {
+                Arg::Str(text) | Arg::CharPointer(_, Some(text)) => text.clone(),
+                Arg::CharPointer(0, None) => "(null)".to_string(),
+                Arg::CharPointer(address, None) => {
+                    return Err(format!("Cannot access memory at address {:#x}", address))
+                }
+                _ => return Err(value_error()),
+            };
+            match spec.precision {
+                Some(precision) => text.chars().take(precision).collect(),
+                None => text,
+            }
+        }
+        'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+            let value = match arg {
+                Arg::Float(value) => *value,
+                Arg::Int(value) => *value as f64,
+                _ => return Err(value_error()),
+            };
+            format_float(spec, value)
+        }
+        c => return Err(format!("Unrecognized format specifier '{}' in printf", c)),
+    })
+}
+
+/// Pads integer digits with zeros to at least `precision` of them.
+fn with_precision(digits: String, precision: Option<usize>) -> String {
+    match precision {
+        Some(precision) if digits.len() < precision => {
+            format!("{}{}", "0".repeat(precision - digits.len()), digits)
+        }
+        _ => digits,
+    }
+}
+
+fn sign(spec: &Spec, negative: bool) -> &'static str {
+    if negative {
+        "-"
+    } else if spec.plus {
+        "+"
+    } else if spec.space {
+        " "
+    } else {
+        ""
+    }
+}
+
+fn format_float(spec: &Spec, value: f64) -> String {
+    let upper = spec.conversion.is_ascii_uppercase();
+    if !value.is_finite() {
+        let text = if value.is_nan() { "nan" } else { "inf" };
+        let text = format!("{}{}", sign(spec, value < 0.0), text);
+        return if upper { text.to_uppercase() } else { text };
+    }
+    let precision = spec.precision.unwrap_or(6);
+    let magnitude = value.abs();
+    let text = match spec.conversion.to_ascii_lowercase() {
+        'f' => format!("{:.*}", precision, magnitude),
+        'e' => exponent_form(magnitude, precision),
+        _ => {
+            // %g uses %e for very large and small values, and %f otherwise, with `precision`
+            // significant digits and no trailing zeros
+            let precision = precision.max(1);
+            let exponent = if magnitude == 0.0 {
+                0
+            } else {
+                exponent_form(magnitude, precision - 1)
+                    .rsplit('e')
+                    .next()
+                    .and_then(|exponent| exponent.parse::<i32>().ok())
+                    .unwrap_or(0)
+            };
+            let text = if -4 <= exponent && exponent < precision as i32 {
+                format!(
+                    "{:.*}",
+                    (precision as i32 - 1 - exponent) as usize,
+                    magnitude
+                )
+            } else {
+                exponent_form(magnitude, precision - 1)
+            };
+            if spec.alternate {
+                text
+            } else {
+                strip_zeros(&text)
+            }
+        }
+    };
+    let text = format!("{}{}", sign(spec, value.is_sign_negative()), text);
+    if upper {
+        text.to_uppercase()
+    } else {
+        text
+    }
+}
+
+/// Formats like C's `%.{precision}e`, e.g. `1.500000e+03`, where Rust would write `1.5e3`.
+fn exponent_form(value: f64, precision: usize) -> String {
+    let text = format!("{:.*e}", precision, value);
+    let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    format!(
+        "{}e{}{:02}",
+        mantissa,
+        if exponent < 0 { '-' } else { '+' },
+        exponent.abs()
+    )
+}
+
+/// Removes the zeros at the end of a `%g` value's fraction, and the point if nothing's left.
+fn strip_zeros(text: &str) -> String {
+    let (number, exponent) = match text.find('e') {
+        Some(e) => text.split_at(e),
+        None => (text, ""),
+    };
+    let number = if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    };
+    format!("{}{}", number, exponent)
+}
+
+/// Pads a converted value to the field width, with zeros after its sign or `0x` if asked to.
+fn pad(spec: &Spec, text: String) -> String {
+    let length = text.chars().count();
+    if length >= spec.width {
+        return text;
+    }
+    let padding = spec.width - length;
+    if spec.left {
+        return format!("{}{}", text, " ".repeat(padding));
+    }
+    let float = matches!(spec.conversion, 'f' | 'F' | 'e' | 'E' | 'g' | 'G');
+    let integer = matches!(spec.conversion, 'd' | 'i' | 'u' | 'x' | 'X' | 'o');
+    if spec.zero && (float || integer && spec.precision.is_none()) {
+        let mut prefix_length = text.starts_with(|c| "+- ".contains(c)) as usize;
+        if matches!(spec.conversion, 'x' | 'X') && spec.alternate && text.len() > 2 {
+            prefix_length += 2;
+        }
+        let (prefix, digits) = text.split_at(prefix_length);
+        return format!("{}{}{}", prefix, "0".repeat(padding), digits);
+    }
+    format!("{}{}", " ".repeat(padding), text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format, parse_string_literal, quote, Arg};
+
+    #[test]
+    fn formats_integers_with_flags_width_and_precision() {
+        let args = [Arg::Int(-42), Arg::Int(42), Arg::Int(255), Arg::Int(7)];
+        assert_eq!(
+            format("[%5d] [%-5d|] [%#06x] [%.3d]", &args).unwrap(),
+            "[  -42] [42   |] [0x00ff] [007]"
+        );
+        let args = [Arg::Int(5), Arg::Int(5), Arg::Int(-1)];
+        assert_eq!(format("%+d % d %hhu", &args).unwrap(), "+5  5 255");
+    }
+
+    #[test]
+    fn formats_floats() {
+        let args = [Arg::Float(12.3456), Arg::Float(1500.0), Arg::Float(0.0001)];
+        assert_eq!(
+            format("%08.2f %e %g", &args).unwrap(),
+            "00012.35 1.500000e+03 0.0001"
+        );
+    }
+
+    #[test]
+    fn formats_strings_from_pointers_and_arrays() {
+        let args = [
+            Arg::CharPointer(0x1000, Some("hello".to_string())),
+            Arg::Str("world".to_string()),
+            Arg::CharPointer(0, None),
+        ];
+        assert_eq!(format("%.3s|%6s|%s", &args).unwrap(), "hel| world|(null)");
+        assert!(format("%s", &[Arg::CharPointer(0x1000, None)]).is_err());
+        assert!(format("%s", &[Arg::Int(1)]).is_err());
+    }
+
+    #[test]
+    fn checks_argument_count_and_field_width() {
+        assert!(format("%d %d", &[Arg::Int(1)]).is_err());
+        assert!(format("%d", &[Arg::Int(1), Arg::Int(2)]).is_err());
+        assert!(format("%99999999999999999999999d", &[Arg::Int(1)]).is_err());
+        assert!(format("%.99999999999999999999999f", &[Arg::Float(1.0)]).is_err());
+    }
+
+    #[test]
+    fn quoted_strings_read_back() {
+        let text = "tab\there \"quoted\" back\\slash\n\0\x1b";
+        let quoted = quote(text);
+        assert_eq!(
+            parse_string_literal(&format!("  {}, rest", quoted)),
+            Some((text.to_string(), ", rest"))
+        );
+        assert_eq!(parse_string_literal("\"unterminated"), None);
+        assert_eq!(parse_string_literal("no quote"), None);
+    }
+}
@@ -0,0 +1,153 @@
+use crate::dwarf_data::DwarfData;
+use crate::inferior::{auxv_value, Target};
+use std::convert::TryInto;
+
+// Layout of the dynamic linker's rendezvous structures on x86_64 (see <link.h>):
+//   struct r_debug { int r_version; struct link_map *r_map; ElfW(Addr) r_brk; ... };
+//   struct link_map { ElfW(Addr) l_addr; char *l_name; ElfW(Dyn) *l_ld; link_map *l_next, *l_prev; };
+//   enum { RT_CONSISTENT, RT_ADD, RT_DELETE } r_state;  (after r_brk)
+const R_DEBUG_R_MAP: usize = 8;
+const R_DEBUG_R_STATE: usize = 24;
+const RT_CONSISTENT: u32 = 0;
+const LINK_MAP_L_ADDR: usize = 0;
+const LINK_MAP_L_NAME: usize = 8;
+const LINK_MAP_L_NEXT: usize = 24;
+
+/// Don't follow a corrupt `l_next` chain forever.
+const MAX_LIBRARIES: usize = 1024;
+
+/// A shared object loaded into the inferior, along with its symbols and debug info (relocated to
+/// where it was loaded).
+pub struct SharedLibrary {
+    pub debug_data: DwarfData,
+}
+
+/// What changed in the list of loaded libraries since the last `SharedLibraries::update`.
+#[derive(Debug, Default)]
+pub struct LibraryChanges {
+    /// Paths of the libraries that were loaded
+    pub loaded: Vec<String>,
+    /// Paths of the libraries that were unloaded
+    pub unloaded: Vec<String>,
+}
+
+/// Keeps track of the shared libraries the dynamic linker has loaded, by following the `r_debug`
+/// rendezvous structure that it maintains for debuggers.
+///
+/// The dynamic linker calls `_dl_debug_state` (an empty function) every time the list of loaded
+/// objects changes, so we put a breakpoint there and re-read the list whenever it is hit.
+#[derive(Default)]
+pub struct SharedLibraries {
+    libraries: Vec<SharedLibrary>,
+    /// Runtime address of the dynamic linker's `_r_debug`
+    r_debug: Option<usize>,
+    /// Runtime address of `_dl_debug_state`
+    event_address: Option<usize>,
+}
+
+impl SharedLibraries {
+    pub fn new() -> SharedLibraries {
+        Default::default()
+    }
+
+    /// Finds the dynamic linker in a freshly exec'd process (or a core file) and loads its
+    /// symbols. `executable` is the main program, which tells us which dynamic linker it uses;
+    /// the kernel tells us where it was loaded through AT_BASE. Does nothing for static
+    /// executables.
+    pub fn init(&mut self, target: &dyn Target, executable: &DwarfData) {
+        *self = SharedLibraries::new();
+        let interpreter = match executable.interpreter() {
+            Some(interpreter) => interpreter,
+            None => return,
+        };
+        let base = match target
+            .get_auxv()
+            .ok()
+            .and_then(|auxv| auxv_value(&auxv, libc::AT_BASE))
+        {
+            Some(base) if base != 0 => base as usize,
+            _ => return,
+        };
+        let mut debug_data = match DwarfData::from_file(interpreter) {
+            Ok(debug_data) => debug_data,
+            Err(e) => {
+                println!("Could not load dynamic linker {interpreter}: {e:?}");
+                return;
+            }
+        };
+        debug_data.set_load_bias(base);
+        self.r_debug = debug_data.get_symbol_address("_r_debug");
+        self.event_address = debug_data.get_symbol_address("_dl_debug_state");
+        self.libraries.push(SharedLibrary { debug_data });
+    }
+
+    /// Returns the address of `_dl_debug_state`, where a breakpoint tells us that libraries have
+    /// been loaded or unloaded.
+    pub fn event_address(&self) -> Option<usize> {
+        self.event_address
+    }
+
+    /// Re-reads the dynamic linker's list of loaded objects, loading the ones we haven't seen yet
+    /// and forgetting the ones that were unloaded. The dynamic linker also signals the start of
+    /// each `dlopen`/`dlclose`, when the list is in flux, so nothing changes until it says the
+    /// list is consistent again.
+    pub fn update(&mut self, target: &dyn Target) -> Result<LibraryChanges, nix::Error> {
+        let mut changes = LibraryChanges::default();
+        let r_debug = match self.r_debug {
+            Some(r_debug) => r_debug,
+            None => return Ok(changes),
+        };
+        let r_state = target.read_memory(r_debug + R_DEBUG_R_STATE, 4)?;
+        if u32::from_ne_bytes(r_state.as_slice().try_into().unwrap()) != RT_CONSISTENT {
+            return Ok(changes);
+        }
+        let mut loaded = Vec::new();
+        let mut link_map = target.read_word(r_debug + R_DEBUG_R_MAP)?;
+        while link_map != 0 && loaded.len() < MAX_LIBRARIES {
+            let l_addr = target.read_word(link_map + LINK_MAP_L_ADDR)?;
+            let l_name = target.read_word(link_map + LINK_MAP_L_NAME)?;
+            let name = if l_name == 0 {
+                String::new()
+            } else {
+                target.read_string(l_name)?
+            };
+            // The main program comes first, with an empty name. The vDSO has a name but no file.
+            if !name.is_empty() && std::path::Path::new(&name).exists() {
+                loaded.push((l_addr, name));
+            }
+            link_map = target.read_word(link_map + LINK_MAP_L_NEXT)?;
+        }
+
+        self.libraries.retain(|lib| {
+            let still_loaded = loaded.iter().any(|(l_addr, name)| {
+                lib.debug_data.load_bias() == *l_addr && lib.debug_data.path() == name
+            });
+            if !still_loaded {
+                changes.unloaded.push(lib.debug_data.path().to_string());
+            }
+            still_loaded
+        });
+        for (l_addr, name) in loaded {
+            if self
+                .libraries
+                .iter()
+                .any(|lib| lib.debug_data.path() == name)
+            {
+                continue;
+            }
+            match DwarfData::from_file(&name) {
+                Ok(mut debug_data) => {
+                    debug_data.set_load_bias(l_addr);
+                    self.libraries.push(SharedLibrary { debug_data });
+                    changes.loaded.push(name);
+                }
+                Err(e) => println!("Could not load symbols for {name}: {e:?}"),
+            }
+        }
+        Ok(changes)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SharedLibrary> {
+        self.libraries.iter()
+    }
+}
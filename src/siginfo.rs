@@ -0,0 +1,142 @@
+use nix::sys::signal::Signal;
+use std::convert::TryFrom;
+use std::convert::TryInto;
+use std::fmt;
+
+/// The parts of a `siginfo_t` that we care about when reporting why the inferior stopped.
+#[derive(Debug, Clone, Copy)]
+pub struct SigInfo {
+    pub signo: i32,
+    pub errno: i32,
+    pub code: i32,
+    /// Faulting address. Only meaningful for SIGSEGV, SIGBUS, SIGFPE and SIGILL.
+    pub addr: usize,
+}
+
+// si_code values. These are small integers that are reused between signals, so libc doesn't
+// export all of them consistently.
+const SI_USER: i32 = 0;
+const SI_KERNEL: i32 = 0x80;
+const SI_QUEUE: i32 = -1;
+const SI_TKILL: i32 = -6;
+
+impl SigInfo {
+    pub fn from_raw(info: &libc::siginfo_t) -> SigInfo {
+        SigInfo {
+            signo: info.si_signo,
+            errno: info.si_errno,
+            code: info.si_code,
+            addr: unsafe { info.si_addr() } as usize,
+        }
+    }
+
+    /// Parses a `siginfo_t` as laid out in memory on x86_64 (e.g. in a core file's NT_SIGINFO
+    /// note).
+    pub fn from_bytes(bytes: &[u8]) -> Option<SigInfo> {
+        let int_at = |offset: usize| -> Option<i32> {
+            Some(i32::from_ne_bytes(
+                bytes.get(offset..offset + 4)?.try_into().ok()?,
+            ))
+        };
+        Some(SigInfo {
+            signo: int_at(0)?,
+            errno: int_at(4)?,
+            code: int_at(8)?,
+            addr: u64::from_ne_bytes(bytes.get(16..24)?.try_into().ok()?) as usize,
+        })
+    }
+
+    /// Serializes this back into the 128-byte x86_64 `siginfo_t` layout.
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = vec![0u8; 128];
+        bytes[0..4].copy_from_slice(&self.signo.to_ne_bytes());
+        bytes[4..8].copy_from_slice(&self.errno.to_ne_bytes());
+        bytes[8..12].copy_from_slice(&self.code.to_ne_bytes());
+        bytes[16..24].copy_from_slice(&(self.addr as u64).to_ne_bytes());
+        bytes
+    }
+
+    pub fn signal(&self) -> Option<Signal> {
+        Signal::try_from(self.signo).ok()
+    }
+
+    /// Returns `true` if the signal was raised by the CPU because of a bad instruction, in which
+    /// case `addr` is the address that caused it.
+    pub fn is_fault(&self) -> bool {
+        matches!(
+            self.signal(),
+            Some(Signal::SIGSEGV)
+                | Some(Signal::SIGBUS)
+                | Some(Signal::SIGFPE)
+                | Some(Signal::SIGILL)
+        ) && self.code > 0
+            && self.code != SI_KERNEL
+    }
+
+    /// Returns the symbolic name of `si_code`, e.g. "SEGV_MAPERR".
+    pub fn code_name(&self) -> &'static str {
+        self.code_details().0
+    }
+
+    /// Returns a human readable description of `si_code`, e.g. "address not mapped to object".
+    pub fn code_description(&self) -> &'static str {
+        self.code_details().1
+    }
+
+    fn code_details(&self) -> (&'static str, &'static str) {
+        match (self.signal(), self.code) {
+            (_, SI_USER) => ("SI_USER", "sent by kill"),
+            (_, SI_KERNEL) => ("SI_KERNEL", "sent by the kernel"),
+            (_, SI_QUEUE) => ("SI_QUEUE", "sent by sigqueue"),
+            (_, SI_TKILL) => ("SI_TKILL", "sent by tkill"),
+            (Some(Signal::SIGSEGV), 1) => ("SEGV_MAPERR", "address not mapped to object"),
+            (Some(Signal::SIGSEGV), 2) => ("SEGV_ACCERR", "invalid permissions for mapped object"),
+            (Some(Signal::SIGSEGV), 3) => ("SEGV_BNDERR", "failed address bound checks"),
+            (Some(Signal::SIGSEGV), 4) => ("SEGV_PKUERR", "access denied by protection keys"),
+            (Some(Signal::SIGBUS), 1) => ("BUS_ADRALN", "invalid address alignment"),
+            (Some(Signal::SIGBUS), 2) => ("BUS_ADRERR", "nonexistent physical address"),
+            (Some(Signal::SIGBUS), 3) => ("BUS_OBJERR", "object-specific hardware error"),
+            (Some(Signal::SIGFPE), 1) => ("FPE_INTDIV", "integer divide by zero"),
+            (Some(Signal::SIGFPE), 2) => ("FPE_INTOVF", "integer overflow"),
+            (Some(Signal::SIGFPE), 3) => ("FPE_FLTDIV", "floating-point divide by zero"),
+            (Some(Signal::SIGFPE), 4) => ("FPE_FLTOVF", "floating-point overflow"),
+            (Some(Signal::SIGFPE), 5) => ("FPE_FLTUND", "floating-point underflow"),
+            (Some(Signal::SIGFPE), 6) => ("FPE_FLTRES", "floating-point inexact result"),
+            (Some(Signal::SIGFPE), 7) => ("FPE_FLTINV", "floating-point invalid operation"),
+            (Some(Signal::SIGFPE), 8) => ("FPE_FLTSUB", "subscript out of range"),
+            (Some(Signal::SIGILL), 1) => ("ILL_ILLOPC", "illegal opcode"),
+            (Some(Signal::SIGILL), 2) => ("ILL_ILLOPN", "illegal operand"),
+            (Some(Signal::SIGILL), 3) => ("ILL_ILLADR", "illegal addressing mode"),
+            (Some(Signal::SIGILL), 4) => ("ILL_ILLTRP", "illegal trap"),
+            (Some(Signal::SIGILL), 5) => ("ILL_PRVOPC", "privileged opcode"),
+            (Some(Signal::SIGILL), 6) => ("ILL_PRVREG", "privileged register"),
+            (Some(Signal::SIGILL), 7) => ("ILL_COPROC", "coprocessor error"),
+            (Some(Signal::SIGILL), 8) => ("ILL_BADSTK", "internal stack error"),
+            _ => ("<unknown>", "unknown reason"),
+        }
+    }
+
+    /// Looks up a field the way it would be written after `$_siginfo` in an expression (e.g.
+    /// `si_code` or `_sigfault.si_addr`). An empty path returns the whole struct.
+    pub fn field(&self, path: &str) -> Option<String> {
+        match path {
+            "" => Some(self.to_string()),
+            "si_signo" => Some(self.signo.to_string()),
+            "si_errno" => Some(self.errno.to_string()),
+            "si_code" => Some(self.code.to_string()),
+            "_sigfault" => Some(format!("{{si_addr = {:#x}}}", self.addr)),
+            "si_addr" | "_sigfault.si_addr" => Some(format!("{:#x}", self.addr)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SigInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{{si_signo = {}, si_errno = {}, si_code = {}, _sigfault = {{si_addr = {:#x}}}}}",
+            self.signo, self.errno, self.code, self.addr
+        )
+    }
+}
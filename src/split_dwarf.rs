@@ -0,0 +1,258 @@
+use crate::debug_section;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Sections that can appear in a `.dwo` file (and, sliced up per unit, in a `.dwp` package)
+const DWO_SECTIONS: &[&str] = &[
+    ".debug_info.dwo",
+    ".debug_abbrev.dwo",
+    ".debug_line.dwo",
+    ".debug_loc.dwo",
+    ".debug_loclists.dwo",
+    ".debug_rnglists.dwo",
+    ".debug_str.dwo",
+    ".debug_str_offsets.dwo",
+];
+
+/// The debug sections holding one split unit, keyed by section name (e.g. ".debug_info.dwo").
+pub type SplitSections<'a> = HashMap<&'static str, &'a [u8]>;
+
+/// Index of the compilation units in a `.dwp` package
+const CU_INDEX_SECTION: &str = ".debug_cu_index";
+
+/// Where one unit's part of a section is in a `.dwp` package: the section, offset and size
+type Contribution = (&'static str, usize, usize);
+
+/// Copies the split DWARF sections (and the unit index, if any) out of a `.dwo` or `.dwp` file.
+fn read_dwo_sections(path: &Path) -> Option<HashMap<&'static str, Vec<u8>>> {
+    let file = fs::File::open(path).ok()?;
+    let mmap = unsafe { memmap::Mmap::map(&file).ok()? };
+    Some(
+        DWO_SECTIONS
+            .iter()
+            .chain(std::iter::once(&CU_INDEX_SECTION))
+            .filter_map(|name| {
+                Some((
+                    *name,
+                    debug_section::section_data(&mmap, name)?.into_owned(),
+                ))
+            })
+            .collect(),
+    )
+}
+
+/// A `.dwo` file: the debug info of one compilation unit built with `-gsplit-dwarf`, left next to
+/// the object file it was compiled to.
+pub struct DwoFile {
+    sections: HashMap<&'static str, Vec<u8>>,
+}
+
+impl DwoFile {
+    /// Returns where `dwo_name` is, which is relative to `comp_dir` unless it's absolute.
+    pub fn path(comp_dir: Option<&str>, dwo_name: &str) -> PathBuf {
+        match comp_dir {
+            Some(comp_dir) => Path::new(comp_dir).join(dwo_name),
+            None => Path::new(dwo_name).to_path_buf(),
+        }
+    }
+
+    /// Opens `dwo_name` (see `path`).
+    pub fn open(comp_dir: Option<&str>, dwo_name: &str) -> Option<DwoFile> {
+        Some(DwoFile {
+            sections: read_dwo_sections(&DwoFile::path(comp_dir, dwo_name))?,
+        })
+    }
+
+    pub fn sections(&self) -> SplitSections<'_> {
+        self.sections
+            .iter()
+            .map(|(name, data)| (*name, data.as_slice()))
+            .collect()
+    }
+}
+
+/// A DWARF package (`.dwp`), which `dwp` builds by merging the `.dwo` files of a program. The
+/// units' contributions to each section are concatenated, and `.debug_cu_index` records where
+/// each unit's pieces are, by DWO ID.
+pub struct Package {
+    sections: HashMap<&'static str, Vec<u8>>,
+    /// For each unit, its DWO ID and its contributions to the sections
+    units: Vec<(u64, Vec<Contribution>)>,
+}
+
+impl Package {
+    /// Returns where the package for the binary at `path` would be: next to it, as `path.dwp`.
+    pub fn path(path: &str) -> PathBuf {
+        PathBuf::from(format!("{}.dwp", path))
+    }
+
+    /// Opens the package for the binary at `path`.
+    pub fn open(path: &str) -> Option<Package> {
+        let mut sections = read_dwo_sections(&Package::path(path))?;
+        let units = parse_cu_index(&sections.remove(CU_INDEX_SECTION)?)?;
+        Some(Package { sections, units })
+    }
+
+    /// Returns the sections of the unit with the given DWO ID.
+    pub fn sections(&self, dwo_id: u64) -> Option<SplitSections<'_>> {
+        let (_, contributions) = self.units.iter().find(|(id, _)| *id == dwo_id)?;
+        let mut sections: SplitSections = self
+            .sections
+            .iter()
+            .map(|(name, data)| (*name, data.as_slice()))
+            .collect();
+        for (name, offset, size) in contributions {
+            let data = self.sections.get(name)?;
+            sections.insert(name, data.get(*offset..*offset + *size)?);
+        }
+        Some(sections)
+    }
+}
+
+/// Parses a unit index (DWARF 5 section 7.3.5, or the GNU version 2 extension it was based on):
+/// a header, a hash table from DWO ID to row, then a table of section offsets and one of sizes,
+/// with one row per unit and one column per section.
+fn parse_cu_index(index: &[u8]) -> Option<Vec<(u64, Vec<Contribution>)>> {
+    let u32_at = |offset: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(
+            index.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+    let u64_at = |offset: usize| -> Option<u64> {
+        Some(u64::from_le_bytes(
+            index.get(offset..offset + 8)?.try_into().ok()?,
+        ))
+    };
+    // Version 5 is a 2-byte version followed by 2 bytes of padding; version 2 is 4 bytes
+    let version = u32_at(0)? & 0xffff;
+    let columns = u32_at(4)? as usize;
+    let slots = u32_at(12)? as usize;
+    let hashes = 16;
+    let rows = hashes + 8 * slots;
+    let section_ids = rows + 4 * slots;
+    let offsets = section_ids + 4 * columns;
+    let unit_count = u32_at(8)? as usize;
+    let sizes = offsets + 4 * columns * unit_count;
+
+    let mut units = Vec::new();
+    for slot in 0..slots {
+        let row = u32_at(rows + 4 * slot)? as usize;
+        if row == 0 {
+            continue;
+        }
+        let dwo_id = u64_at(hashes + 8 * slot)?;
+        let mut contributions = Vec::new();
+        for column in 0..columns {
+            let name = match section_name(version, u32_at(section_ids + 4 * column)?) {
+                Some(name) => name,
+                None => continue,
+            };
+            let cell = 4 * ((row - 1) * columns + column);
+            contributions.push((
+                name,
+                u32_at(offsets + cell)? as usize,
+                u32_at(sizes + cell)? as usize,
+            ));
+        }
+        units.push((dwo_id, contributions));
+    }
+    Some(units)
+}
+
+/// Maps a section identifier in a unit index to the section's name.
+fn section_name(version: u32, id: u32) -> Option<&'static str> {
+    match (version, id) {
+        (_, 1) => Some(".debug_info.dwo"),
+        (_, 3) => Some(".debug_abbrev.dwo"),
+        (_, 4) => Some(".debug_line.dwo"),
+        (2, 5) => Some(".debug_loc.dwo"),
+        (5, 5) => Some(".debug_loclists.dwo"),
+        (_, 6) => Some(".debug_str_offsets.dwo"),
+        (5, 8) => Some(".debug_rnglists.dwo"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A unit in a test index: its hash slot, DWO ID and (offset, size) in each column
+    type IndexedUnit = (u32, u64, Vec<(u32, u32)>);
+
+    /// Builds a version 5 `.debug_cu_index` with one column per section id in `columns`.
+    fn cu_index(columns: &[u32], slots: u32, units: &[IndexedUnit]) -> Vec<u8> {
+        let mut index = Vec::new();
+        index.extend_from_slice(&5u32.to_le_bytes());
+        index.extend_from_slice(&(columns.len() as u32).to_le_bytes());
+        index.extend_from_slice(&(units.len() as u32).to_le_bytes());
+        index.extend_from_slice(&slots.to_le_bytes());
+        let mut hashes = vec![0u64; slots as usize];
+        let mut rows = vec![0u32; slots as usize];
+        for (row, (slot, dwo_id, _)) in units.iter().enumerate() {
+            hashes[*slot as usize] = *dwo_id;
+            rows[*slot as usize] = row as u32 + 1;
+        }
+        hashes
+            .iter()
+            .for_each(|hash| index.extend_from_slice(&hash.to_le_bytes()));
+        rows.iter()
+            .for_each(|row| index.extend_from_slice(&row.to_le_bytes()));
+        columns
+            .iter()
+            .for_each(|id| index.extend_from_slice(&id.to_le_bytes()));
+        for (_, _, cells) in units {
+            cells
+                .iter()
+                .for_each(|(offset, _)| index.extend_from_slice(&offset.to_le_bytes()));
+        }
+        for (_, _, cells) in units {
+            cells
+                .iter()
+                .for_each(|(_, size)| index.extend_from_slice(&size.to_le_bytes()));
+        }
+        index
+    }
+
+    #[test]
+    fn finds_unit_contributions_by_dwo_id() {
+        // Two units: info and abbrev columns, plus a column with an unknown section id
+        let index = cu_index(
+            &[1, 3, 99],
+            4,
+            &[
+                (3, 0xaaaa, vec![(0, 10), (0, 4), (0, 1)]),
+                (1, 0xbbbb, vec![(10, 6), (4, 2), (1, 1)]),
+            ],
+        );
+        let units = parse_cu_index(&index).unwrap();
+        assert_eq!(units.len(), 2);
+
+        let mut sections = HashMap::new();
+        sections.insert(".debug_info.dwo", (0..16).collect::<Vec<u8>>());
+        sections.insert(
+            ".debug_abbrev.dwo",
+            vec![0xa0, 0xa1, 0xa2, 0xa3, 0xb0, 0xb1],
+        );
+        let package = Package { sections, units };
+
+        let first = package.sections(0xaaaa).unwrap();
+        assert_eq!(first[".debug_info.dwo"], &(0..10).collect::<Vec<u8>>()[..]);
+        assert_eq!(first[".debug_abbrev.dwo"], &[0xa0, 0xa1, 0xa2, 0xa3][..]);
+        let second = package.sections(0xbbbb).unwrap();
+        assert_eq!(
+            second[".debug_info.dwo"],
+            &(10..16).collect::<Vec<u8>>()[..]
+        );
+        assert_eq!(second[".debug_abbrev.dwo"], &[0xb0, 0xb1][..]);
+        assert!(package.sections(0xcccc).is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_index() {
+        let index = cu_index(&[1], 2, &[(0, 1, vec![(0, 1)])]);
+        assert!(parse_cu_index(&index[..index.len() - 1]).is_none());
+    }
+}
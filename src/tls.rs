@@ -0,0 +1,90 @@
+//! Finds a thread's copy of a thread-local variable, which DWARF describes as an offset into the
+//! TLS block of the executable or library defining it (`DW_OP_form_tls_address`). Where each
+//! thread's blocks are is up to the C library; this follows glibc's layout on x86_64, where
+//! `fs_base` points to the thread control block:
+//!   struct tcbhead_t { void *tcb; dtv_t *dtv; void *self; ... };
+//!   union dtv_t { size_t counter; struct { void *val; void *to_free; } pointer; };
+//! The executable's block comes right before the thread control block ("static TLS"), so its
+//! variables are at a fixed offset from `fs_base`. Libraries' blocks can be anywhere, so we look
+//! them up in the thread's dynamic thread vector (DTV), which has one entry per module with TLS.
+
+use crate::core_file::{read_u16, read_u32, read_u64};
+use crate::dwarf_data::DwarfData;
+use crate::inferior::Target;
+
+const PT_TLS: u32 = 7;
+const TCB_DTV: usize = 8;
+const DTV_ENTRY_SIZE: usize = 16;
+/// What a DTV entry holds until the thread first uses the module's variables
+const TLS_DTV_UNALLOCATED: usize = usize::MAX;
+
+/// The PT_TLS segment of an executable or library: the template each thread's TLS block is
+/// initialized from.
+#[derive(Debug, Clone, Copy)]
+pub struct TlsSegment {
+    /// Link-time address, which only matters for how it's aligned
+    pub address: usize,
+    pub size: usize,
+    pub align: usize,
+}
+
+/// Returns the PT_TLS segment of the (64-bit, little-endian) ELF file `data`, if it has one.
+pub fn tls_segment(data: &[u8]) -> Option<TlsSegment> {
+    let phoff = read_u64(data, 32)? as usize;
+    let phentsize = read_u16(data, 54)? as usize;
+    let phnum = read_u16(data, 56)? as usize;
+    (0..phnum).find_map(|i| {
+        let ph = phoff.checked_add(i * phentsize)?;
+        if read_u32(data, ph)? != PT_TLS {
+            return None;
+        }
+        Some(TlsSegment {
+            address: read_u64(data, ph.checked_add(16)?)? as usize,
+            size: read_u64(data, ph.checked_add(40)?)? as usize,
+            align: (read_u64(data, ph.checked_add(48)?)? as usize).max(1),
+        })
+    })
+}
+
+/// Returns the address of the variable at `offset` in `module`'s TLS block, in the thread
+/// `target` is looking at. `modules` are the executable followed by the libraries in the order
+/// they were loaded, which is the order glibc numbers modules with TLS in.
+pub fn tls_address(
+    target: &dyn Target,
+    modules: &[&DwarfData],
+    module: &DwarfData,
+    offset: usize,
+) -> Option<usize> {
+    let segment = module.tls_segment()?;
+    let fs_base = target.get_registers().ok()?.fs_base as usize;
+    // Before the C library has set up the thread, there's no TLS to find
+    if fs_base == 0 {
+        return None;
+    }
+    let executable = modules.first()?;
+    if std::ptr::eq(*executable, module) {
+        // glibc places the block so that its first byte has the same alignment as in the file.
+        // An alignment of 0 means no alignment, like 1.
+        let align = segment.align.max(1);
+        let first_byte = segment.address.wrapping_neg() & (align - 1);
+        let block_offset =
+            round_up(segment.size.saturating_sub(first_byte), align)?.checked_add(first_byte)?;
+        return fs_base.checked_sub(block_offset)?.checked_add(offset);
+    }
+    let module_id = 1 + modules
+        .iter()
+        .take_while(|other| !std::ptr::eq(**other, module))
+        .filter(|other| other.tls_segment().is_some())
+        .count();
+    let dtv = target.read_word(fs_base.checked_add(TCB_DTV)?).ok()?;
+    let entry = dtv.checked_add(module_id.checked_mul(DTV_ENTRY_SIZE)?)?;
+    match target.read_word(entry).ok()? {
+        0 | TLS_DTV_UNALLOCATED => None,
+        block => block.checked_add(offset),
+    }
+}
+
+/// Rounds `value` up to a multiple of `align`, which ELF requires to be a power of two.
+fn round_up(value: usize, align: usize) -> Option<usize> {
+    Some(value.checked_add(align - 1)? & !(align - 1))
+}
@@ -0,0 +1,460 @@
+//! Formatting of raw bytes read from the inferior according to their DWARF type.
+
+use crate::dwarf_data::{Member, Struct, Type, TypeKind, Types, VariantPart};
+use crate::inferior::Target;
+use crate::printf::Arg;
+use std::convert::TryInto;
+
+/// How many elements of an array, vector or slice we show, like gdb's `print elements`
+const MAX_ELEMENTS: usize = 200;
+/// How far into values nested in other values we go
+const MAX_DEPTH: usize = 8;
+
+/// Formats a value the way its language would show it: C values the way gdb prints them, and
+/// Rust values the way `{:?}` does, knowing what `String`s, `Vec`s and slices look like inside.
+/// Values a pointer, `String` or `Vec` leads to are read from `target`.
+pub fn format_value(
+    bytes: &[u8],
+    entity_type: &Type,
+    types: &Types,
+    target: &dyn Target,
+) -> String {
+    Formatter { types, target }.format(bytes, entity_type, 0)
+}
+
+/// Converts a value to what `printf` would get if the program passed it: a number, or a string
+/// for `char *`s and `char` arrays. Returns `None` for values `printf` can't take, like structs.
+pub fn printf_arg(
+    bytes: &[u8],
+    entity_type: &Type,
+    types: &Types,
+    target: &dyn Target,
+) -> Option<Arg> {
+    let entity_type = types.resolve(entity_type)?;
+    let is_char = |offset: usize| {
+        matches!(
+            types.get(offset),
+            Some(Type { kind: TypeKind::Base(encoding), size: 1, .. })
+                if *encoding == gimli::DW_ATE_signed_char.0
+                    || *encoding == gimli::DW_ATE_unsigned_char.0
+        )
+    };
+    Some(match &entity_type.kind {
+        TypeKind::Base(encoding) => match gimli::DwAte(*encoding) {
+            gimli::DW_ATE_float => match bytes.len() {
+                4 => Arg::Float(f32::from_le_bytes(bytes.try_into().ok()?).into()),
+                8 => Arg::Float(f64::from_le_bytes(bytes.try_into().ok()?)),
+                _ => return None,
+            },
+            gimli::DW_ATE_signed | gimli::DW_ATE_signed_char => Arg::Int(to_i64(bytes)),
+            _ => Arg::Int(to_u64(bytes) as i64),
+        },
+        TypeKind::Enum(_) => Arg::Int(to_i64(bytes)),
+        TypeKind::Pointer(Some(pointee)) if is_char(*pointee) => {
+            let address = to_u64(bytes);
+            let string = Some(address)
+                .filter(|address| *address != 0)
+                .and_then(|address| target.read_string(address as usize).ok());
+            Arg::CharPointer(address, string)
+        }
+        TypeKind::Pointer(_) => Arg::Int(to_u64(bytes) as i64),
+        TypeKind::Array { element, counts } if counts.len() == 1 && is_char(*element) => {
+            let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+            Arg::Str(String::from_utf8_lossy(&bytes[..end]).into_owned())
+        }
+        _ => return None,
+    })
+}
+
+struct Formatter<'a> {
+    types: &'a Types,
+    target: &'a dyn Target,
+}
+
+impl Formatter<'_> {
+    fn format(&self, bytes: &[u8], entity_type: &Type, depth: usize) -> String {
+        let entity_type = match self.types.resolve(entity_type) {
+            Some(entity_type) if depth <= MAX_DEPTH => entity_type,
+            _ => return "...".to_string(),
+        };
+        match &entity_type.kind {
+            TypeKind::Base(encoding) => format_base(bytes, *encoding, self.types.rust),
+            TypeKind::Pointer(target) => self.format_pointer(bytes, entity_type, *target),
+            TypeKind::Struct(fields) => self.format_struct(bytes, entity_type, fields, depth),
+            TypeKind::Array { element, counts } => match self.types.get(*element) {
+                Some(element) => self.format_array(bytes, element, counts, depth),
+                None => hex_bytes(bytes),
+            },
+            TypeKind::Enum(enumerators) => {
+                let value = to_i64(bytes);
+                match enumerators
+                    .iter()
+                    .find(|(_, enumerator)| *enumerator == value)
+                {
+                    Some((name, _)) => name.clone(),
+                    None => value.to_string(),
+                }
+            }
+            TypeKind::Alias(_) | TypeKind::Unknown => hex_bytes(bytes),
+        }
+    }
+
+    /// Formats an array with the dimensions in `counts`, as an array of arrays if there's more
+    /// than one.
+    fn format_array(&self, bytes: &[u8], element: &Type, counts: &[usize], depth: usize) -> String {
+        let (count, inner) = match counts.split_first() {
+            Some((count, inner)) => (*count, inner),
+            None => return String::new(),
+        };
+        let size = inner
+            .iter()
+            .fold(element.size, |size, count| size.saturating_mul(*count));
+        let elements = (0..count.min(MAX_ELEMENTS))
+            .map(|i| {
+                let bytes = field(bytes, i.saturating_mul(size), size);
+                if inner.is_empty() {
+                    self.format(bytes, element, depth + 1)
+                } else {
+                    self.format_array(bytes, element, inner, depth + 1)
+                }
+            })
+            .collect();
+        self.list(elements, count > MAX_ELEMENTS)
+    }
+
+    fn format_pointer(&self, bytes: &[u8], entity_type: &Type, target: Option<usize>) -> String {
+        let address = to_u64(bytes);
+        if self.types.rust {
+            return format!("{:#x}", address);
+        }
+        let mut text = format!("({}) {:#x}", entity_type.name, address);
+        // Like gdb, show the string a `char *` points to
+        let points_to_char = matches!(
+            target.and_then(|target| self.types.get(target)),
+            Some(Type { kind: TypeKind::Base(encoding), size: 1, .. })
+                if *encoding == gimli::DW_ATE_signed_char.0
+                    || *encoding == gimli::DW_ATE_unsigned_char.0
+        );
+        if points_to_char && address != 0 {
+            match self.target.read_string(address as usize) {
+                Ok(string) => text.push_str(&format!(" {:?}", string)),
+                Err(_) => text.push_str(" <error: Cannot access memory>"),
+            }
+        }
+        text
+    }
+
+    fn format_struct(
+        &self,
+        bytes: &[u8],
+        entity_type: &Type,
+        fields: &Struct,
+        depth: usize,
+    ) -> String {
+        if !self.types.rust {
+            let members: Vec<String> = fields
+                .members
+                .iter()
+                .map(|member| {
+                    let value = self.format_member(bytes, member, depth);
+                    if !member.name.is_empty() {
+                        return format!("{} = {}", member.name, value);
+                    }
+                    // Unnamed members are C++ base classes, shown as `<Base> = {...}`, or
+                    // anonymous structs and unions
+                    match self.types.get(member.type_offset) {
+                        Some(base) if !base.name.is_empty() => {
+                            format!("<{}> = {}", base.name, value)
+                        }
+                        _ => value,
+                    }
+                })
+                .collect();
+            return format!("{{{}}}", members.join(", "));
+        }
+        let name = entity_type.name.as_str();
+        if name == "alloc::string::String" {
+            if let Some(text) = self.format_string(bytes, fields) {
+                return text;
+            }
+        } else if name == "&str" || name == "&mut str" {
+            let pointer = self.named_member(bytes, fields, "data_ptr");
+            let length = self.named_member(bytes, fields, "length");
+            if let (Some(pointer), Some(length)) = (pointer, length) {
+                return match self.read_elements(pointer, length as usize, 1) {
+                    Ok(bytes) => format!("{:?}", String::from_utf8_lossy(&bytes)),
+                    Err(text) => text,
+                };
+            }
+        } else if name.starts_with("&[") || name.starts_with("&mut [") {
+            let pointer = self.named_member(bytes, fields, "data_ptr");
+            let length = self.named_member(bytes, fields, "length");
+            let element = fields
+                .members
+                .iter()
+                .find(|member| member.name == "data_ptr")
+                .and_then(|member| match self.types.get(member.type_offset)?.kind {
+                    TypeKind::Pointer(Some(element)) => self.types.get(element),
+                    _ => None,
+                });
+            if let (Some(pointer), Some(length), Some(element)) = (pointer, length, element) {
+                return self.format_elements(pointer, length as usize, element, depth);
+            }
+        } else if name.starts_with("alloc::vec::Vec<") {
+            if let Some((pointer, length, element)) = self.vec_parts(bytes, fields) {
+                return self.format_elements(pointer, length, element, depth);
+            }
+        }
+        match &fields.variants {
+            Some(variants) => self.format_variant(bytes, variants, depth),
+            // Tuples are named after their types, e.g. `(i32, f64)`, and shown without a name
+            None if name.starts_with('(') => self.format_fields("", bytes, &fields.members, depth),
+            None => self.format_fields(short_name(name), bytes, &fields.members, depth),
+        }
+    }
+
+    /// Formats a Rust struct, tuple, tuple struct or enum variant.
+    fn format_fields(&self, name: &str, bytes: &[u8], members: &[Member], depth: usize) -> String {
+        if members.is_empty() {
+            return name.to_string();
+        }
+        let values = members
+            .iter()
+            .map(|member| self.format_member(bytes, member, depth));
+        // Tuple fields are called __0, __1 and so on
+        let tuple = members.iter().all(|member| {
+            member.name.len() > 2
+                && member.name.starts_with("__")
+                && member.name[2..].chars().all(|c| c.is_ascii_digit())
+        });
+        if tuple {
+            let values: Vec<String> = values.collect();
+            format!("{}({})", name, values.join(", "))
+        } else {
+            let fields: Vec<String> = members
+                .iter()
+                .zip(values)
+                .map(|(member, value)| format!("{}: {}", member.name, value))
+                .collect();
+            format!("{} {{ {} }}", name, fields.join(", "))
+        }
+    }
+
+    /// Formats a Rust enum, which holds the variant its discriminant says, or the variant
+    /// without a discriminant value if none matches (its fields then hold the discriminant too).
+    fn format_variant(&self, bytes: &[u8], variant_part: &VariantPart, depth: usize) -> String {
+        let discriminant = variant_part.discriminant.as_ref().and_then(|member| {
+            let size = self.types.get(member.type_offset)?.size;
+            Some(to_u64(field(bytes, member.offset, size)))
+        });
+        let variant = variant_part
+            .variants
+            .iter()
+            .find(|(value, _)| value.is_some() && *value == discriminant)
+            .or_else(|| {
+                variant_part
+                    .variants
+                    .iter()
+                    .find(|(value, _)| value.is_none())
+            });
+        let member = match variant {
+            Some((_, member)) => member,
+            None => return format!("<invalid discriminant {:?}>", discriminant),
+        };
+        match self.types.get(member.type_offset) {
+            Some(Type {
+                kind: TypeKind::Struct(fields),
+                size,
+                ..
+            }) => {
+                let bytes = field(bytes, member.offset, *size);
+                self.format_fields(&member.name, bytes, &fields.members, depth + 1)
+            }
+            _ => member.name.clone(),
+        }
+    }
+
+    fn format_member(&self, bytes: &[u8], member: &Member, depth: usize) -> String {
+        match self.types.types.get(&member.type_offset) {
+            Some(member_type) => {
+                let size = self.types.resolve(member_type).map_or(0, |t| t.size);
+                self.format(field(bytes, member.offset, size), member_type, depth + 1)
+            }
+            None => "?".to_string(),
+        }
+    }
+
+    /// Returns the value of the integer or pointer member called `name`.
+    fn named_member(&self, bytes: &[u8], fields: &Struct, name: &str) -> Option<u64> {
+        let member = fields.members.iter().find(|member| member.name == name)?;
+        let size = self.types.get(member.type_offset)?.size;
+        Some(to_u64(field(bytes, member.offset, size)))
+    }
+
+    /// Returns where a `Vec`'s elements are, how many there are and their type. The pointer to
+    /// them is buried in the `Vec`'s `RawVec`, in a form that changes between Rust versions, so
+    /// we take the first pointer we find.
+    fn vec_parts(&self, bytes: &[u8], fields: &Struct) -> Option<(u64, usize, &Type)> {
+        let length = self.named_member(bytes, fields, "len")? as usize;
+        let (_, element) = fields
+            .template_params
+            .iter()
+            .find(|(name, _)| name == "T")?;
+        let element = self.types.get(*element)?;
+        Some((self.first_pointer(bytes, fields, 0)?, length, element))
+    }
+
+    fn first_pointer(&self, bytes: &[u8], fields: &Struct, depth: usize) -> Option<u64> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        fields.members.iter().find_map(|member| {
+            let member_type = self.types.get(member.type_offset)?;
+            let bytes = field(bytes, member.offset, member_type.size);
+            match &member_type.kind {
+                TypeKind::Pointer(_) => Some(to_u64(bytes)),
+                TypeKind::Struct(fields) => self.first_pointer(bytes, fields, depth + 1),
+                _ => None,
+            }
+        })
+    }
+
+    fn format_string(&self, bytes: &[u8], fields: &Struct) -> Option<String> {
+        let vec = fields.members.iter().find(|member| member.name == "vec")?;
+        let vec_type = self.types.get(vec.type_offset)?;
+        let vec_fields = match &vec_type.kind {
+            TypeKind::Struct(vec_fields) => vec_fields,
+            _ => return None,
+        };
+        let (pointer, length, _) =
+            self.vec_parts(field(bytes, vec.offset, vec_type.size), vec_fields)?;
+        Some(match self.read_elements(pointer, length, 1) {
+            Ok(bytes) => format!("{:?}", String::from_utf8_lossy(&bytes)),
+            Err(text) => text,
+        })
+    }
+
+    fn format_elements(&self, pointer: u64, length: usize, element: &Type, depth: usize) -> String {
+        let shown = length.min(MAX_ELEMENTS);
+        match self.read_elements(pointer, shown, element.size) {
+            Ok(bytes) => {
+                // Big elements can mean fewer were read than asked for
+                let shown = shown.min(bytes.len().checked_div(element.size).unwrap_or(shown));
+                let elements = (0..shown)
+                    .map(|i| {
+                        let bytes = field(&bytes, i * element.size, element.size);
+                        self.format(bytes, element, depth + 1)
+                    })
+                    .collect();
+                self.list(elements, length > shown)
+            }
+            Err(text) => text,
+        }
+    }
+
+    /// Reads `count` elements of `size` bytes at `pointer`, or returns what to show instead.
+    fn read_elements(&self, pointer: u64, count: usize, size: usize) -> Result<Vec<u8>, String> {
+        let count = count.min(MAX_ELEMENTS * 64 / size.max(1));
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        self.target
+            .read_memory(pointer as usize, count * size)
+            .map_err(|_| format!("<error: Cannot access memory at address {:#x}>", pointer))
+    }
+
+    fn list(&self, mut elements: Vec<String>, truncated: bool) -> String {
+        if truncated {
+            elements.push("...".to_string());
+        }
+        if self.types.rust {
+            format!("[{}]", elements.join(", "))
+        } else {
+            format!("{{{}}}", elements.join(", "))
+        }
+    }
+}
+
+/// Formats a value of a base type with the given `DW_ATE_*` encoding.
+fn format_base(bytes: &[u8], encoding: u8, rust: bool) -> String {
+    match gimli::DwAte(encoding) {
+        gimli::DW_ATE_boolean => (to_u64(bytes) != 0).to_string(),
+        gimli::DW_ATE_float => match bytes.len() {
+            4 if rust => format!("{:?}", f32::from_le_bytes(bytes.try_into().unwrap())),
+            8 if rust => format!("{:?}", f64::from_le_bytes(bytes.try_into().unwrap())),
+            4 => f32::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            8 => f64::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            _ => hex_bytes(bytes),
+        },
+        gimli::DW_ATE_signed_char | gimli::DW_ATE_unsigned_char if bytes.len() == 1 => {
+            let value = if encoding == gimli::DW_ATE_signed_char.0 {
+                bytes[0] as i8 as i64
+            } else {
+                bytes[0] as i64
+            };
+            format!("{} {:?}", value, bytes[0] as char)
+        }
+        gimli::DW_ATE_UTF => match std::char::from_u32(to_u64(bytes) as u32) {
+            Some(c) => format!("{:?}", c),
+            None => to_u64(bytes).to_string(),
+        },
+        gimli::DW_ATE_signed | gimli::DW_ATE_signed_char if bytes.len() <= 16 => {
+            to_i128(bytes).to_string()
+        }
+        gimli::DW_ATE_unsigned | gimli::DW_ATE_unsigned_char if bytes.len() <= 16 => {
+            to_u128(bytes).to_string()
+        }
+        _ => hex_bytes(bytes),
+    }
+}
+
+/// Returns the name of a Rust type without its path or generic arguments, e.g. `Vec` for
+/// `alloc::vec::Vec<i32, alloc::alloc::Global>`, as `{:?}` would show it.
+fn short_name(name: &str) -> &str {
+    let name = match name.find('<') {
+        Some(generics) => &name[..generics],
+        None => name,
+    };
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// Returns the `size` bytes at `offset` in `bytes`, or as many of them as there are.
+fn field(bytes: &[u8], offset: usize, size: usize) -> &[u8] {
+    let start = offset.min(bytes.len());
+    &bytes[start..offset.saturating_add(size).min(bytes.len())]
+}
+
+fn to_u128(bytes: &[u8]) -> u128 {
+    let mut raw = [0u8; 16];
+    let len = bytes.len().min(16);
+    raw[..len].copy_from_slice(&bytes[..len]);
+    u128::from_le_bytes(raw)
+}
+
+/// Reads a little-endian integer, sign-extending it from its width.
+fn to_i128(bytes: &[u8]) -> i128 {
+    if bytes.is_empty() {
+        return 0;
+    }
+    let shift = 128 - 8 * bytes.len().min(16) as u32;
+    ((to_u128(bytes) << shift) as i128) >> shift
+}
+
+fn to_u64(bytes: &[u8]) -> u64 {
+    to_u128(bytes) as u64
+}
+
+fn to_i64(bytes: &[u8]) -> i64 {
+    to_i128(bytes) as i64
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    format!(
+        "{{{}}}",
+        bytes
+            .iter()
+            .map(|b| format!("{:#04x}", b))
+            .collect::<Vec<String>>()
+            .join(", ")
+    )
+}